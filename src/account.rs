@@ -0,0 +1,377 @@
+use std::str::FromStr;
+
+use bip39::Mnemonic;
+use bitcoin::absolute::LockTime;
+use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint, KeySource, Xpriv, Xpub};
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Address, Amount, Network, OutPoint, Psbt, PublicKey, ScriptBuf, Sequence, Transaction, TxIn,
+    TxOut, Witness,
+};
+use ord_rs::transaction::TxInput;
+use zeroize::Zeroizing;
+
+use crate::descriptor::{Descriptor, DescriptorKind};
+use crate::error::{FeeError, PsbtError, Result};
+use crate::key_provider::ADDRESS_GAP;
+use crate::psbt::reveal_psbt;
+use crate::rpc_client;
+use crate::secret::SecretXpriv;
+use crate::taproot::TaprootPayload;
+
+/// Account able to sign, holding the master key of the mnemonic
+#[derive(Debug)]
+pub struct Account {
+    pub address: Address,
+    pub public_key: PublicKey,
+    pub private_key: SecretXpriv,
+    pub input_xpub: Xpub,
+    pub path: DerivationPath,
+}
+
+impl Account {
+    pub fn from_mnemonic(secp: &Secp256k1<All>, mnemonic: &str) -> Result<Self> {
+        let mnemonic = Zeroizing::new(Mnemonic::from_str(mnemonic)?);
+        let seed = Zeroizing::new(mnemonic.to_seed(""));
+        let root = SecretXpriv::new(Xpriv::new_master(Network::Testnet, seed.as_ref())?);
+
+        Self::from_master_key(secp, root)
+    }
+
+    /// Build the account of the master key `root`, on the network of the key
+    pub fn from_master_key(secp: &Secp256k1<All>, root: SecretXpriv) -> Result<Self> {
+        let network = root.expose_secret().network;
+
+        // derive child xpub
+        let path = DerivationPath::from_str("m/84h/0h/0h")?;
+        let child = SecretXpriv::new(root.expose_secret().derive_priv(secp, &path)?);
+        let xpub = Xpub::from_priv(secp, child.expose_secret());
+
+        let zero = ChildNumber::from_normal_idx(0)?;
+        let public_key = xpub.derive_pub(secp, &[zero, zero])?.public_key;
+
+        let public_key = PublicKey::new(public_key);
+        let address = Address::p2wpkh(&public_key, network)?;
+
+        Ok(Self {
+            address,
+            public_key,
+            private_key: root,
+            input_xpub: xpub,
+            path,
+        })
+    }
+
+    /// Export the receive descriptor of the account, e.g. `wpkh([fingerprint/84h/0h/0h]tpub.../0/*)`
+    pub fn descriptor(&self, secp: &Secp256k1<All>) -> Descriptor {
+        Descriptor::new(
+            DescriptorKind::Wpkh,
+            self.private_key.fingerprint(secp),
+            self.path.clone(),
+            self.input_xpub,
+        )
+    }
+
+    /// Get the watch-only view of this account, without any private key
+    pub fn watch_only(&self, secp: &Secp256k1<All>) -> Result<WatchOnlyAccount> {
+        WatchOnlyAccount::new(secp, self.descriptor(secp))
+    }
+}
+
+/// UTXO of a watch-only account, with the receive address holding it
+#[derive(Debug, Clone)]
+pub struct AccountUtxo {
+    pub input: TxInput,
+    /// Index of the address in the receive branch of the descriptor
+    pub address_index: u32,
+}
+
+/// Account built from an output descriptor, which can build PSBTs but never sign them
+#[derive(Debug, Clone)]
+pub struct WatchOnlyAccount {
+    pub address: Address,
+    pub descriptor: Descriptor,
+}
+
+impl WatchOnlyAccount {
+    /// Build the account from the first receive address of `descriptor`
+    pub fn new(secp: &Secp256k1<All>, descriptor: Descriptor) -> Result<Self> {
+        Ok(Self {
+            address: descriptor.address(secp, 0)?,
+            descriptor,
+        })
+    }
+
+    /// Build the account from the xpub at `path` (e.g. `m/84h/0h/0h`) of the master key with `fingerprint`
+    pub fn from_xpub(
+        secp: &Secp256k1<All>,
+        xpub: Xpub,
+        fingerprint: Fingerprint,
+        path: DerivationPath,
+    ) -> Result<Self> {
+        Self::new(
+            secp,
+            Descriptor::new(DescriptorKind::Wpkh, fingerprint, path, xpub),
+        )
+    }
+
+    /// Build the account from a `wpkh(...)`, `sh(wpkh(...))`, `pkh(...)` or `tr(...)` descriptor
+    pub fn from_descriptor(secp: &Secp256k1<All>, descriptor: &str) -> Result<Self> {
+        Self::new(secp, Descriptor::from_str(descriptor)?)
+    }
+
+    /// Key origin of the address key
+    pub fn key_source(&self, secp: &Secp256k1<All>) -> Result<KeySource> {
+        Ok(self.descriptor.derive(secp, 0)?.key_source)
+    }
+
+    /// Fetch the UTXOs of the receive addresses, until `ADDRESS_GAP` consecutive addresses hold none
+    pub async fn discover_utxos(&self, secp: &Secp256k1<All>) -> Result<Vec<AccountUtxo>> {
+        let mut utxos = Vec::new();
+        let mut unused = 0;
+        let mut address_index = 0;
+        while unused < ADDRESS_GAP {
+            let address = self.descriptor.address(secp, address_index)?;
+            let found = rpc_client::get_utxos(&address, *address.network()).await?;
+            if found.is_empty() {
+                unused += 1;
+            } else {
+                unused = 0;
+            }
+            utxos.extend(found.into_iter().map(|input| AccountUtxo {
+                input,
+                address_index,
+            }));
+            address_index += 1;
+        }
+
+        Ok(utxos)
+    }
+
+    /// Build the unsigned commit PSBT, funding the taproot payload and sending the change back.
+    ///
+    /// Change below the dust limit is left to the fee. Legacy accounts are refused, as BIP174
+    /// requires the previous transaction of their inputs, which UTXOs do not carry.
+    pub fn commit_psbt(
+        &self,
+        secp: &Secp256k1<All>,
+        inputs: &[AccountUtxo],
+        taproot: &TaprootPayload,
+        fee: Amount,
+    ) -> Result<Psbt> {
+        if self.descriptor.kind == DescriptorKind::Pkh {
+            return Err(PsbtError::LegacyInputs {
+                descriptor: self.descriptor.to_string(),
+            }
+            .into());
+        }
+
+        let total = inputs.iter().map(|utxo| utxo.input.amount).sum::<Amount>();
+        let required = taproot.prevouts.value + fee;
+        let change = total
+            .checked_sub(required)
+            .ok_or(FeeError::InsufficientFunds {
+                purpose: "fund the commit",
+                available: total,
+                required,
+            })?;
+
+        let mut output = vec![taproot.prevouts.clone()];
+        let change_script = self.address.script_pubkey();
+        if change >= change_script.dust_value() {
+            output.push(TxOut {
+                value: change,
+                script_pubkey: change_script,
+            });
+        }
+
+        let unsigned_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|utxo| TxIn {
+                    previous_output: OutPoint {
+                        txid: utxo.input.id,
+                        vout: utxo.input.index,
+                    },
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::from_consensus(0xffffffff),
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output,
+        };
+
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)?;
+        for (psbt_input, utxo) in psbt.inputs.iter_mut().zip(inputs) {
+            psbt_input.witness_utxo = Some(TxOut {
+                value: utxo.input.amount,
+                script_pubkey: self.descriptor.script_pubkey(secp, utxo.address_index)?,
+            });
+            self.descriptor
+                .update_input(secp, utxo.address_index, psbt_input)?;
+        }
+
+        Ok(psbt)
+    }
+
+    /// Build the reveal PSBT spending the taproot payload, the first output of `commit`,
+    /// into the `postage` of `recipient`
+    pub fn reveal_psbt(
+        &self,
+        commit: &Psbt,
+        taproot: &TaprootPayload,
+        redeem_script: &ScriptBuf,
+        recipient: &Address,
+        postage: Amount,
+    ) -> Result<Psbt> {
+        let reveal_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: commit.unsigned_tx.txid(),
+                    vout: 0,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_consensus(0xffffffff),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: postage,
+                script_pubkey: recipient.script_pubkey(),
+            }],
+        };
+
+        reveal_psbt(reveal_tx, taproot, redeem_script)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::opcodes::all::OP_CHECKSIG;
+    use bitcoin::script::Builder as ScriptBuilder;
+    use bitcoin::Txid;
+
+    use super::*;
+    use crate::{taproot, SENDER_ADDRESS_MNEMONIC};
+
+    #[test]
+    fn test_should_build_commit_psbt_watch_only() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let watch_only = sender.watch_only(&secp).unwrap();
+
+        let descriptor = format!(
+            "wpkh([{}/84h/0h/0h]{}/0/*)",
+            watch_only.descriptor.fingerprint, watch_only.descriptor.xpub
+        );
+        let from_descriptor = WatchOnlyAccount::from_descriptor(&secp, &descriptor).unwrap();
+        assert_eq!(from_descriptor.address, sender.address);
+        assert_eq!(
+            from_descriptor.key_source(&secp).unwrap(),
+            watch_only.key_source(&secp).unwrap()
+        );
+
+        let (keypair, x_public_key) = taproot::generate_keypair(&secp);
+        let redeem_script = ScriptBuilder::new()
+            .push_x_only_key(&x_public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let taproot_payload = TaprootPayload::build(
+            &secp,
+            keypair,
+            x_public_key,
+            &redeem_script,
+            5_000,
+            Network::Testnet,
+        )
+        .unwrap();
+
+        let inputs = [AccountUtxo {
+            input: TxInput {
+                id: Txid::from_str(
+                    "14a7109b642b4fca7f10cd9bee89db73770c5a2d107f6a51c6bd7625dcdc2aed",
+                )
+                .unwrap(),
+                index: 0,
+                amount: Amount::from_sat(8_000),
+            },
+            address_index: 0,
+        }];
+        let mut psbt = from_descriptor
+            .commit_psbt(&secp, &inputs, &taproot_payload, Amount::from_sat(1_000))
+            .unwrap();
+        assert_eq!(psbt.unsigned_tx.output.len(), 2);
+        assert_eq!(psbt.unsigned_tx.output[1].value, Amount::from_sat(2_000));
+
+        // the signing side finds its key through the derivation data
+        let keys = psbt
+            .sign(sender.private_key.expose_secret(), &secp)
+            .unwrap();
+        assert_eq!(keys.values().map(Vec::len).sum::<usize>(), 1);
+        assert_eq!(psbt.inputs[0].partial_sigs.len(), 1);
+
+        // an input of a later receive address is signed with the key of that address
+        let gap_inputs = [AccountUtxo {
+            input: TxInput {
+                id: inputs[0].input.id,
+                index: 1,
+                amount: inputs[0].input.amount,
+            },
+            address_index: 7,
+        }];
+        let mut psbt = from_descriptor
+            .commit_psbt(
+                &secp,
+                &gap_inputs,
+                &taproot_payload,
+                Amount::from_sat(1_000),
+            )
+            .unwrap();
+        assert_eq!(
+            psbt.inputs[0].witness_utxo.as_ref().unwrap().script_pubkey,
+            from_descriptor.descriptor.script_pubkey(&secp, 7).unwrap()
+        );
+        let keys = psbt
+            .sign(sender.private_key.expose_secret(), &secp)
+            .unwrap();
+        assert_eq!(keys.values().map(Vec::len).sum::<usize>(), 1);
+
+        // change below the dust limit is left to the fee
+        let psbt = from_descriptor
+            .commit_psbt(&secp, &inputs, &taproot_payload, Amount::from_sat(2_900))
+            .unwrap();
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(psbt.fee().unwrap(), Amount::from_sat(3_000));
+
+        // the reveal spends the payload output of the commit
+        let reveal = from_descriptor
+            .reveal_psbt(
+                &psbt,
+                &taproot_payload,
+                &redeem_script,
+                &sender.address,
+                Amount::from_sat(333),
+            )
+            .unwrap();
+        assert_eq!(
+            reveal.unsigned_tx.input[0].previous_output,
+            OutPoint::new(psbt.unsigned_tx.txid(), 0)
+        );
+        assert_eq!(
+            reveal.inputs[0].witness_utxo.as_ref(),
+            Some(&taproot_payload.prevouts)
+        );
+
+        // legacy inputs would need their previous transaction
+        let mut pkh = from_descriptor.descriptor.clone();
+        pkh.kind = DescriptorKind::Pkh;
+        assert!(WatchOnlyAccount::new(&secp, pkh)
+            .unwrap()
+            .commit_psbt(&secp, &inputs, &taproot_payload, Amount::from_sat(1_000))
+            .is_err());
+    }
+}
@@ -0,0 +1,438 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::bip32::{self, ChildNumber, DerivationPath, Fingerprint, KeySource, Xpub};
+use bitcoin::psbt::Input;
+use bitcoin::secp256k1::{Secp256k1, Verification};
+use bitcoin::{Address, Network, PublicKey, ScriptBuf, XOnlyPublicKey};
+
+use crate::error::{Error, KeyError, Result};
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Script type of a single key descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorKind {
+    /// `wpkh(KEY)`
+    Wpkh,
+    /// `sh(wpkh(KEY))`, P2WPKH nested in P2SH
+    ShWpkh,
+    /// `pkh(KEY)`, legacy P2PKH
+    Pkh,
+    /// `tr(KEY)`, key path only (BIP86)
+    Tr,
+}
+
+impl DescriptorKind {
+    /// Script expression around the key, e.g. `("sh(wpkh(", "))")`
+    fn wrapper(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Wpkh => ("wpkh(", ")"),
+            Self::ShWpkh => ("sh(wpkh(", "))"),
+            Self::Pkh => ("pkh(", ")"),
+            Self::Tr => ("tr(", ")"),
+        }
+    }
+}
+
+/// Ranged single key output descriptor, e.g. `wpkh([d34db33f/84h/1h/0h]tpub.../0/*)#checksum`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Descriptor {
+    pub kind: DescriptorKind,
+    /// Fingerprint of the master key
+    pub fingerprint: Fingerprint,
+    /// Path from the master key to `xpub`
+    pub origin: DerivationPath,
+    pub xpub: Xpub,
+    /// Path from `xpub` to the keys, without the final wildcard step
+    pub branch: DerivationPath,
+}
+
+/// Key derived from a descriptor
+#[derive(Debug, Clone)]
+pub struct DerivedKey {
+    pub public_key: PublicKey,
+    pub key_source: KeySource,
+}
+
+impl Descriptor {
+    /// Descriptor of the receive branch (`/0/*`) of the account `xpub`
+    pub fn new(
+        kind: DescriptorKind,
+        fingerprint: Fingerprint,
+        origin: DerivationPath,
+        xpub: Xpub,
+    ) -> Self {
+        Self {
+            kind,
+            fingerprint,
+            origin,
+            xpub,
+            branch: DerivationPath::from(vec![ChildNumber::Normal { index: 0 }]),
+        }
+    }
+
+    pub fn network(&self) -> Network {
+        self.xpub.network
+    }
+
+    /// Derive the key at `index`
+    pub fn derive<C: Verification>(&self, secp: &Secp256k1<C>, index: u32) -> Result<DerivedKey> {
+        let path = self.branch.extend([ChildNumber::from_normal_idx(index)?]);
+        let public_key = PublicKey::new(self.xpub.derive_pub(secp, &path)?.public_key);
+        let origin = self.origin.extend(path);
+
+        Ok(DerivedKey {
+            public_key,
+            key_source: (self.fingerprint, origin),
+        })
+    }
+
+    /// Index of the key at `key_source`, when it is one of the descriptor keys
+    pub fn address_index(&self, (fingerprint, path): &KeySource) -> Option<u32> {
+        if *fingerprint != self.fingerprint {
+            return None;
+        }
+        let prefix = self.origin.extend(&self.branch);
+        match path.as_ref().strip_prefix(prefix.as_ref())? {
+            [ChildNumber::Normal { index }] => Some(*index),
+            _ => None,
+        }
+    }
+
+    /// Derive the address at `index`
+    pub fn address<C: Verification>(&self, secp: &Secp256k1<C>, index: u32) -> Result<Address> {
+        let key = self.derive(secp, index)?;
+        let address = match self.kind {
+            DescriptorKind::Wpkh => Address::p2wpkh(&key.public_key, self.network())?,
+            DescriptorKind::ShWpkh => Address::p2shwpkh(&key.public_key, self.network())?,
+            DescriptorKind::Pkh => Address::p2pkh(&key.public_key, self.network()),
+            DescriptorKind::Tr => Address::p2tr(
+                secp,
+                XOnlyPublicKey::from(key.public_key.inner),
+                None,
+                self.network(),
+            ),
+        };
+
+        Ok(address)
+    }
+
+    pub fn script_pubkey<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<ScriptBuf> {
+        Ok(self.address(secp, index)?.script_pubkey())
+    }
+
+    /// Fill in the derivation data of a PSBT input spending the output at `index`
+    pub fn update_input<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+        input: &mut Input,
+    ) -> Result<()> {
+        let key = self.derive(secp, index)?;
+        match self.kind {
+            DescriptorKind::Wpkh | DescriptorKind::Pkh => {
+                input
+                    .bip32_derivation
+                    .insert(key.public_key.inner, key.key_source);
+            }
+            DescriptorKind::ShWpkh => {
+                let program = Address::p2wpkh(&key.public_key, self.network())?.script_pubkey();
+                input.redeem_script = Some(program);
+                input
+                    .bip32_derivation
+                    .insert(key.public_key.inner, key.key_source);
+            }
+            DescriptorKind::Tr => {
+                let x_public_key = XOnlyPublicKey::from(key.public_key.inner);
+                input.tap_internal_key = Some(x_public_key);
+                input
+                    .tap_key_origins
+                    .insert(x_public_key, (Vec::new(), key.key_source));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn to_string_without_checksum(&self) -> String {
+        let (prefix, suffix) = self.kind.wrapper();
+        let origin = self
+            .origin
+            .as_ref()
+            .iter()
+            .map(|child| format!("/{child:#}"))
+            .collect::<String>();
+        let branch = self
+            .branch
+            .as_ref()
+            .iter()
+            .map(|child| format!("/{child:#}"))
+            .collect::<String>();
+
+        format!(
+            "{prefix}[{}{origin}]{}{branch}/*{suffix}",
+            self.fingerprint, self.xpub
+        )
+    }
+}
+
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let descriptor = self.to_string_without_checksum();
+        let checksum = checksum(&descriptor).map_err(|_| fmt::Error)?;
+        write!(f, "{descriptor}#{checksum}")
+    }
+}
+
+impl FromStr for Descriptor {
+    type Err = Error;
+
+    /// Parse a descriptor, verifying its checksum when present. The key origin is optional and
+    /// a `<0;1>` multipath step resolves to the receive branch
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let descriptor = match s.split_once('#') {
+            Some((descriptor, expected)) => {
+                let computed = checksum(descriptor)?;
+                if computed != expected {
+                    return Err(KeyError::DescriptorChecksum {
+                        expected: expected.to_string(),
+                        computed,
+                    }
+                    .into());
+                }
+                descriptor
+            }
+            None => s,
+        };
+        let invalid = |reason| KeyError::InvalidDescriptor {
+            descriptor: descriptor.to_string(),
+            reason,
+        };
+
+        let (kind, key) = [
+            DescriptorKind::Wpkh,
+            DescriptorKind::ShWpkh,
+            DescriptorKind::Pkh,
+            DescriptorKind::Tr,
+        ]
+        .into_iter()
+        .find_map(|kind| Some((kind, descriptor.strip_prefix(kind.wrapper().0)?)))
+        .ok_or_else(|| invalid("unsupported descriptor"))?;
+        let key = key
+            .strip_suffix(kind.wrapper().1)
+            .ok_or_else(|| invalid("unbalanced descriptor"))?;
+        let key = key
+            .strip_suffix("/*")
+            .ok_or_else(|| invalid("descriptor is not ranged"))?;
+
+        let (origin, key) = match key.strip_prefix('[') {
+            Some(key) => {
+                let (origin, key) = key
+                    .split_once(']')
+                    .ok_or_else(|| invalid("unbalanced key origin"))?;
+                (Some(origin), key)
+            }
+            None => (None, key),
+        };
+        let (xpub, branch) = key.split_once('/').unwrap_or((key, ""));
+        let xpub = Xpub::from_str(xpub)?;
+        // a key without origin is its own master key
+        let (fingerprint, origin) = match origin {
+            Some(origin) => {
+                let (fingerprint, origin) = origin.split_once('/').unwrap_or((origin, ""));
+                (
+                    Fingerprint::from_str(fingerprint).map_err(bip32::Error::Hex)?,
+                    parse_path(origin)?,
+                )
+            }
+            None => (xpub.fingerprint(), DerivationPath::master()),
+        };
+        let branch = receive_branch(branch).ok_or_else(|| invalid("invalid multipath step"))?;
+
+        Ok(Self {
+            kind,
+            fingerprint,
+            origin,
+            xpub,
+            branch: parse_path(&branch)?,
+        })
+    }
+}
+
+/// Resolve the BIP389 multipath step of `branch`, e.g. `<0;1>`, to its first (receive) path
+fn receive_branch(branch: &str) -> Option<String> {
+    let steps = branch
+        .split('/')
+        .map(|step| match step.strip_prefix('<') {
+            Some(paths) => paths
+                .strip_suffix('>')
+                .and_then(|paths| paths.split_once(';'))
+                .map(|(receive, _)| receive),
+            None => Some(step),
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(steps.join("/"))
+}
+
+fn parse_path(path: &str) -> Result<DerivationPath> {
+    if path.is_empty() {
+        return Ok(DerivationPath::master());
+    }
+
+    Ok(DerivationPath::from_str(&format!("m/{path}"))?)
+}
+
+/// BIP380 descriptor checksum
+pub fn checksum(descriptor: &str) -> Result<String> {
+    fn polymod(c: u64, val: u64) -> u64 {
+        let c0 = c >> 35;
+        let mut c = ((c & 0x7ffffffff) << 5) ^ val;
+        for (bit, generator) in [
+            0xf5dee51989,
+            0xa9fdca3312,
+            0x1bab10e32d,
+            0x3706b1677a,
+            0x644d626ffd,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            if c0 & (1 << bit) != 0 {
+                c ^= generator;
+            }
+        }
+        c
+    }
+
+    let mut c = 1;
+    let mut class = 0;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let pos = INPUT_CHARSET
+            .find(ch)
+            .ok_or(KeyError::DescriptorCharacter(ch))? as u64;
+        c = polymod(c, pos & 31);
+        class = class * 3 + (pos >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+
+    Ok((0..8)
+        .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Account;
+    use crate::SENDER_ADDRESS_MNEMONIC;
+
+    #[test]
+    fn test_should_roundtrip_descriptor_with_checksum() {
+        assert_eq!(checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let descriptor = sender.descriptor(&secp);
+        assert_eq!(descriptor.address(&secp, 0).unwrap(), sender.address);
+
+        let exported = descriptor.to_string();
+        assert!(exported.starts_with("wpkh(["));
+        assert_eq!(Descriptor::from_str(&exported).unwrap(), descriptor);
+
+        let mut tampered = exported.clone();
+        tampered.pop();
+        tampered.push('x');
+        assert!(Descriptor::from_str(&tampered).is_err());
+
+        let tr = Descriptor {
+            kind: DescriptorKind::Tr,
+            ..descriptor.clone()
+        };
+        let address = tr.address(&secp, 0).unwrap();
+        assert_eq!(address.address_type(), Some(bitcoin::AddressType::P2tr));
+        assert_eq!(Descriptor::from_str(&tr.to_string()).unwrap(), tr);
+
+        let mut input = Input::default();
+        tr.update_input(&secp, 0, &mut input).unwrap();
+        assert!(input.tap_internal_key.is_some());
+        assert_eq!(input.tap_key_origins.len(), 1);
+
+        // legacy and nested segwit addresses of the same keys
+        let sh_wpkh = Descriptor {
+            kind: DescriptorKind::ShWpkh,
+            ..descriptor.clone()
+        };
+        assert!(sh_wpkh.to_string().starts_with("sh(wpkh(["));
+        assert_eq!(Descriptor::from_str(&sh_wpkh.to_string()).unwrap(), sh_wpkh);
+        let mut input = Input::default();
+        sh_wpkh.update_input(&secp, 0, &mut input).unwrap();
+        assert_eq!(
+            input.redeem_script.unwrap().to_p2sh(),
+            sh_wpkh.script_pubkey(&secp, 0).unwrap()
+        );
+
+        let pkh = Descriptor {
+            kind: DescriptorKind::Pkh,
+            ..descriptor
+        };
+        assert_eq!(
+            pkh.address(&secp, 0).unwrap().address_type(),
+            Some(bitcoin::AddressType::P2pkh)
+        );
+        assert_eq!(Descriptor::from_str(&pkh.to_string()).unwrap(), pkh);
+    }
+
+    #[test]
+    fn test_should_import_wallet_exports() {
+        // BIP84 vector of `abandon abandon ... about`, as exported by Sparrow and Bitcoin Core
+        let secp = Secp256k1::new();
+        let exported = "wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*)#hpg6d6w2";
+        let descriptor = Descriptor::from_str(exported).unwrap();
+        assert_eq!(descriptor.fingerprint.to_string(), "73c5da0a");
+        assert_eq!(descriptor.origin.to_string(), "m/84'/0'/0'");
+        assert_eq!(
+            descriptor.address(&secp, 0).unwrap().to_string(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+        let key = descriptor.derive(&secp, 0).unwrap();
+        assert_eq!(key.key_source.1.to_string(), "m/84'/0'/0'/0/0");
+
+        // a key without origin is its own master key
+        let exported = "wpkh(xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/1/*)#8xmuadkz";
+        let change = Descriptor::from_str(exported).unwrap();
+        assert_eq!(change.fingerprint, descriptor.xpub.fingerprint());
+        assert_eq!(
+            change.address(&secp, 0).unwrap().to_string(),
+            "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el"
+        );
+        assert_eq!(
+            change.derive(&secp, 0).unwrap().key_source.1.to_string(),
+            "m/1/0"
+        );
+
+        assert!(Descriptor::from_str("wpkh([73c5da0a/84'/0'/0'xpub/0/*)").is_err());
+        let unbalanced = format!("wpkh({}/<0;1/*)", descriptor.xpub);
+        assert!(Descriptor::from_str(&unbalanced).is_err());
+    }
+}
@@ -0,0 +1,357 @@
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+use bitcoin::psbt::{PsbtSighashType, SignError, SigningErrors};
+use bitcoin::{bip32, Amount, FeeRate};
+use thiserror::Error;
+
+use crate::interpreter::ScriptVerificationReport;
+use crate::signer::SkippedInput;
+use crate::verify::VerificationReport;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Error of the crate, by the step which failed
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Key(#[from] KeyError),
+    #[error(transparent)]
+    Psbt(#[from] PsbtError),
+    #[error(transparent)]
+    Signing(#[from] SigningError),
+    #[error(transparent)]
+    Fee(#[from] FeeError),
+    #[error(transparent)]
+    Backend(#[from] BackendError),
+    #[error(transparent)]
+    Inscription(#[from] InscriptionError),
+    #[error(transparent)]
+    Policy(#[from] PolicyError),
+    #[error(transparent)]
+    Rpc(#[from] RpcError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// Keys, mnemonics, descriptors, addresses and the keystore
+#[derive(Debug, Error)]
+pub enum KeyError {
+    #[error(transparent)]
+    Bip32(#[from] bip32::Error),
+    #[error(transparent)]
+    Mnemonic(#[from] bip39::Error),
+    #[error(transparent)]
+    Address(#[from] bitcoin::address::Error),
+    #[error("{reason}: {descriptor}")]
+    InvalidDescriptor {
+        descriptor: String,
+        reason: &'static str,
+    },
+    #[error("invalid descriptor checksum {expected}, expected {computed}")]
+    DescriptorChecksum { expected: String, computed: String },
+    #[error("invalid character {0:?} in descriptor")]
+    DescriptorCharacter(char),
+    #[error("no account `{name}` in the keystore")]
+    UnknownAccount { name: String },
+    #[error("account `{name}` already exists in the keystore")]
+    DuplicateAccount { name: String },
+    #[error("account `{name}` does not match its xpub")]
+    AccountMismatch { name: String },
+    #[error("wrong password for account `{name}` or corrupted keystore")]
+    WrongPassword { name: String },
+    #[error("keystore account `{name}` is corrupted: {reason}")]
+    CorruptedAccount { name: String, reason: String },
+    #[error("unsupported keystore version {version}")]
+    UnsupportedKeystore { version: u32 },
+    #[error("keystore key derivation failed: {0}")]
+    Kdf(String),
+    #[error("failed to encrypt the secret of account `{name}`")]
+    Encryption { name: String },
+}
+
+/// Building, parsing and finalizing PSBTs and the documents carrying them
+#[derive(Debug, Error)]
+pub enum PsbtError {
+    #[error(transparent)]
+    Psbt(#[from] bitcoin::psbt::Error),
+    #[error(transparent)]
+    Parse(#[from] bitcoin::psbt::PsbtParseError),
+    #[error(transparent)]
+    Decode(#[from] bitcoin::consensus::encode::Error),
+    #[error(transparent)]
+    LockTime(#[from] bitcoin::absolute::Error),
+    #[error("malformed PSBT: {0}")]
+    Malformed(&'static str),
+    #[error("unsupported PSBT version {version}")]
+    UnsupportedVersion { version: u32 },
+    #[error("duplicate PSBT key {key}")]
+    DuplicateKey { key: String },
+    #[error("{0} are not modifiable")]
+    NotModifiable(&'static str),
+    #[error("input would change the locktime of a signed transaction")]
+    LockTimeChange,
+    #[error("inputs require incompatible locktime types")]
+    IncompatibleLockTimes,
+    #[error("input {index} has no spent utxo")]
+    MissingUtxo { index: usize },
+    #[error("cannot spend the legacy outputs of {descriptor} without their previous transactions")]
+    LegacyInputs { descriptor: String },
+    #[error("input {index} has no tap leaf script")]
+    MissingTapLeafScript { index: usize },
+    #[error("input {index} is not signed")]
+    UnsignedInput { index: usize },
+    #[error("input {index} needs two escrow signatures")]
+    MissingEscrowSignature { index: usize },
+    #[error("arbitration must pay either the sender or the recipient")]
+    InvalidArbitration,
+    #[error("psbt signatures are not complete:\n{0}")]
+    Incomplete(VerificationReport),
+    #[error("document is neither a base64 PSBT nor hex")]
+    InvalidDocument,
+    #[error("invalid signing bundle: {0}")]
+    InvalidBundle(String),
+    #[error("invalid listing: {0}")]
+    InvalidListing(&'static str),
+}
+
+/// Computing sighashes and producing signatures
+#[derive(Debug, Error)]
+pub enum SigningError {
+    #[error(transparent)]
+    Sighash(#[from] bitcoin::sighash::Error),
+    #[error(transparent)]
+    Secp256k1(#[from] bitcoin::secp256k1::Error),
+    #[error("transaction has {inputs} inputs but {spent} spent outputs were given")]
+    SpentOutputCount { inputs: usize, spent: usize },
+    #[error("input {index} not found")]
+    InputNotFound { index: usize },
+    #[error("input {index} has the unsupported sighash type {sighash_type}")]
+    SighashType {
+        index: usize,
+        sighash_type: PsbtSighashType,
+    },
+    #[error("taproot signature of input {index} needs every prevout")]
+    TaprootPrevouts { index: usize },
+    #[error("input {index} does not spend a taproot output")]
+    NotTaproot { index: usize },
+    #[error("script of input {index} does not commit to the signing key")]
+    ForeignKey { index: usize },
+    #[error("tap script signature of input {index} is for a leaf the input does not reveal")]
+    UnknownLeaf { index: usize },
+    #[error("signature of input {index} does not use the {expected} sighash type of the input")]
+    SighashMismatch {
+        index: usize,
+        expected: PsbtSighashType,
+    },
+    #[error("cannot sign input {index}: {source}")]
+    Psbt { index: usize, source: SignError },
+    #[error("expected {expected} signing keys, got {signed}")]
+    KeyCount { expected: usize, signed: usize },
+    #[error("signed {signed} of {inputs} inputs, skipped {skipped:?}")]
+    Skipped {
+        signed: usize,
+        inputs: usize,
+        skipped: Vec<SkippedInput>,
+    },
+    #[error("transaction scripts do not verify:\n{0}")]
+    ScriptVerification(ScriptVerificationReport),
+}
+
+/// Funding transactions and enforcing the fee policy
+#[derive(Debug, Error)]
+pub enum FeeError {
+    #[error("cannot compute the psbt fee: {0}")]
+    PsbtFee(#[source] bitcoin::psbt::Error),
+    #[error("fee of {fee} exceeds the absolute cap of {max_fee}")]
+    AbsoluteFee { fee: Amount, max_fee: Amount },
+    #[error("fee of {fee} ({} sat/vB) exceeds the fee rate cap of {} sat/vB", fee_rate.to_sat_per_vb_ceil(), max_fee_rate.to_sat_per_vb_floor())]
+    FeeRate {
+        fee: Amount,
+        fee_rate: FeeRate,
+        max_fee_rate: FeeRate,
+    },
+    #[error("fee of {fee} is more than {max_percent}% of the {inputs} spent")]
+    InputsPercentage {
+        fee: Amount,
+        inputs: Amount,
+        max_percent: u8,
+    },
+    #[error("insufficient balance to {purpose}: {available} available, {required} required")]
+    InsufficientFunds {
+        purpose: &'static str,
+        available: Amount,
+        required: Amount,
+    },
+    #[error("dummy UTXOs of {padding} are below the dust limit of {dust}")]
+    DustPadding { padding: Amount, dust: Amount },
+    #[error("marketplace fee of {basis_points} basis points exceeds the price")]
+    ExcessiveMarketplaceFee { basis_points: u64 },
+}
+
+/// Signing services holding the keys, such as an external signer
+#[derive(Debug, Error)]
+pub enum BackendError {
+    #[error("cannot run external signer {}: {source}", command.display())]
+    Spawn {
+        command: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("cannot talk to external signer {}: {source}", command.display())]
+    Io {
+        command: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("external signer {} exited with {status}: {stderr}", command.display())]
+    Exit {
+        command: PathBuf,
+        status: ExitStatus,
+        stderr: String,
+    },
+    #[error("external signer {} error: {error}", command.display())]
+    Rejected { command: PathBuf, error: String },
+    #[error("invalid response from external signer {}: {source}", command.display())]
+    InvalidResponse {
+        command: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("external signer has no {kind} receive descriptor")]
+    MissingDescriptor { kind: &'static str },
+}
+
+/// Inscription envelopes and the taproot or P2WSH outputs committing to scripts
+#[derive(Debug, Error)]
+pub enum InscriptionError {
+    #[error(transparent)]
+    PushBytes(#[from] bitcoin::script::PushBytesError),
+    #[error(transparent)]
+    TaprootBuilder(#[from] bitcoin::taproot::TaprootBuilderError),
+    #[error("invalid inscription data: {0}")]
+    Data(#[from] ord_rs::OrdError),
+    #[error("cannot compute the taproot spend info: {0}")]
+    TaprootCompute(&'static str),
+    #[error("script of {size} bytes exceeds the standard {output} limit of {limit}")]
+    ScriptTooLarge {
+        output: &'static str,
+        size: usize,
+        limit: usize,
+    },
+}
+
+/// Spending policies compiled to miniscript
+#[derive(Debug, Error)]
+pub enum PolicyError {
+    #[error("miniscript: {0}")]
+    Miniscript(String),
+    #[error("unknown key {name} in policy")]
+    UnknownKey { name: String },
+    #[error("invalid hash {hash} in policy")]
+    InvalidHash { hash: String },
+    #[error("missing control block for policy leaf")]
+    MissingControlBlock,
+    #[error("unsupported policy descriptor {descriptor}")]
+    UnsupportedDescriptor { descriptor: String },
+    #[error("cannot satisfy the policy: {0}")]
+    Unsatisfiable(String),
+}
+
+/// Esplora API calls
+#[derive(Debug, Error)]
+pub enum RpcError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("failed to broadcast transaction ({status}): {body}")]
+    Broadcast {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    #[error("invalid txid {txid}: {source}")]
+    InvalidTxid {
+        txid: String,
+        source: bitcoin::hashes::hex::HexToArrayError,
+    },
+}
+
+impl FeeError {
+    /// The fee computed for the transaction refused by the fee policy, if any
+    pub fn fee(&self) -> Option<Amount> {
+        match self {
+            Self::AbsoluteFee { fee, .. }
+            | Self::FeeRate { fee, .. }
+            | Self::InputsPercentage { fee, .. } => Some(*fee),
+            _ => None,
+        }
+    }
+}
+
+impl SigningError {
+    /// Error of the first input `Psbt::sign` failed on
+    pub fn from_psbt(errors: SigningErrors) -> Self {
+        match errors.into_iter().next() {
+            Some((index, source)) => Self::Psbt { index, source },
+            None => Self::KeyCount {
+                expected: 1,
+                signed: 0,
+            },
+        }
+    }
+}
+
+/// Convert errors of the dependencies straight into their category
+macro_rules! impl_from {
+    ($($source:ty => $category:ident),* $(,)?) => {
+        $(
+            impl From<$source> for Error {
+                fn from(err: $source) -> Self {
+                    Self::$category(err.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from! {
+    bip32::Error => Key,
+    bip39::Error => Key,
+    bitcoin::address::Error => Key,
+    bitcoin::psbt::Error => Psbt,
+    bitcoin::psbt::PsbtParseError => Psbt,
+    bitcoin::consensus::encode::Error => Psbt,
+    bitcoin::absolute::Error => Psbt,
+    bitcoin::sighash::Error => Signing,
+    bitcoin::secp256k1::Error => Signing,
+    bitcoin::script::PushBytesError => Inscription,
+    bitcoin::taproot::TaprootBuilderError => Inscription,
+    ord_rs::OrdError => Inscription,
+    reqwest::Error => Rpc,
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::psbt::SigningErrors;
+
+    use super::*;
+
+    #[test]
+    fn test_should_match_on_failing_input() {
+        let errors =
+            SigningErrors::from([(1, SignError::MissingSpendUtxo), (2, SignError::NotWpkh)]);
+        let err = Error::from(SigningError::from_psbt(errors));
+        assert!(matches!(
+            err,
+            Error::Signing(SigningError::Psbt {
+                index: 1,
+                source: SignError::MissingSpendUtxo
+            })
+        ));
+        assert_eq!(
+            err.to_string(),
+            "cannot sign input 1: missing spend utxo in PSBT"
+        );
+
+        let err = Error::from(bip32::Error::InvalidChildNumber(1 << 31));
+        assert!(matches!(err, Error::Key(KeyError::Bip32(_))));
+    }
+}
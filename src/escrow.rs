@@ -0,0 +1,533 @@
+use bitcoin::bip32::{ChildNumber, KeySource};
+use bitcoin::key::XOnlyPublicKey;
+use bitcoin::opcodes::all::{OP_CHECKMULTISIG, OP_CHECKSIG, OP_CHECKSIGADD, OP_NUMEQUAL};
+use bitcoin::script::Builder as ScriptBuilder;
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::taproot::{LeafVersion, TapLeafHash, TaprootBuilder, TaprootSpendInfo};
+use bitcoin::{
+    absolute::LockTime, transaction::Version, Address, Amount, Network, OutPoint, Psbt, PublicKey,
+    ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use ord_rs::transaction::TxInput;
+
+use crate::error::{FeeError, InscriptionError, PsbtError, Result, SigningError};
+use crate::fee::FeePolicy;
+use crate::signer::Signer;
+use crate::Account;
+
+/// BIP341 "nothing up my sleeve" point, used as internal key so that the
+/// taproot escrow can only be spent through the 2-of-3 script path.
+pub(crate) const NUMS_INTERNAL_KEY: [u8; 32] =
+    hex_literal::hex!("50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0");
+
+/// Output type used to lock the escrowed funds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscrowKind {
+    /// `OP_2 <pk> <pk> <pk> OP_3 OP_CHECKMULTISIG` with sorted keys
+    P2wsh,
+    /// `<pk> OP_CHECKSIG <pk> OP_CHECKSIGADD <pk> OP_CHECKSIGADD OP_2 OP_NUMEQUAL` leaf
+    Taproot,
+}
+
+/// Participants of the escrow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscrowParty {
+    Sender,
+    Recipient,
+    Marketplace,
+}
+
+/// Ways to spend the escrow output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpendPath {
+    /// Pay the recipient; cosigned by sender and recipient
+    Release,
+    /// Pay the sender back; cosigned by recipient and sender
+    Refund,
+    /// Pay the given party; cosigned by the marketplace and that party
+    Arbitration(EscrowParty),
+}
+
+#[derive(Debug, Clone)]
+struct Participant {
+    public_key: PublicKey,
+    key_source: KeySource,
+    address: Address,
+}
+
+/// 2-of-3 escrow between sender, recipient and marketplace
+#[derive(Debug)]
+pub struct Escrow {
+    pub kind: EscrowKind,
+    pub address: Address,
+    /// Witness script for P2WSH, leaf script for Taproot
+    pub script: ScriptBuf,
+    taproot_spend_info: Option<TaprootSpendInfo>,
+    sender: Participant,
+    recipient: Participant,
+    marketplace: Participant,
+}
+
+impl Escrow {
+    /// Derive the escrow address from the xpubs of the three accounts
+    pub fn new(
+        secp: &Secp256k1<All>,
+        kind: EscrowKind,
+        sender: &Account,
+        recipient: &Account,
+        marketplace: &Account,
+        network: Network,
+    ) -> Result<Self> {
+        let sender = Participant::from_account(secp, sender)?;
+        let recipient = Participant::from_account(secp, recipient)?;
+        let marketplace = Participant::from_account(secp, marketplace)?;
+
+        let mut keys = [
+            sender.public_key,
+            recipient.public_key,
+            marketplace.public_key,
+        ];
+        keys.sort_by_key(|key| key.inner.serialize());
+
+        let (script, taproot_spend_info, address) = match kind {
+            EscrowKind::P2wsh => {
+                let script = ScriptBuilder::new()
+                    .push_int(2)
+                    .push_key(&keys[0])
+                    .push_key(&keys[1])
+                    .push_key(&keys[2])
+                    .push_int(3)
+                    .push_opcode(OP_CHECKMULTISIG)
+                    .into_script();
+                let address = Address::p2wsh(&script, network);
+
+                (script, None, address)
+            }
+            EscrowKind::Taproot => {
+                let keys = keys.map(|key| XOnlyPublicKey::from(key.inner));
+                let script = ScriptBuilder::new()
+                    .push_x_only_key(&keys[0])
+                    .push_opcode(OP_CHECKSIG)
+                    .push_x_only_key(&keys[1])
+                    .push_opcode(OP_CHECKSIGADD)
+                    .push_x_only_key(&keys[2])
+                    .push_opcode(OP_CHECKSIGADD)
+                    .push_int(2)
+                    .push_opcode(OP_NUMEQUAL)
+                    .into_script();
+
+                let internal_key = XOnlyPublicKey::from_slice(&NUMS_INTERNAL_KEY)?;
+                let taproot_spend_info = TaprootBuilder::new()
+                    .add_leaf(0, script.clone())?
+                    .finalize(secp, internal_key)
+                    .map_err(|_| {
+                        InscriptionError::TaprootCompute("incomplete escrow script tree")
+                    })?;
+                let address = Address::p2tr_tweaked(taproot_spend_info.output_key(), network);
+
+                (script, Some(taproot_spend_info), address)
+            }
+        };
+
+        Ok(Self {
+            kind,
+            address,
+            script,
+            taproot_spend_info,
+            sender,
+            recipient,
+            marketplace,
+        })
+    }
+
+    /// Build the PSBT moving `amount` from the funder's P2WPKH inputs into the escrow.
+    ///
+    /// The leftover, minus `fee`, goes back to the funder.
+    pub fn funding_psbt(
+        &self,
+        secp: &Secp256k1<All>,
+        funder: &Account,
+        inputs: &[TxInput],
+        amount: Amount,
+        fee: Amount,
+    ) -> Result<Psbt> {
+        let total = inputs.iter().map(|input| input.amount).sum::<Amount>();
+        let required = amount + fee;
+        let change = total
+            .checked_sub(required)
+            .ok_or(FeeError::InsufficientFunds {
+                purpose: "fund the escrow",
+                available: total,
+                required,
+            })?;
+
+        let mut output = vec![TxOut {
+            value: amount,
+            script_pubkey: self.address.script_pubkey(),
+        }];
+        if change > Amount::ZERO {
+            output.push(TxOut {
+                value: change,
+                script_pubkey: funder.address.script_pubkey(),
+            });
+        }
+
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx(inputs, output))?;
+
+        let funder = Participant::from_account(secp, funder)?;
+        for (psbt_input, input) in psbt.inputs.iter_mut().zip(inputs) {
+            psbt_input.witness_utxo = Some(TxOut {
+                value: input.amount,
+                script_pubkey: funder.address.script_pubkey(),
+            });
+            psbt_input
+                .bip32_derivation
+                .insert(funder.public_key.inner, funder.key_source.clone());
+        }
+
+        Ok(psbt)
+    }
+
+    /// Build the PSBT spending the escrow output `input` along the given path
+    pub fn spend_psbt(&self, path: SpendPath, input: &TxInput, fee: Amount) -> Result<Psbt> {
+        let destination = match path {
+            SpendPath::Release | SpendPath::Arbitration(EscrowParty::Recipient) => &self.recipient,
+            SpendPath::Refund | SpendPath::Arbitration(EscrowParty::Sender) => &self.sender,
+            SpendPath::Arbitration(EscrowParty::Marketplace) => {
+                return Err(PsbtError::InvalidArbitration.into())
+            }
+        };
+        let value = input
+            .amount
+            .checked_sub(fee)
+            .ok_or(FeeError::InsufficientFunds {
+                purpose: "pay the escrow spend fee",
+                available: input.amount,
+                required: fee,
+            })?;
+
+        let output = vec![TxOut {
+            value,
+            script_pubkey: destination.address.script_pubkey(),
+        }];
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx(std::slice::from_ref(input), output))?;
+
+        let psbt_input = &mut psbt.inputs[0];
+        psbt_input.witness_utxo = Some(TxOut {
+            value: input.amount,
+            script_pubkey: self.address.script_pubkey(),
+        });
+        match &self.taproot_spend_info {
+            None => {
+                psbt_input.witness_script = Some(self.script.clone());
+                for participant in self.participants() {
+                    psbt_input
+                        .bip32_derivation
+                        .insert(participant.public_key.inner, participant.key_source.clone());
+                }
+            }
+            Some(spend_info) => {
+                let leaf_hash = TapLeafHash::from_script(&self.script, LeafVersion::TapScript);
+                let control_block = spend_info
+                    .control_block(&(self.script.clone(), LeafVersion::TapScript))
+                    .ok_or(InscriptionError::TaprootCompute(
+                        "escrow leaf not found in taproot tree",
+                    ))?;
+
+                psbt_input.tap_internal_key = Some(spend_info.internal_key());
+                psbt_input.tap_merkle_root = spend_info.merkle_root();
+                psbt_input
+                    .tap_scripts
+                    .insert(control_block, (self.script.clone(), LeafVersion::TapScript));
+                for participant in self.participants() {
+                    psbt_input.tap_key_origins.insert(
+                        participant.public_key.inner.into(),
+                        (vec![leaf_hash], participant.key_source.clone()),
+                    );
+                }
+            }
+        }
+
+        Ok(psbt)
+    }
+
+    /// Sign every input of `psbt` which the account holds a key for.
+    ///
+    /// Returns the number of signatures added.
+    pub fn sign(&self, secp: &Secp256k1<All>, psbt: &mut Psbt, account: &Account) -> Result<usize> {
+        let mut signed = match psbt.sign(&account.private_key, secp) {
+            Ok(keys) => keys.values().map(Vec::len).sum(),
+            Err((_, errors)) => return Err(SigningError::from_psbt(errors).into()),
+        };
+
+        // rust-bitcoin does not sign taproot inputs, so go through the Signer
+        let mut signer = Signer::with_keys(&account.private_key, secp, psbt.unsigned_tx.clone());
+        signed += signer.sign_psbt_tap_script(psbt)?;
+
+        Ok(signed)
+    }
+
+    /// Build the final witnesses and extract the signed transaction.
+    ///
+    /// Escrow inputs need signatures from two of the three participants,
+    /// funding inputs a single P2WPKH signature.
+    pub fn finalize(&self, mut psbt: Psbt, fee_policy: &FeePolicy) -> Result<Transaction> {
+        for (index, input) in psbt.inputs.iter_mut().enumerate() {
+            let witness = if let Some(witness_script) = &input.witness_script {
+                let keys = witness_script
+                    .instructions()
+                    .filter_map(|instruction| {
+                        instruction
+                            .ok()?
+                            .push_bytes()
+                            .map(|b| b.as_bytes().to_vec())
+                    })
+                    .filter_map(|bytes| PublicKey::from_slice(&bytes).ok())
+                    .collect::<Vec<_>>();
+                let sigs = keys
+                    .iter()
+                    .filter_map(|key| input.partial_sigs.get(key))
+                    .take(2)
+                    .collect::<Vec<_>>();
+                if sigs.len() < 2 {
+                    return Err(PsbtError::MissingEscrowSignature { index }.into());
+                }
+
+                // the leading empty element is consumed by the CHECKMULTISIG off-by-one
+                let mut witness = Witness::new();
+                witness.push([]);
+                for sig in sigs {
+                    witness.push_ecdsa_signature(sig);
+                }
+                witness.push(witness_script.as_bytes());
+                witness
+            } else if let Some((control_block, (script, _))) = input.tap_scripts.iter().next() {
+                let leaf_hash = TapLeafHash::from_script(script, LeafVersion::TapScript);
+                let keys = script
+                    .instructions()
+                    .filter_map(|instruction| {
+                        instruction
+                            .ok()?
+                            .push_bytes()
+                            .map(|b| b.as_bytes().to_vec())
+                    })
+                    .filter_map(|bytes| XOnlyPublicKey::from_slice(&bytes).ok())
+                    .collect::<Vec<_>>();
+
+                // exactly two signatures, otherwise OP_NUMEQUAL fails
+                let mut remaining = 2;
+                let mut sigs = Vec::with_capacity(keys.len());
+                for key in &keys {
+                    match input.tap_script_sigs.get(&(*key, leaf_hash)) {
+                        Some(sig) if remaining > 0 => {
+                            remaining -= 1;
+                            sigs.push(sig.to_vec());
+                        }
+                        _ => sigs.push(Vec::new()),
+                    }
+                }
+                if remaining > 0 {
+                    return Err(PsbtError::MissingEscrowSignature { index }.into());
+                }
+
+                // the first key in the script consumes the topmost stack element
+                let mut witness = Witness::new();
+                for sig in sigs.iter().rev() {
+                    witness.push(sig);
+                }
+                witness.push(script.as_bytes());
+                witness.push(control_block.serialize());
+                witness
+            } else {
+                let (pubkey, sig) = input
+                    .partial_sigs
+                    .iter()
+                    .next()
+                    .ok_or(PsbtError::UnsignedInput { index })?;
+                Witness::p2wpkh(sig, &pubkey.inner)
+            };
+
+            // Clear all the data fields as per the spec.
+            *input = bitcoin::psbt::Input {
+                witness_utxo: input.witness_utxo.take(),
+                final_script_witness: Some(witness),
+                ..Default::default()
+            };
+        }
+
+        Ok(fee_policy.extract_tx(psbt)?)
+    }
+
+    fn participants(&self) -> [&Participant; 3] {
+        [&self.sender, &self.recipient, &self.marketplace]
+    }
+}
+
+impl Participant {
+    fn from_account(secp: &Secp256k1<All>, account: &Account) -> Result<Self> {
+        let zero = ChildNumber::from_normal_idx(0)?;
+        let public_key = PublicKey::new(
+            account
+                .input_xpub
+                .derive_pub(secp, &[zero, zero])?
+                .public_key,
+        );
+        let key_source = (
+            account.private_key.fingerprint(secp),
+            account.path.extend([zero, zero]),
+        );
+
+        Ok(Self {
+            public_key,
+            key_source,
+            address: account.address.clone(),
+        })
+    }
+}
+
+fn unsigned_tx(inputs: &[TxInput], output: Vec<TxOut>) -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: inputs
+            .iter()
+            .map(|input| TxIn {
+                previous_output: OutPoint {
+                    txid: input.id,
+                    vout: input.index,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_consensus(0xffffffff),
+                witness: Witness::new(),
+            })
+            .collect(),
+        output,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr as _;
+
+    use bitcoin::Txid;
+
+    use super::*;
+    use crate::{
+        MARKETPLACE_ADDRESS_MNEMONIC, RECIPIENT_ADDRESS_MNEMONIC, SENDER_ADDRESS_MNEMONIC,
+    };
+
+    fn accounts(secp: &Secp256k1<All>) -> (Account, Account, Account) {
+        (
+            Account::from_mnemonic(secp, SENDER_ADDRESS_MNEMONIC).unwrap(),
+            Account::from_mnemonic(secp, RECIPIENT_ADDRESS_MNEMONIC).unwrap(),
+            Account::from_mnemonic(secp, MARKETPLACE_ADDRESS_MNEMONIC).unwrap(),
+        )
+    }
+
+    fn escrow_input() -> TxInput {
+        TxInput {
+            id: Txid::from_str("14a7109b642b4fca7f10cd9bee89db73770c5a2d107f6a51c6bd7625dcdc2aed")
+                .unwrap(),
+            index: 0,
+            amount: Amount::from_sat(10_000),
+        }
+    }
+
+    #[test]
+    fn test_should_release_p2wsh_escrow() {
+        let secp = Secp256k1::new();
+        let (sender, recipient, marketplace) = accounts(&secp);
+        let escrow = Escrow::new(
+            &secp,
+            EscrowKind::P2wsh,
+            &sender,
+            &recipient,
+            &marketplace,
+            Network::Testnet,
+        )
+        .unwrap();
+        assert!(escrow.address.script_pubkey().is_p2wsh());
+
+        let mut psbt = escrow
+            .spend_psbt(SpendPath::Release, &escrow_input(), Amount::from_sat(500))
+            .unwrap();
+        assert_eq!(escrow.sign(&secp, &mut psbt, &sender).unwrap(), 1);
+        assert!(escrow
+            .finalize(psbt.clone(), &FeePolicy::default())
+            .is_err());
+        assert_eq!(escrow.sign(&secp, &mut psbt, &recipient).unwrap(), 1);
+
+        let tx = escrow.finalize(psbt, &FeePolicy::default()).unwrap();
+        assert_eq!(tx.input[0].witness.len(), 4);
+        assert_eq!(
+            tx.output[0].script_pubkey,
+            recipient.address.script_pubkey()
+        );
+    }
+
+    #[test]
+    fn test_should_arbitrate_taproot_escrow() {
+        let secp = Secp256k1::new();
+        let (sender, recipient, marketplace) = accounts(&secp);
+        let escrow = Escrow::new(
+            &secp,
+            EscrowKind::Taproot,
+            &sender,
+            &recipient,
+            &marketplace,
+            Network::Testnet,
+        )
+        .unwrap();
+        assert!(escrow.address.script_pubkey().is_p2tr());
+
+        let mut psbt = escrow
+            .spend_psbt(
+                SpendPath::Arbitration(EscrowParty::Sender),
+                &escrow_input(),
+                Amount::from_sat(500),
+            )
+            .unwrap();
+        assert_eq!(escrow.sign(&secp, &mut psbt, &marketplace).unwrap(), 1);
+        assert_eq!(escrow.sign(&secp, &mut psbt, &sender).unwrap(), 1);
+        assert_eq!(escrow.sign(&secp, &mut psbt, &recipient).unwrap(), 1);
+
+        let tx = escrow.finalize(psbt, &FeePolicy::default()).unwrap();
+        let witness = &tx.input[0].witness;
+        assert_eq!(witness.len(), 5);
+        assert_eq!(
+            witness.iter().take(3).filter(|sig| sig.is_empty()).count(),
+            1
+        );
+        assert_eq!(tx.output[0].script_pubkey, sender.address.script_pubkey());
+    }
+
+    #[test]
+    fn test_should_fund_escrow() {
+        let secp = Secp256k1::new();
+        let (sender, recipient, marketplace) = accounts(&secp);
+        let escrow = Escrow::new(
+            &secp,
+            EscrowKind::P2wsh,
+            &sender,
+            &recipient,
+            &marketplace,
+            Network::Testnet,
+        )
+        .unwrap();
+
+        let mut psbt = escrow
+            .funding_psbt(
+                &secp,
+                &sender,
+                &[escrow_input()],
+                Amount::from_sat(8_000),
+                Amount::from_sat(500),
+            )
+            .unwrap();
+        assert_eq!(escrow.sign(&secp, &mut psbt, &sender).unwrap(), 1);
+
+        let tx = escrow.finalize(psbt, &FeePolicy::default()).unwrap();
+        assert_eq!(tx.output[0].script_pubkey, escrow.address.script_pubkey());
+        assert_eq!(tx.output[1].value, Amount::from_sat(1_500));
+    }
+}
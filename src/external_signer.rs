@@ -0,0 +1,287 @@
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr as _;
+
+use bitcoin::bip32::{self, Fingerprint};
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::{Network, Psbt};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::account::WatchOnlyAccount;
+use crate::error::{BackendError, Result};
+
+/// Signer living in a separate executable, driven like Bitcoin Core drives its `-signer`, e.g. HWI.
+///
+/// Every call runs `<command> --fingerprint <fingerprint> --chain <chain> <subcommand>` and reads
+/// a JSON document from its stdout, except `signtx`: it runs `<command> --stdin --fingerprint
+/// <fingerprint> --chain <chain>` and writes `signtx <base64 psbt>` on stdin.
+#[derive(Debug, Clone)]
+pub struct ExternalSigner {
+    pub command: PathBuf,
+    pub fingerprint: Fingerprint,
+    pub network: Network,
+    pub name: Option<String>,
+}
+
+/// Descriptors returned by `getdescriptors`
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignerDescriptors {
+    pub receive: Vec<String>,
+    pub internal: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EnumerateEntry {
+    fingerprint: Option<String>,
+    model: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignTxResponse {
+    psbt: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Response<T> {
+    Error { error: String },
+    Ok(T),
+}
+
+impl ExternalSigner {
+    /// List the signers reachable through `command`
+    pub fn enumerate(command: impl Into<PathBuf>, network: Network) -> Result<Vec<Self>> {
+        let command = command.into();
+        let entries: Vec<EnumerateEntry> = run(
+            &command,
+            &["--chain", network.to_core_arg(), "enumerate"],
+            None,
+        )?;
+
+        let mut signers = Vec::with_capacity(entries.len());
+        for entry in entries {
+            if let Some(error) = entry.error {
+                warn!("external signer {} error: {error}", command.display());
+                continue;
+            }
+            let Some(fingerprint) = entry.fingerprint else {
+                continue;
+            };
+            signers.push(Self {
+                command: command.clone(),
+                fingerprint: Fingerprint::from_str(&fingerprint).map_err(bip32::Error::Hex)?,
+                network,
+                name: entry.model,
+            });
+        }
+
+        Ok(signers)
+    }
+
+    /// Get the receive and change descriptors of the given BIP44 account
+    pub fn descriptors(&self, account: u32) -> Result<SignerDescriptors> {
+        let account = account.to_string();
+        self.call(&["getdescriptors", "--account", &account], None)
+    }
+
+    /// Build the watch-only account of the `wpkh` receive descriptor exposed by the signer
+    pub fn watch_only(&self, secp: &Secp256k1<All>, account: u32) -> Result<WatchOnlyAccount> {
+        let descriptors = self.descriptors(account)?;
+        let descriptor = descriptors
+            .receive
+            .iter()
+            .find(|descriptor| descriptor.starts_with("wpkh("))
+            .ok_or(BackendError::MissingDescriptor { kind: "wpkh" })?;
+
+        WatchOnlyAccount::from_descriptor(secp, descriptor)
+    }
+
+    /// Send the PSBT to the signer and merge the signatures it returns.
+    ///
+    /// Returns the number of partial signatures added.
+    pub fn sign_psbt(&self, psbt: &mut Psbt) -> Result<usize> {
+        let response: SignTxResponse = self.call(&[], Some(&format!("signtx {psbt}")))?;
+        let signed = Psbt::from_str(&response.psbt)?;
+
+        let count = |psbt: &Psbt| {
+            psbt.inputs
+                .iter()
+                .map(|input| {
+                    input.partial_sigs.len()
+                        + input.tap_script_sigs.len()
+                        + usize::from(input.tap_key_sig.is_some())
+                })
+                .sum::<usize>()
+        };
+        let before = count(psbt);
+        psbt.combine(signed)?;
+
+        Ok(count(psbt) - before)
+    }
+
+    /// Run the subcommand in `args`, or the one written to stdin after `--stdin` if `stdin` is given
+    fn call<T: DeserializeOwned>(&self, args: &[&str], stdin: Option<&str>) -> Result<T> {
+        let fingerprint = self.fingerprint.to_string();
+        let mut full_args = Vec::new();
+        if stdin.is_some() {
+            full_args.push("--stdin");
+        }
+        full_args.extend([
+            "--fingerprint",
+            &fingerprint,
+            "--chain",
+            self.network.to_core_arg(),
+        ]);
+        full_args.extend_from_slice(args);
+
+        run(&self.command, &full_args, stdin)
+    }
+}
+
+fn run<T: DeserializeOwned>(command: &Path, args: &[&str], stdin: Option<&str>) -> Result<T> {
+    debug!("running external signer {} {args:?}", command.display());
+    let io_error = |source| BackendError::Io {
+        command: command.to_path_buf(),
+        source,
+    };
+    let mut child = Command::new(command)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|source| BackendError::Spawn {
+            command: command.to_path_buf(),
+            source,
+        })?;
+
+    {
+        let mut child_stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| io_error(io::Error::other("cannot open the stdin")))?;
+        if let Some(input) = stdin {
+            writeln!(child_stdin, "{input}").map_err(io_error)?;
+        }
+    }
+
+    let output = child.wait_with_output().map_err(io_error)?;
+    if !output.status.success() {
+        return Err(BackendError::Exit {
+            command: command.to_path_buf(),
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    let response =
+        serde_json::from_slice(&output.stdout).map_err(|source| BackendError::InvalidResponse {
+            command: command.to_path_buf(),
+            source,
+        })?;
+    match response {
+        Response::Error { error } => Err(BackendError::Rejected {
+            command: command.to_path_buf(),
+            error,
+        }
+        .into()),
+        Response::Ok(response) => Ok(response),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    use bitcoin::{Amount, Txid};
+    use ord_rs::transaction::TxInput;
+
+    use super::*;
+    use crate::account::{Account, AccountUtxo};
+    use crate::taproot::{self, TaprootPayload};
+    use crate::SENDER_ADDRESS_MNEMONIC;
+
+    fn write_stub(path: &Path, fingerprint: &str, descriptor: &str, signed_psbt: &str) {
+        let script = format!(
+            r#"#!/bin/sh
+# like HWI, the command comes from argv, or from stdin with `--stdin`
+cmd=""
+for arg in "$@"; do
+  case "$arg" in
+    enumerate|getdescriptors) cmd="$arg" ;;
+    --stdin) read -r cmd psbt ;;
+  esac
+done
+case "$cmd" in
+  enumerate) echo '[{{"fingerprint": "{fingerprint}", "model": "stub"}}]' ;;
+  getdescriptors) echo '{{"receive": ["{descriptor}"], "internal": []}}' ;;
+  signtx) [ -n "$psbt" ] && echo '{{"psbt": "{signed_psbt}"}}' || echo '{{"error": "no psbt"}}' ;;
+  *) echo '{{"error": "unknown command"}}' ;;
+esac
+"#
+        );
+        std::fs::write(path, script).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_should_sign_with_external_signer() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let watch_only = sender.watch_only(&secp).unwrap();
+        let descriptor = format!(
+            "wpkh([{}/84h/0h/0h]{}/0/*)",
+            watch_only.descriptor.fingerprint, watch_only.descriptor.xpub
+        );
+
+        let (keypair, x_public_key) = taproot::generate_keypair(&secp);
+        let taproot_payload = TaprootPayload::build(
+            &secp,
+            keypair,
+            x_public_key,
+            &bitcoin::ScriptBuf::new(),
+            5_000,
+            Network::Testnet,
+        )
+        .unwrap();
+        let inputs = [AccountUtxo {
+            input: TxInput {
+                id: Txid::from_str(
+                    "14a7109b642b4fca7f10cd9bee89db73770c5a2d107f6a51c6bd7625dcdc2aed",
+                )
+                .unwrap(),
+                index: 0,
+                amount: Amount::from_sat(8_000),
+            },
+            address_index: 0,
+        }];
+        let mut psbt = watch_only
+            .commit_psbt(&secp, &inputs, &taproot_payload, Amount::from_sat(1_000))
+            .unwrap();
+        let mut signed_psbt = psbt.clone();
+        signed_psbt.sign(&sender.private_key, &secp).unwrap();
+
+        let stub = std::env::temp_dir().join(format!("psbt-signer-stub-{}", std::process::id()));
+        write_stub(
+            &stub,
+            &watch_only.descriptor.fingerprint.to_string(),
+            &descriptor,
+            &signed_psbt.to_string(),
+        );
+
+        let signers = ExternalSigner::enumerate(&stub, Network::Testnet).unwrap();
+        assert_eq!(signers.len(), 1);
+        let signer = &signers[0];
+        assert_eq!(signer.fingerprint, watch_only.descriptor.fingerprint);
+        assert_eq!(signer.watch_only(&secp, 0).unwrap().address, sender.address);
+
+        assert_eq!(signer.sign_psbt(&mut psbt).unwrap(), 1);
+        assert_eq!(psbt.inputs[0].partial_sigs.len(), 1);
+
+        std::fs::remove_file(stub).unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+use bitcoin::{Amount, FeeRate, Psbt, Transaction};
+
+use crate::error::FeeError;
+
+/// Limits checked before a transaction is extracted from a finalized PSBT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeePolicy {
+    /// Maximum absolute fee
+    pub max_fee: Option<Amount>,
+    /// Maximum fee rate
+    pub max_fee_rate: Option<FeeRate>,
+    /// Maximum fee as a percentage of the value of the spent inputs
+    pub max_inputs_percent: Option<u8>,
+    /// Extract the transaction regardless of the limits above
+    pub allow_excessive_fee: bool,
+}
+
+impl Default for FeePolicy {
+    /// Same limit as [`Psbt::extract_tx_fee_rate_limit`]
+    fn default() -> Self {
+        Self {
+            max_fee: None,
+            max_fee_rate: Some(Psbt::DEFAULT_MAX_FEE_RATE),
+            max_inputs_percent: None,
+            allow_excessive_fee: false,
+        }
+    }
+}
+
+impl FeePolicy {
+    /// A policy without any limit
+    pub fn unlimited() -> Self {
+        Self {
+            max_fee: None,
+            max_fee_rate: None,
+            max_inputs_percent: None,
+            allow_excessive_fee: false,
+        }
+    }
+
+    pub fn with_max_fee(mut self, max_fee: Amount) -> Self {
+        self.max_fee = Some(max_fee);
+        self
+    }
+
+    pub fn with_max_fee_rate(mut self, max_fee_rate: FeeRate) -> Self {
+        self.max_fee_rate = Some(max_fee_rate);
+        self
+    }
+
+    pub fn with_max_inputs_percent(mut self, max_percent: u8) -> Self {
+        self.max_inputs_percent = Some(max_percent);
+        self
+    }
+
+    /// Explicitly accept fees above the configured limits
+    pub fn allow_excessive_fee(mut self) -> Self {
+        self.allow_excessive_fee = true;
+        self
+    }
+
+    /// Check the fee of the finalized PSBT against the policy and extract the transaction
+    pub fn extract_tx(&self, psbt: Psbt) -> Result<Transaction, FeeError> {
+        let fee = psbt.fee().map_err(FeeError::PsbtFee)?;
+        let inputs = psbt
+            .inputs
+            .iter()
+            .zip(psbt.unsigned_tx.input.iter())
+            .map(|(input, txin)| {
+                input
+                    .witness_utxo
+                    .as_ref()
+                    .map(|utxo| utxo.value)
+                    .or_else(|| {
+                        input
+                            .non_witness_utxo
+                            .as_ref()
+                            .map(|tx| tx.output[txin.previous_output.vout as usize].value)
+                    })
+                    .unwrap_or(Amount::ZERO)
+            })
+            .sum::<Amount>();
+        let tx = psbt.extract_tx_unchecked_fee_rate();
+
+        if self.allow_excessive_fee {
+            if let Err(err) = self.check(fee, inputs, &tx) {
+                warn!(
+                    "extracting transaction {} above the fee policy: {err}",
+                    tx.txid()
+                );
+            }
+            return Ok(tx);
+        }
+
+        self.check(fee, inputs, &tx)?;
+        Ok(tx)
+    }
+
+    fn check(&self, fee: Amount, inputs: Amount, tx: &Transaction) -> Result<(), FeeError> {
+        if let Some(max_fee) = self.max_fee {
+            if fee > max_fee {
+                return Err(FeeError::AbsoluteFee { fee, max_fee });
+            }
+        }
+
+        if let Some(max_fee_rate) = self.max_fee_rate {
+            let fee_rate = fee / tx.weight();
+            if fee_rate > max_fee_rate {
+                return Err(FeeError::FeeRate {
+                    fee,
+                    fee_rate,
+                    max_fee_rate,
+                });
+            }
+        }
+
+        if let Some(max_percent) = self.max_inputs_percent {
+            if fee.to_sat() * 100 > inputs.to_sat() * u64::from(max_percent) {
+                return Err(FeeError::InputsPercentage {
+                    fee,
+                    inputs,
+                    max_percent,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version;
+    use bitcoin::{OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Witness};
+
+    use super::*;
+
+    fn finalized_psbt(input: u64, output: u64) -> Psbt {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_consensus(0xffffffff),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(output),
+                script_pubkey: ScriptBuf::new_op_return([0; 4]),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(input),
+            script_pubkey: ScriptBuf::new(),
+        });
+        psbt.inputs[0].final_script_witness = Some(Witness::from_slice(&[[0u8; 72]]));
+        psbt
+    }
+
+    #[test]
+    fn test_should_enforce_fee_policy() {
+        let psbt = finalized_psbt(100_000, 90_000);
+
+        assert!(FeePolicy::default().extract_tx(psbt.clone()).is_ok());
+
+        let err = FeePolicy::unlimited()
+            .with_max_fee(Amount::from_sat(5_000))
+            .extract_tx(psbt.clone())
+            .unwrap_err();
+        assert!(matches!(err, FeeError::AbsoluteFee { .. }));
+        assert_eq!(err.fee(), Some(Amount::from_sat(10_000)));
+
+        let err = FeePolicy::unlimited()
+            .with_max_fee_rate(FeeRate::from_sat_per_vb_unchecked(10))
+            .extract_tx(psbt.clone())
+            .unwrap_err();
+        assert!(matches!(err, FeeError::FeeRate { .. }));
+
+        let err = FeePolicy::unlimited()
+            .with_max_inputs_percent(5)
+            .extract_tx(psbt.clone())
+            .unwrap_err();
+        assert!(matches!(err, FeeError::InputsPercentage { .. }));
+        assert!(FeePolicy::unlimited()
+            .with_max_inputs_percent(10)
+            .extract_tx(psbt.clone())
+            .is_ok());
+
+        assert!(FeePolicy::unlimited()
+            .with_max_fee(Amount::from_sat(5_000))
+            .allow_excessive_fee()
+            .extract_tx(psbt)
+            .is_ok());
+    }
+}
@@ -0,0 +1,488 @@
+use std::fmt;
+use std::str::FromStr as _;
+
+use bitcoin::consensus::encode;
+use bitcoin::opcodes::all::{OP_PUSHNUM_1, OP_PUSHNUM_16};
+use bitcoin::psbt::Input;
+use bitcoin::script::Instruction;
+use bitcoin::{Address, Network, Psbt, Script, Transaction, TxOut, Witness};
+use serde::Serialize;
+
+use crate::error::{PsbtError, Result};
+
+/// Human-readable summary of a PSBT or transaction
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub kind: DocumentKind,
+    pub txid: String,
+    pub version: i32,
+    pub lock_time: u32,
+    pub inputs: Vec<InputReport>,
+    pub outputs: Vec<OutputReport>,
+    /// Fee in sats, if every prevout is known
+    pub fee: Option<u64>,
+    /// Fee rate in sat/vB, if the fee is known
+    pub fee_rate: Option<f64>,
+    pub vsize: u64,
+    /// Whether `vsize` is an estimate, because some witnesses are still missing
+    pub vsize_estimated: bool,
+    pub ready_to_finalize: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentKind {
+    Psbt,
+    Transaction,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InputReport {
+    pub index: usize,
+    pub outpoint: String,
+    pub prevout_value: Option<u64>,
+    pub script_type: Option<ScriptType>,
+    pub status: SignatureStatus,
+    pub sighash: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutputReport {
+    pub index: usize,
+    pub address: Option<String>,
+    pub script_type: ScriptType,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptType {
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    OpReturn,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "state", content = "signatures")]
+pub enum SignatureStatus {
+    Unsigned,
+    PartiallySigned(usize),
+    Signed(usize),
+    Finalized,
+}
+
+/// Decode a PSBT (base64 or hex) or a raw transaction (hex) and inspect it
+pub fn decode(document: &str, network: Network) -> Result<Report> {
+    let document = document.trim();
+    if let Ok(psbt) = Psbt::from_str(document) {
+        return Ok(inspect_psbt(&psbt, network));
+    }
+
+    let bytes = hex::decode(document).map_err(|_| PsbtError::InvalidDocument)?;
+    if let Ok(psbt) = Psbt::deserialize(&bytes) {
+        return Ok(inspect_psbt(&psbt, network));
+    }
+    let tx: Transaction = encode::deserialize(&bytes)?;
+
+    Ok(inspect_tx(&tx, network))
+}
+
+/// Inspect a PSBT
+pub fn inspect_psbt(psbt: &Psbt, network: Network) -> Report {
+    let tx = &psbt.unsigned_tx;
+
+    let inputs = psbt
+        .inputs
+        .iter()
+        .zip(&tx.input)
+        .enumerate()
+        .map(|(index, (input, tx_in))| {
+            let prevout = spent_utxo(psbt, index);
+            InputReport {
+                index,
+                outpoint: tx_in.previous_output.to_string(),
+                prevout_value: prevout.map(|utxo| utxo.value.to_sat()),
+                script_type: prevout.map(|utxo| ScriptType::of(&utxo.script_pubkey)),
+                status: signature_status(input, prevout),
+                sighash: sighash(input),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // fill in the witnesses we know or can estimate, to get the final size
+    let mut vsize_estimated = false;
+    let mut final_tx = tx.clone();
+    for (index, (tx_in, input)) in final_tx.input.iter_mut().zip(&psbt.inputs).enumerate() {
+        if let Some(script_sig) = &input.final_script_sig {
+            tx_in.script_sig = script_sig.clone();
+        }
+        if let Some(witness) = &input.final_script_witness {
+            tx_in.witness = witness.clone();
+        } else if input.final_script_sig.is_none() {
+            vsize_estimated = true;
+            tx_in.witness = estimated_witness(input, spent_utxo(psbt, index));
+        }
+    }
+    let vsize = final_tx.vsize() as u64;
+    let fee = psbt.fee().ok().map(|fee| fee.to_sat());
+
+    Report {
+        kind: DocumentKind::Psbt,
+        txid: tx.txid().to_string(),
+        version: tx.version.0,
+        lock_time: tx.lock_time.to_consensus_u32(),
+        ready_to_finalize: inputs.iter().all(|input| {
+            matches!(
+                input.status,
+                SignatureStatus::Signed(_) | SignatureStatus::Finalized
+            )
+        }),
+        inputs,
+        outputs: outputs(&tx.output, network),
+        fee,
+        fee_rate: fee.map(|fee| fee as f64 / vsize as f64),
+        vsize,
+        vsize_estimated,
+    }
+}
+
+/// Inspect a raw transaction. Prevouts are unknown, so is the fee.
+pub fn inspect_tx(tx: &Transaction, network: Network) -> Report {
+    let inputs = tx
+        .input
+        .iter()
+        .enumerate()
+        .map(|(index, tx_in)| InputReport {
+            index,
+            outpoint: tx_in.previous_output.to_string(),
+            prevout_value: None,
+            script_type: None,
+            status: if tx_in.witness.is_empty() && tx_in.script_sig.is_empty() {
+                SignatureStatus::Unsigned
+            } else {
+                SignatureStatus::Finalized
+            },
+            sighash: None,
+        })
+        .collect::<Vec<_>>();
+
+    Report {
+        kind: DocumentKind::Transaction,
+        txid: tx.txid().to_string(),
+        version: tx.version.0,
+        lock_time: tx.lock_time.to_consensus_u32(),
+        ready_to_finalize: inputs
+            .iter()
+            .all(|input| input.status == SignatureStatus::Finalized),
+        inputs,
+        outputs: outputs(&tx.output, network),
+        fee: None,
+        fee_rate: None,
+        vsize: tx.vsize() as u64,
+        vsize_estimated: false,
+    }
+}
+
+impl ScriptType {
+    pub fn of(script: &Script) -> Self {
+        if script.is_p2pkh() {
+            Self::P2pkh
+        } else if script.is_p2sh() {
+            Self::P2sh
+        } else if script.is_p2wpkh() {
+            Self::P2wpkh
+        } else if script.is_p2wsh() {
+            Self::P2wsh
+        } else if script.is_p2tr() {
+            Self::P2tr
+        } else if script.is_op_return() {
+            Self::OpReturn
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+impl fmt::Display for ScriptType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::P2pkh => "p2pkh",
+            Self::P2sh => "p2sh",
+            Self::P2wpkh => "p2wpkh",
+            Self::P2wsh => "p2wsh",
+            Self::P2tr => "p2tr",
+            Self::OpReturn => "op_return",
+            Self::Unknown => "unknown",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsigned => write!(f, "unsigned"),
+            Self::PartiallySigned(n) => write!(f, "partially signed ({n} sigs)"),
+            Self::Signed(n) => write!(f, "signed ({n} sigs)"),
+            Self::Finalized => write!(f, "finalized"),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            DocumentKind::Psbt => "psbt",
+            DocumentKind::Transaction => "transaction",
+        };
+        writeln!(f, "{kind} {}", self.txid)?;
+        writeln!(f, "version: {}, locktime: {}", self.version, self.lock_time)?;
+
+        writeln!(f, "inputs:")?;
+        for input in &self.inputs {
+            write!(f, "  #{} {}", input.index, input.outpoint)?;
+            match input.prevout_value {
+                Some(value) => write!(f, " {value} sat")?,
+                None => write!(f, " ? sat")?,
+            }
+            if let Some(script_type) = input.script_type {
+                write!(f, " {script_type}")?;
+            }
+            write!(f, " {}", input.status)?;
+            if let Some(sighash) = &input.sighash {
+                write!(f, " {sighash}")?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f, "outputs:")?;
+        for output in &self.outputs {
+            writeln!(
+                f,
+                "  #{} {} {} sat {}",
+                output.index,
+                output.address.as_deref().unwrap_or("-"),
+                output.amount,
+                output.script_type
+            )?;
+        }
+
+        match (self.fee, self.fee_rate) {
+            (Some(fee), Some(fee_rate)) => write!(f, "fee: {fee} sat ({fee_rate:.2} sat/vB)")?,
+            _ => write!(f, "fee: unknown")?,
+        }
+        let estimated = if self.vsize_estimated {
+            " (estimated)"
+        } else {
+            ""
+        };
+        writeln!(f, ", vsize: {} vB{estimated}", self.vsize)?;
+        write!(
+            f,
+            "ready to finalize: {}",
+            if self.ready_to_finalize { "yes" } else { "no" }
+        )
+    }
+}
+
+fn outputs(outputs: &[TxOut], network: Network) -> Vec<OutputReport> {
+    outputs
+        .iter()
+        .enumerate()
+        .map(|(index, output)| OutputReport {
+            index,
+            address: Address::from_script(&output.script_pubkey, network)
+                .ok()
+                .map(|address| address.to_string()),
+            script_type: ScriptType::of(&output.script_pubkey),
+            amount: output.value.to_sat(),
+        })
+        .collect()
+}
+
+pub(crate) fn spent_utxo(psbt: &Psbt, index: usize) -> Option<&TxOut> {
+    let input = &psbt.inputs[index];
+    input.witness_utxo.as_ref().or_else(|| {
+        let vout = psbt.unsigned_tx.input[index].previous_output.vout as usize;
+        input.non_witness_utxo.as_ref()?.output.get(vout)
+    })
+}
+
+fn signature_status(input: &Input, prevout: Option<&TxOut>) -> SignatureStatus {
+    if input.final_script_witness.is_some() || input.final_script_sig.is_some() {
+        return SignatureStatus::Finalized;
+    }
+
+    let sigs =
+        input.partial_sigs.len() + input.tap_script_sigs.len() + input.tap_key_sig.map_or(0, |_| 1);
+    if sigs == 0 {
+        return SignatureStatus::Unsigned;
+    }
+
+    let required = match (&input.witness_script, prevout) {
+        (Some(witness_script), _) => multisig_threshold(witness_script).unwrap_or(1),
+        (None, Some(prevout)) if prevout.script_pubkey.is_p2tr() && input.tap_key_sig.is_none() => {
+            // script path: assume every key of the leaf has to sign unless it is a threshold
+            input
+                .tap_scripts
+                .values()
+                .next()
+                .map_or(1, |(script, _)| checksigadd_threshold(script).unwrap_or(1))
+        }
+        _ => 1,
+    };
+
+    if sigs >= required {
+        SignatureStatus::Signed(sigs)
+    } else {
+        SignatureStatus::PartiallySigned(sigs)
+    }
+}
+
+fn sighash(input: &Input) -> Option<String> {
+    if let Some(sighash_type) = input.sighash_type {
+        return Some(sighash_type.to_string());
+    }
+
+    input
+        .partial_sigs
+        .values()
+        .map(|sig| sig.hash_ty.to_string())
+        .chain(input.tap_key_sig.iter().map(|sig| sig.hash_ty.to_string()))
+        .chain(
+            input
+                .tap_script_sigs
+                .values()
+                .map(|sig| sig.hash_ty.to_string()),
+        )
+        .next()
+}
+
+/// `m` of an `OP_m <keys> OP_n OP_CHECKMULTISIG` script
+pub(crate) fn multisig_threshold(script: &Script) -> Option<usize> {
+    match script.instructions().next()?.ok()? {
+        Instruction::Op(op)
+            if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) =>
+        {
+            Some((op.to_u8() - OP_PUSHNUM_1.to_u8() + 1) as usize)
+        }
+        _ => None,
+    }
+}
+
+/// `m` of a `<key> OP_CHECKSIG <key> OP_CHECKSIGADD ... OP_m OP_NUMEQUAL` script
+pub(crate) fn checksigadd_threshold(script: &Script) -> Option<usize> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    match instructions.iter().rev().nth(1)? {
+        Instruction::Op(op)
+            if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) =>
+        {
+            Some((op.to_u8() - OP_PUSHNUM_1.to_u8() + 1) as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Dummy witness of the expected size, for the inputs which are not finalized yet
+fn estimated_witness(input: &Input, prevout: Option<&TxOut>) -> Witness {
+    let Some(prevout) = prevout else {
+        return Witness::new();
+    };
+
+    let mut witness = Witness::new();
+    if prevout.script_pubkey.is_p2wpkh() {
+        witness.push([0; 72]);
+        witness.push([0; 33]);
+    } else if let Some(witness_script) = &input.witness_script {
+        witness.push([]);
+        for _ in 0..multisig_threshold(witness_script).unwrap_or(1) {
+            witness.push([0; 72]);
+        }
+        witness.push(witness_script.as_bytes());
+    } else if let Some((control_block, (script, _))) = input.tap_scripts.iter().next() {
+        for _ in 0..checksigadd_threshold(script).unwrap_or(1) {
+            witness.push([0; 64]);
+        }
+        witness.push(script.as_bytes());
+        witness.push(control_block.serialize());
+    } else if prevout.script_pubkey.is_p2tr() {
+        witness.push([0; 64]);
+    }
+
+    witness
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::Amount;
+    use ord_rs::transaction::TxInput;
+
+    use super::*;
+    use crate::fee::FeePolicy;
+    use crate::marketplace::{complete_listing, finalize_purchase, Listing};
+    use crate::{Account, RECIPIENT_ADDRESS_MNEMONIC, SENDER_ADDRESS_MNEMONIC};
+
+    #[test]
+    fn test_should_inspect_psbt_and_transaction() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let seller = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let buyer = Account::from_mnemonic(&secp, RECIPIENT_ADDRESS_MNEMONIC).unwrap();
+        let utxo = |index, amount| TxInput {
+            id: bitcoin::Txid::from_str(
+                "14a7109b642b4fca7f10cd9bee89db73770c5a2d107f6a51c6bd7625dcdc2aed",
+            )
+            .unwrap(),
+            index,
+            amount: Amount::from_sat(amount),
+        };
+
+        let listing =
+            Listing::new(&secp, &seller, &utxo(0, 546), Amount::from_sat(10_000)).unwrap();
+        let report = decode(&listing.export(), Network::Testnet).unwrap();
+        assert_eq!(report.kind, DocumentKind::Psbt);
+        assert_eq!(report.inputs[0].status, SignatureStatus::Finalized);
+        assert_eq!(report.inputs[0].script_type, Some(ScriptType::P2wpkh));
+        assert_eq!(report.outputs[0].address, Some(seller.address.to_string()));
+        assert!(report.ready_to_finalize);
+
+        let mut psbt = complete_listing(
+            &secp,
+            &listing,
+            &buyer,
+            &[utxo(1, 600), utxo(2, 600)],
+            &[utxo(3, 20_000)],
+            Amount::from_sat(1_000),
+        )
+        .unwrap();
+        psbt.inputs[3].partial_sigs.clear();
+        let report = inspect_psbt(&psbt, Network::Testnet);
+        assert_eq!(report.inputs[0].status, SignatureStatus::Signed(1));
+        assert_eq!(report.inputs[0].sighash.as_deref(), Some("SIGHASH_ALL"));
+        assert_eq!(report.inputs[3].status, SignatureStatus::Unsigned);
+        assert_eq!(report.fee, Some(1_000));
+        assert!(report.vsize_estimated);
+        assert!(!report.ready_to_finalize);
+
+        let psbt = complete_listing(
+            &secp,
+            &listing,
+            &buyer,
+            &[utxo(1, 600), utxo(2, 600)],
+            &[utxo(3, 20_000)],
+            Amount::from_sat(1_000),
+        )
+        .unwrap();
+        let estimated = inspect_psbt(&psbt, Network::Testnet).vsize;
+        let tx = finalize_purchase(psbt, &FeePolicy::default()).unwrap();
+        let report = decode(&encode::serialize_hex(&tx), Network::Testnet).unwrap();
+        assert_eq!(report.kind, DocumentKind::Transaction);
+        assert_eq!(report.fee, None);
+        assert!(report.ready_to_finalize);
+        assert!(report.vsize.abs_diff(estimated) <= 2);
+        serde_json::to_string(&report).unwrap();
+    }
+}
@@ -0,0 +1,1498 @@
+use std::fmt;
+
+use bitcoin::constants::MAX_SCRIPT_ELEMENT_SIZE;
+use bitcoin::hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash as _};
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::{Class, ClassifyContext, Opcode};
+use bitcoin::script::{self, Instruction, PushBytesBuf};
+use bitcoin::secp256k1::{Message, Secp256k1, Verification};
+use bitcoin::sighash::{Annex, Prevouts, SighashCache};
+use bitcoin::taproot::{ControlBlock, LeafVersion, TapLeafHash, TAPROOT_ANNEX_PREFIX};
+use bitcoin::{
+    ecdsa, taproot, Psbt, PubkeyHash, PublicKey, Script, ScriptBuf, Sequence, Transaction, TxOut,
+    XOnlyPublicKey,
+};
+
+use crate::error::{PsbtError, Result, SigningError};
+use crate::inspect::spent_utxo;
+
+/// Lock time values below this are block heights, above are timestamps
+const LOCKTIME_THRESHOLD: i64 = 500_000_000;
+/// BIP68 flag disabling the relative lock time of an input
+const SEQUENCE_DISABLE_FLAG: i64 = 1 << 31;
+/// BIP68 flag selecting a time based relative lock time
+const SEQUENCE_TYPE_FLAG: i64 = 1 << 22;
+const SEQUENCE_MASK: i64 = SEQUENCE_TYPE_FLAG | 0xffff;
+/// Maximum number of keys of an OP_CHECKMULTISIG
+const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+/// Code separator position committed to when the script has no OP_CODESEPARATOR
+const NO_CODESEPARATOR: u32 = 0xffff_ffff;
+/// Maximum size of a legacy or witness v0 script
+const MAX_SCRIPT_SIZE: usize = 10_000;
+/// Maximum number of non push opcodes of a legacy or witness v0 script
+const MAX_OPS_PER_SCRIPT: usize = 201;
+/// Maximum number of elements of the stack and the alt stack together
+const MAX_STACK_SIZE: usize = 1_000;
+
+/// Reason an input script fails, named after the bitcoind script errors
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ScriptError {
+    #[error("no prevout for the input")]
+    MissingPrevout,
+    #[error("unsupported script pubkey {0}")]
+    UnsupportedScriptPubkey(ScriptBuf),
+    #[error("script sig of a native segwit input must be empty")]
+    WitnessMalleated,
+    #[error("script sig of a nested segwit input must only push the redeem script")]
+    WitnessMalleatedP2sh,
+    #[error("legacy input has a witness")]
+    WitnessUnexpected,
+    #[error("script sig must only push data")]
+    SigPushOnly,
+    #[error("witness does not match the witness program")]
+    WitnessProgramMismatch,
+    #[error("invalid witness: {0}")]
+    InvalidWitness(&'static str),
+    #[error("control block does not commit to the leaf script and output key")]
+    InvalidControlBlock,
+    #[error("malformed script")]
+    BadScript,
+    #[error("script exceeds {MAX_SCRIPT_SIZE} bytes")]
+    ScriptSize,
+    #[error("push exceeds {MAX_SCRIPT_ELEMENT_SIZE} bytes")]
+    PushSize,
+    #[error("script exceeds {MAX_OPS_PER_SCRIPT} opcodes")]
+    OpCount,
+    #[error("stack exceeds {MAX_STACK_SIZE} elements")]
+    StackSize,
+    #[error("data push is not minimally encoded")]
+    MinimalData,
+    #[error("disabled or reserved opcode {0}")]
+    BadOpcode(Opcode),
+    #[error("opcode {0} is not supported by the interpreter")]
+    UnsupportedOpcode(Opcode),
+    #[error("unbalanced conditional")]
+    UnbalancedConditional,
+    #[error("OP_IF argument must be empty or 1")]
+    MinimalIf,
+    #[error("stack underflow")]
+    StackUnderflow,
+    #[error("invalid script number")]
+    InvalidNumber,
+    #[error("{0} failed")]
+    Verify(Opcode),
+    #[error("invalid signature encoding: {0}")]
+    SignatureEncoding(String),
+    #[error("invalid public key")]
+    PublicKeyType,
+    #[error("signature does not verify")]
+    InvalidSignature,
+    #[error("signature check failed with a non empty signature")]
+    NullFail,
+    #[error("OP_CHECKMULTISIG dummy element must be empty")]
+    NullDummy,
+    #[error("lock time not satisfied")]
+    UnsatisfiedLocktime,
+    #[error("script finished with false on the stack")]
+    EvalFalse,
+    #[error("script must leave exactly one element on the stack")]
+    CleanStack,
+    #[error("cannot compute sighash: {0}")]
+    Sighash(String),
+}
+
+/// Result of running the scripts of every input of a signed transaction
+#[derive(Debug)]
+pub struct ScriptVerificationReport {
+    pub inputs: Vec<InputScriptResult>,
+}
+
+#[derive(Debug)]
+pub struct InputScriptResult {
+    pub index: usize,
+    /// `None` if the input scripts succeed
+    pub error: Option<ScriptError>,
+}
+
+impl ScriptVerificationReport {
+    /// Whether every input script succeeds
+    pub fn is_valid(&self) -> bool {
+        self.inputs.iter().all(|input| input.error.is_none())
+    }
+
+    /// Fail with the report if some input script fails
+    pub fn ensure_valid(self) -> Result<Self> {
+        if self.is_valid() {
+            Ok(self)
+        } else {
+            Err(SigningError::ScriptVerification(self).into())
+        }
+    }
+}
+
+impl fmt::Display for ScriptVerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (position, input) in self.inputs.iter().enumerate() {
+            if position > 0 {
+                writeln!(f)?;
+            }
+            match &input.error {
+                None => write!(f, "input {}: ok", input.index)?,
+                Some(error) => write!(f, "input {}: {error}", input.index)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Run the scripts of every input of `tx` against `prevouts`, the outputs it spends in input order
+pub fn verify_transaction<C: Verification>(
+    secp: &Secp256k1<C>,
+    tx: &Transaction,
+    prevouts: &[TxOut],
+) -> ScriptVerificationReport {
+    let inputs = (0..tx.input.len())
+        .map(|index| {
+            let checker = Checker {
+                secp,
+                tx,
+                index,
+                prevouts,
+            };
+            InputScriptResult {
+                index,
+                error: checker.verify_input().err(),
+            }
+        })
+        .collect();
+
+    ScriptVerificationReport { inputs }
+}
+
+/// Outputs spent by the inputs of `psbt`, as taken by [`verify_transaction`]
+pub fn psbt_prevouts(psbt: &Psbt) -> Result<Vec<TxOut>> {
+    (0..psbt.inputs.len())
+        .map(|index| {
+            spent_utxo(psbt, index)
+                .cloned()
+                .ok_or_else(|| PsbtError::MissingUtxo { index }.into())
+        })
+        .collect()
+}
+
+/// Which signature hashing and opcode rules apply to the executed script
+#[derive(Clone, Copy)]
+enum SigVersion<'s> {
+    Base {
+        script_code: &'s Script,
+    },
+    WitnessV0 {
+        script_code: &'s Script,
+    },
+    Tapscript {
+        leaf_hash: TapLeafHash,
+        annex: Option<&'s [u8]>,
+        /// Opcode position of the last executed OP_CODESEPARATOR
+        code_separator: u32,
+    },
+}
+
+/// Checks the signatures and lock times of one input
+struct Checker<'a, C: Verification> {
+    secp: &'a Secp256k1<C>,
+    tx: &'a Transaction,
+    index: usize,
+    prevouts: &'a [TxOut],
+}
+
+type Stack = Vec<Vec<u8>>;
+
+impl<C: Verification> Checker<'_, C> {
+    fn verify_input(&self) -> Result<(), ScriptError> {
+        if self.prevouts.len() != self.tx.input.len() {
+            return Err(ScriptError::MissingPrevout);
+        }
+        let script_pubkey = &self.prevouts[self.index].script_pubkey;
+        let tx_in = &self.tx.input[self.index];
+        if script_pubkey.is_witness_program() {
+            if !tx_in.script_sig.is_empty() {
+                return Err(ScriptError::WitnessMalleated);
+            }
+            return self.verify_witness_program(script_pubkey);
+        }
+
+        let mut stack = push_only(&tx_in.script_sig)?;
+        if script_pubkey.is_p2sh() {
+            let redeem_script = ScriptBuf::from(pop(&mut stack)?);
+            if redeem_script.script_hash().as_byte_array() != &script_pubkey.as_bytes()[2..22] {
+                return Err(ScriptError::EvalFalse);
+            }
+            if redeem_script.is_witness_program() {
+                if !stack.is_empty() {
+                    return Err(ScriptError::WitnessMalleatedP2sh);
+                }
+                return self.verify_witness_program(&redeem_script);
+            }
+            if !tx_in.witness.is_empty() {
+                return Err(ScriptError::WitnessUnexpected);
+            }
+            self.execute(
+                &redeem_script,
+                stack,
+                SigVersion::Base {
+                    script_code: &redeem_script,
+                },
+            )
+        } else if script_pubkey.is_p2pkh() {
+            if !tx_in.witness.is_empty() {
+                return Err(ScriptError::WitnessUnexpected);
+            }
+            self.execute(
+                script_pubkey,
+                stack,
+                SigVersion::Base {
+                    script_code: script_pubkey,
+                },
+            )
+        } else {
+            Err(ScriptError::UnsupportedScriptPubkey(script_pubkey.clone()))
+        }
+    }
+
+    /// Check the witness of the input against `program_script`, native or nested in P2SH
+    fn verify_witness_program(&self, program_script: &Script) -> Result<(), ScriptError> {
+        let program = &program_script.as_bytes()[2..];
+        let mut witness = self.tx.input[self.index].witness.to_vec();
+        if program_script.is_p2wpkh() {
+            if witness.len() != 2 {
+                return Err(ScriptError::InvalidWitness(
+                    "p2wpkh witness must be a signature and a public key",
+                ));
+            }
+            let pubkey_hash =
+                PubkeyHash::from_slice(program).map_err(|_| ScriptError::BadScript)?;
+            let script_code = ScriptBuf::new_p2pkh(&pubkey_hash);
+            self.execute(
+                &script_code,
+                witness,
+                SigVersion::WitnessV0 {
+                    script_code: &script_code,
+                },
+            )
+        } else if program_script.is_p2wsh() {
+            let witness_script = ScriptBuf::from(
+                witness
+                    .pop()
+                    .ok_or(ScriptError::InvalidWitness("empty witness"))?,
+            );
+            if witness_script.wscript_hash().as_byte_array() != program {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+            self.execute(
+                &witness_script,
+                witness,
+                SigVersion::WitnessV0 {
+                    script_code: &witness_script,
+                },
+            )
+        } else if program_script.is_p2tr() {
+            let output_key =
+                XOnlyPublicKey::from_slice(program).map_err(|_| ScriptError::PublicKeyType)?;
+            self.verify_taproot(&output_key, witness)
+        } else {
+            Err(ScriptError::UnsupportedScriptPubkey(
+                program_script.to_owned(),
+            ))
+        }
+    }
+
+    fn verify_taproot(
+        &self,
+        output_key: &XOnlyPublicKey,
+        mut witness: Stack,
+    ) -> Result<(), ScriptError> {
+        let annex = match witness.last() {
+            Some(last) if witness.len() >= 2 && last.first() == Some(&TAPROOT_ANNEX_PREFIX) => {
+                witness.pop()
+            }
+            _ => None,
+        };
+
+        match witness.len() {
+            0 => Err(ScriptError::InvalidWitness("empty witness")),
+            // key path
+            1 => {
+                if self.check_schnorr(&witness[0], output_key, annex.as_deref(), None)? {
+                    Ok(())
+                } else {
+                    Err(ScriptError::InvalidSignature)
+                }
+            }
+            // script path
+            _ => {
+                let control_block = witness.pop().unwrap_or_default();
+                let script = ScriptBuf::from(witness.pop().unwrap_or_default());
+                let control_block = ControlBlock::decode(&control_block)
+                    .map_err(|_| ScriptError::InvalidControlBlock)?;
+                if !control_block.verify_taproot_commitment(self.secp, *output_key, &script) {
+                    return Err(ScriptError::InvalidControlBlock);
+                }
+                // BIP342: unknown leaf versions are reserved for upgrades and always succeed
+                if control_block.leaf_version != LeafVersion::TapScript {
+                    return Ok(());
+                }
+
+                let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+                self.execute(
+                    &script,
+                    witness,
+                    SigVersion::Tapscript {
+                        leaf_hash,
+                        annex: annex.as_deref(),
+                        code_separator: NO_CODESEPARATOR,
+                    },
+                )
+            }
+        }
+    }
+
+    /// Run `script` on the initial `stack` and require a single true element left
+    fn execute<'s>(
+        &self,
+        script: &'s Script,
+        mut stack: Stack,
+        mut sig_version: SigVersion<'s>,
+    ) -> Result<(), ScriptError> {
+        let is_tapscript = matches!(sig_version, SigVersion::Tapscript { .. });
+        let context = if is_tapscript {
+            ClassifyContext::TapScript
+        } else {
+            ClassifyContext::Legacy
+        };
+        let instructions = script
+            .instruction_indices()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ScriptError::BadScript)?;
+
+        // BIP342: any OP_SUCCESS makes the script succeed before execution
+        let has_success_op = instructions.iter().any(|(_, instruction)| {
+            matches!(instruction, Instruction::Op(op) if op.classify(context) == Class::SuccessOp)
+        });
+        if has_success_op {
+            return Ok(());
+        }
+
+        if !is_tapscript && script.len() > MAX_SCRIPT_SIZE {
+            return Err(ScriptError::ScriptSize);
+        }
+        // the witness stack is bound by the same limits as the pushes
+        if !matches!(sig_version, SigVersion::Base { .. }) {
+            if stack
+                .iter()
+                .any(|item| item.len() > MAX_SCRIPT_ELEMENT_SIZE)
+            {
+                return Err(ScriptError::PushSize);
+            }
+            if stack.len() > MAX_STACK_SIZE {
+                return Err(ScriptError::StackSize);
+            }
+        }
+
+        let mut alt_stack = Stack::new();
+        let mut conditions: Vec<bool> = Vec::new();
+        let mut op_count = 0;
+        for (position, (offset, instruction)) in instructions.into_iter().enumerate() {
+            let executing = conditions.iter().all(|condition| *condition);
+            let op = match instruction {
+                Instruction::PushBytes(bytes) => {
+                    if bytes.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                        return Err(ScriptError::PushSize);
+                    }
+                    if executing {
+                        check_minimal_push(script.as_bytes()[offset], bytes.as_bytes())?;
+                        stack.push(bytes.as_bytes().to_vec());
+                    }
+                    check_stack_size(&stack, &alt_stack)?;
+                    continue;
+                }
+                Instruction::Op(op) => op,
+            };
+            // push number opcodes are free, tapscript has no opcode limit
+            if !is_tapscript && op.to_u8() > OP_PUSHNUM_16.to_u8() {
+                op_count += 1;
+                if op_count > MAX_OPS_PER_SCRIPT {
+                    return Err(ScriptError::OpCount);
+                }
+            }
+
+            match op.classify(context) {
+                Class::IllegalOp => return Err(ScriptError::BadOpcode(op)),
+                _ if !executing && !matches!(op, OP_IF | OP_NOTIF | OP_ELSE | OP_ENDIF) => {}
+                Class::ReturnOp => return Err(ScriptError::BadOpcode(op)),
+                // the lock time opcodes are redefined NOPs, so they must be matched first
+                _ if op == OP_CLTV => self.check_lock_time(read_num(peek(&stack, 0)?, 5)?)?,
+                _ if op == OP_CSV => self.check_sequence(read_num(peek(&stack, 0)?, 5)?)?,
+                Class::NoOp | Class::SuccessOp | Class::PushBytes(_) => {}
+                Class::PushNum(n) => stack.push(encode_num(n.into())),
+                Class::Ordinary(_) => match op {
+                    OP_IF | OP_NOTIF => {
+                        let mut condition = false;
+                        if executing {
+                            let top = pop(&mut stack)?;
+                            // minimal if is only enforced in segwit scripts
+                            let minimal = top.is_empty() || top == [1];
+                            if !minimal && !matches!(sig_version, SigVersion::Base { .. }) {
+                                return Err(ScriptError::MinimalIf);
+                            }
+                            condition = script::read_scriptbool(&top) == (op == OP_IF);
+                        }
+                        conditions.push(condition);
+                    }
+                    OP_ELSE => {
+                        let condition = conditions
+                            .last_mut()
+                            .ok_or(ScriptError::UnbalancedConditional)?;
+                        *condition = !*condition;
+                    }
+                    OP_ENDIF => {
+                        conditions.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                    }
+                    OP_VERIFY => verify(op, script::read_scriptbool(&pop(&mut stack)?))?,
+                    OP_TOALTSTACK => alt_stack.push(pop(&mut stack)?),
+                    OP_FROMALTSTACK => stack.push(pop(&mut alt_stack)?),
+                    OP_DROP => {
+                        pop(&mut stack)?;
+                    }
+                    OP_2DROP => {
+                        pop(&mut stack)?;
+                        pop(&mut stack)?;
+                    }
+                    OP_DUP => stack.push(peek(&stack, 0)?.clone()),
+                    OP_2DUP | OP_3DUP => {
+                        let count = if op == OP_2DUP { 2 } else { 3 };
+                        for _ in 0..count {
+                            stack.push(peek(&stack, count - 1)?.clone());
+                        }
+                    }
+                    OP_2OVER => {
+                        for _ in 0..2 {
+                            stack.push(peek(&stack, 3)?.clone());
+                        }
+                    }
+                    OP_ROT | OP_2ROT => {
+                        // move the third item, or pair of items, to the top
+                        let (depth, count) = if op == OP_ROT { (2, 1) } else { (5, 2) };
+                        peek(&stack, depth)?;
+                        let position = stack.len() - depth - 1;
+                        let moved = stack.drain(position..position + count).collect::<Vec<_>>();
+                        stack.extend(moved);
+                    }
+                    OP_2SWAP => {
+                        peek(&stack, 3)?;
+                        let position = stack.len() - 4;
+                        stack[position..].rotate_left(2);
+                    }
+                    OP_PICK | OP_ROLL => {
+                        let depth = read_num(&pop(&mut stack)?, 4)?;
+                        let depth =
+                            usize::try_from(depth).map_err(|_| ScriptError::StackUnderflow)?;
+                        let item = peek(&stack, depth)?.clone();
+                        if op == OP_ROLL {
+                            stack.remove(stack.len() - depth - 1);
+                        }
+                        stack.push(item);
+                    }
+                    OP_TUCK => {
+                        let top = peek(&stack, 0)?.clone();
+                        peek(&stack, 1)?;
+                        stack.insert(stack.len() - 2, top);
+                    }
+                    OP_IFDUP => {
+                        let top = peek(&stack, 0)?.clone();
+                        if script::read_scriptbool(&top) {
+                            stack.push(top);
+                        }
+                    }
+                    OP_OVER => stack.push(peek(&stack, 1)?.clone()),
+                    OP_NIP => {
+                        let top = pop(&mut stack)?;
+                        pop(&mut stack)?;
+                        stack.push(top);
+                    }
+                    OP_SWAP => {
+                        let len = stack.len();
+                        if len < 2 {
+                            return Err(ScriptError::StackUnderflow);
+                        }
+                        stack.swap(len - 1, len - 2);
+                    }
+                    OP_DEPTH => stack.push(encode_num(stack.len() as i64)),
+                    OP_SIZE => stack.push(encode_num(peek(&stack, 0)?.len() as i64)),
+                    OP_EQUAL | OP_EQUALVERIFY => {
+                        let equal = pop(&mut stack)? == pop(&mut stack)?;
+                        if op == OP_EQUALVERIFY {
+                            verify(op, equal)?;
+                        } else {
+                            stack.push(encode_bool(equal));
+                        }
+                    }
+                    OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                        let n = read_num(&pop(&mut stack)?, 4)?;
+                        let result = match op {
+                            OP_1ADD => n + 1,
+                            OP_1SUB => n - 1,
+                            OP_NEGATE => -n,
+                            OP_ABS => n.abs(),
+                            OP_NOT => (n == 0).into(),
+                            _ => (n != 0).into(),
+                        };
+                        stack.push(encode_num(result));
+                    }
+                    OP_ADD
+                    | OP_SUB
+                    | OP_BOOLAND
+                    | OP_BOOLOR
+                    | OP_NUMEQUAL
+                    | OP_NUMEQUALVERIFY
+                    | OP_NUMNOTEQUAL
+                    | OP_LESSTHAN
+                    | OP_GREATERTHAN
+                    | OP_LESSTHANOREQUAL
+                    | OP_GREATERTHANOREQUAL
+                    | OP_MIN
+                    | OP_MAX => {
+                        let b = read_num(&pop(&mut stack)?, 4)?;
+                        let a = read_num(&pop(&mut stack)?, 4)?;
+                        let result = match op {
+                            OP_ADD => a + b,
+                            OP_SUB => a - b,
+                            OP_BOOLAND => (a != 0 && b != 0).into(),
+                            OP_BOOLOR => (a != 0 || b != 0).into(),
+                            OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b).into(),
+                            OP_NUMNOTEQUAL => (a != b).into(),
+                            OP_LESSTHAN => (a < b).into(),
+                            OP_GREATERTHAN => (a > b).into(),
+                            OP_LESSTHANOREQUAL => (a <= b).into(),
+                            OP_GREATERTHANOREQUAL => (a >= b).into(),
+                            OP_MIN => a.min(b),
+                            _ => a.max(b),
+                        };
+                        if op == OP_NUMEQUALVERIFY {
+                            verify(op, result != 0)?;
+                        } else {
+                            stack.push(encode_num(result));
+                        }
+                    }
+                    OP_WITHIN => {
+                        let max = read_num(&pop(&mut stack)?, 4)?;
+                        let min = read_num(&pop(&mut stack)?, 4)?;
+                        let n = read_num(&pop(&mut stack)?, 4)?;
+                        stack.push(encode_bool(min <= n && n < max));
+                    }
+                    OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                        let data = pop(&mut stack)?;
+                        let digest = match op {
+                            OP_RIPEMD160 => ripemd160::Hash::hash(&data).to_byte_array().to_vec(),
+                            OP_SHA1 => sha1::Hash::hash(&data).to_byte_array().to_vec(),
+                            OP_SHA256 => sha256::Hash::hash(&data).to_byte_array().to_vec(),
+                            OP_HASH160 => hash160::Hash::hash(&data).to_byte_array().to_vec(),
+                            _ => sha256d::Hash::hash(&data).to_byte_array().to_vec(),
+                        };
+                        stack.push(digest);
+                    }
+                    OP_CODESEPARATOR => {
+                        // signatures commit to the script after the last executed separator
+                        let script_code = Script::from_bytes(&script.as_bytes()[offset + 1..]);
+                        sig_version = match sig_version {
+                            SigVersion::Base { .. } => SigVersion::Base { script_code },
+                            SigVersion::WitnessV0 { .. } => SigVersion::WitnessV0 { script_code },
+                            SigVersion::Tapscript {
+                                leaf_hash, annex, ..
+                            } => SigVersion::Tapscript {
+                                leaf_hash,
+                                annex,
+                                code_separator: position as u32,
+                            },
+                        };
+                    }
+                    OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                        let public_key = pop(&mut stack)?;
+                        let signature = pop(&mut stack)?;
+                        let valid = self.check_sig(&signature, &public_key, sig_version)?;
+                        if op == OP_CHECKSIGVERIFY {
+                            verify(op, valid)?;
+                        } else {
+                            stack.push(encode_bool(valid));
+                        }
+                    }
+                    OP_CHECKSIGADD => {
+                        let public_key = pop(&mut stack)?;
+                        let n = read_num(&pop(&mut stack)?, 4)?;
+                        let signature = pop(&mut stack)?;
+                        let valid = self.check_sig(&signature, &public_key, sig_version)?;
+                        stack.push(encode_num(n + i64::from(valid)));
+                    }
+                    OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                        let valid = self.check_multisig(&mut stack, sig_version, &mut op_count)?;
+                        if op == OP_CHECKMULTISIGVERIFY {
+                            verify(op, valid)?;
+                        } else {
+                            stack.push(encode_bool(valid));
+                        }
+                    }
+                    _ => return Err(ScriptError::UnsupportedOpcode(op)),
+                },
+            }
+            check_stack_size(&stack, &alt_stack)?;
+        }
+
+        if !conditions.is_empty() {
+            return Err(ScriptError::UnbalancedConditional);
+        }
+        match stack.as_slice() {
+            [top] if script::read_scriptbool(top) => Ok(()),
+            [_] => Err(ScriptError::EvalFalse),
+            _ => Err(ScriptError::CleanStack),
+        }
+    }
+
+    /// Whether `signature` is a valid signature of `public_key`, failing on a non empty invalid one
+    fn check_sig(
+        &self,
+        signature: &[u8],
+        public_key: &[u8],
+        sig_version: SigVersion,
+    ) -> Result<bool, ScriptError> {
+        let valid = match sig_version {
+            // a legacy signature cannot commit to itself, it is deleted from the script code
+            SigVersion::Base { script_code } => {
+                let script_code = find_and_delete(script_code, &[signature]);
+                let sig_version = SigVersion::Base {
+                    script_code: &script_code,
+                };
+                self.check_ecdsa(signature, public_key, sig_version)?
+            }
+            SigVersion::WitnessV0 { .. } => self.check_ecdsa(signature, public_key, sig_version)?,
+            SigVersion::Tapscript {
+                leaf_hash,
+                annex,
+                code_separator,
+            } => {
+                if public_key.is_empty() {
+                    return Err(ScriptError::PublicKeyType);
+                }
+                // BIP342: keys of unknown type are reserved for upgrades and always succeed
+                if signature.is_empty() || public_key.len() != 32 {
+                    return Ok(!signature.is_empty());
+                }
+                let public_key = XOnlyPublicKey::from_slice(public_key)
+                    .map_err(|_| ScriptError::PublicKeyType)?;
+                self.check_schnorr(
+                    signature,
+                    &public_key,
+                    annex,
+                    Some((leaf_hash, code_separator)),
+                )?
+            }
+        };
+
+        if !valid && !signature.is_empty() {
+            return Err(ScriptError::NullFail);
+        }
+        Ok(valid)
+    }
+
+    /// Pop and check the operands of OP_CHECKMULTISIG, keys and signatures matched in order
+    fn check_multisig(
+        &self,
+        stack: &mut Stack,
+        sig_version: SigVersion,
+        op_count: &mut usize,
+    ) -> Result<bool, ScriptError> {
+        if let SigVersion::Tapscript { .. } = sig_version {
+            return Err(ScriptError::BadOpcode(OP_CHECKMULTISIG));
+        }
+
+        let key_count = read_num(&pop(stack)?, 4)?;
+        if !(0..=MAX_PUBKEYS_PER_MULTISIG).contains(&key_count) {
+            return Err(ScriptError::InvalidNumber);
+        }
+        // every key counts against the opcode limit
+        *op_count += key_count as usize;
+        if *op_count > MAX_OPS_PER_SCRIPT {
+            return Err(ScriptError::OpCount);
+        }
+        let mut public_keys = (0..key_count)
+            .map(|_| pop(stack))
+            .collect::<Result<Vec<_>, _>>()?;
+        public_keys.reverse();
+        let signature_count = read_num(&pop(stack)?, 4)?;
+        if !(0..=key_count).contains(&signature_count) {
+            return Err(ScriptError::InvalidNumber);
+        }
+        let mut signatures = (0..signature_count)
+            .map(|_| pop(stack))
+            .collect::<Result<Vec<_>, _>>()?;
+        signatures.reverse();
+        if !pop(stack)?.is_empty() {
+            return Err(ScriptError::NullDummy);
+        }
+
+        let script_code;
+        let sig_version = match sig_version {
+            SigVersion::Base { script_code: code } => {
+                let signatures = signatures.iter().map(Vec::as_slice).collect::<Vec<_>>();
+                script_code = find_and_delete(code, &signatures);
+                SigVersion::Base {
+                    script_code: &script_code,
+                }
+            }
+            sig_version => sig_version,
+        };
+
+        let mut keys = public_keys.iter();
+        let mut valid = true;
+        for signature in &signatures {
+            let mut matched = false;
+            for public_key in keys.by_ref() {
+                if self.check_ecdsa(signature, public_key, sig_version)? {
+                    matched = true;
+                    break;
+                }
+            }
+            if !matched {
+                valid = false;
+                break;
+            }
+        }
+
+        if !valid && signatures.iter().any(|signature| !signature.is_empty()) {
+            return Err(ScriptError::NullFail);
+        }
+        Ok(valid)
+    }
+
+    /// ECDSA check of a legacy or witness v0 script
+    fn check_ecdsa(
+        &self,
+        signature: &[u8],
+        public_key: &[u8],
+        sig_version: SigVersion,
+    ) -> Result<bool, ScriptError> {
+        // witness v0 only allows compressed keys
+        let public_key = PublicKey::from_slice(public_key)
+            .ok()
+            .filter(|public_key| {
+                public_key.compressed || matches!(sig_version, SigVersion::Base { .. })
+            })
+            .ok_or(ScriptError::PublicKeyType)?;
+        if signature.is_empty() {
+            return Ok(false);
+        }
+        let signature = ecdsa::Signature::from_slice(signature)
+            .map_err(|err| ScriptError::SignatureEncoding(err.to_string()))?;
+
+        let mut sighash_cache = SighashCache::new(self.tx);
+        let sighash = match sig_version {
+            SigVersion::Base { script_code } => sighash_cache
+                .legacy_signature_hash(self.index, script_code, signature.hash_ty.to_u32())
+                .map(|sighash| sighash.to_byte_array()),
+            SigVersion::WitnessV0 { script_code } => sighash_cache
+                .p2wsh_signature_hash(
+                    self.index,
+                    script_code,
+                    self.prevouts[self.index].value,
+                    signature.hash_ty,
+                )
+                .map(|sighash| sighash.to_byte_array()),
+            SigVersion::Tapscript { .. } => return Err(ScriptError::PublicKeyType),
+        }
+        .map_err(|err| ScriptError::Sighash(err.to_string()))?;
+        let message = Message::from_digest(sighash);
+
+        Ok(self
+            .secp
+            .verify_ecdsa(&message, &signature.sig, &public_key.inner)
+            .is_ok())
+    }
+
+    fn check_schnorr(
+        &self,
+        signature: &[u8],
+        public_key: &XOnlyPublicKey,
+        annex: Option<&[u8]>,
+        leaf: Option<(TapLeafHash, u32)>,
+    ) -> Result<bool, ScriptError> {
+        let signature = taproot::Signature::from_slice(signature)
+            .map_err(|err| ScriptError::SignatureEncoding(err.to_string()))?;
+        let annex = annex
+            .map(Annex::new)
+            .transpose()
+            .map_err(|err| ScriptError::Sighash(err.to_string()))?;
+
+        let sighash = SighashCache::new(self.tx)
+            .taproot_signature_hash(
+                self.index,
+                &Prevouts::All(self.prevouts),
+                annex,
+                leaf,
+                signature.hash_ty,
+            )
+            .map_err(|err| ScriptError::Sighash(err.to_string()))?;
+        let message = Message::from_digest(sighash.to_byte_array());
+
+        Ok(self
+            .secp
+            .verify_schnorr(&signature.sig, &message, public_key)
+            .is_ok())
+    }
+
+    /// BIP65 OP_CHECKLOCKTIMEVERIFY
+    fn check_lock_time(&self, lock_time: i64) -> Result<(), ScriptError> {
+        let tx_lock_time = i64::from(self.tx.lock_time.to_consensus_u32());
+        let same_kind = (lock_time < LOCKTIME_THRESHOLD) == (tx_lock_time < LOCKTIME_THRESHOLD);
+        let final_input = self.tx.input[self.index].sequence == Sequence::MAX;
+        if lock_time < 0 || !same_kind || lock_time > tx_lock_time || final_input {
+            return Err(ScriptError::UnsatisfiedLocktime);
+        }
+
+        Ok(())
+    }
+
+    /// BIP112 OP_CHECKSEQUENCEVERIFY
+    fn check_sequence(&self, sequence: i64) -> Result<(), ScriptError> {
+        if sequence < 0 {
+            return Err(ScriptError::UnsatisfiedLocktime);
+        }
+        if sequence & SEQUENCE_DISABLE_FLAG != 0 {
+            return Ok(());
+        }
+
+        let tx_sequence = i64::from(self.tx.input[self.index].sequence.to_consensus_u32());
+        if self.tx.version.0 < 2 || tx_sequence & SEQUENCE_DISABLE_FLAG != 0 {
+            return Err(ScriptError::UnsatisfiedLocktime);
+        }
+        let (sequence, tx_sequence) = (sequence & SEQUENCE_MASK, tx_sequence & SEQUENCE_MASK);
+        let same_kind = (sequence < SEQUENCE_TYPE_FLAG) == (tx_sequence < SEQUENCE_TYPE_FLAG);
+        if !same_kind || sequence > tx_sequence {
+            return Err(ScriptError::UnsatisfiedLocktime);
+        }
+
+        Ok(())
+    }
+}
+
+/// Initial stack of a legacy script sig, which may only push data
+fn push_only(script_sig: &Script) -> Result<Stack, ScriptError> {
+    if script_sig.len() > MAX_SCRIPT_SIZE {
+        return Err(ScriptError::ScriptSize);
+    }
+    let stack = script_sig
+        .instruction_indices()
+        .map(
+            |instruction| match instruction.map_err(|_| ScriptError::BadScript)? {
+                (offset, Instruction::PushBytes(bytes)) => {
+                    if bytes.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                        return Err(ScriptError::PushSize);
+                    }
+                    check_minimal_push(script_sig.as_bytes()[offset], bytes.as_bytes())?;
+                    Ok(bytes.as_bytes().to_vec())
+                }
+                (_, Instruction::Op(op)) => match op.classify(ClassifyContext::Legacy) {
+                    Class::PushNum(n) => Ok(encode_num(n.into())),
+                    _ => Err(ScriptError::SigPushOnly),
+                },
+            },
+        )
+        .collect::<Result<Stack, _>>()?;
+    check_stack_size(&stack, &[])?;
+
+    Ok(stack)
+}
+
+/// MINIMALDATA: `bytes` must be pushed by the shortest opcode, `opcode`
+fn check_minimal_push(opcode: u8, bytes: &[u8]) -> Result<(), ScriptError> {
+    let minimal = match bytes {
+        [] => opcode == OP_PUSHBYTES_0.to_u8(),
+        // OP_1NEGATE and OP_1 to OP_16
+        [0x81] | [1..=16] => false,
+        _ if bytes.len() <= 75 => usize::from(opcode) == bytes.len(),
+        _ if bytes.len() <= 0xff => opcode == OP_PUSHDATA1.to_u8(),
+        _ if bytes.len() <= 0xffff => opcode == OP_PUSHDATA2.to_u8(),
+        _ => true,
+    };
+    if minimal {
+        Ok(())
+    } else {
+        Err(ScriptError::MinimalData)
+    }
+}
+
+fn check_stack_size(stack: &[Vec<u8>], alt_stack: &[Vec<u8>]) -> Result<(), ScriptError> {
+    if stack.len() + alt_stack.len() > MAX_STACK_SIZE {
+        return Err(ScriptError::StackSize);
+    }
+
+    Ok(())
+}
+
+/// FindAndDelete: remove the pushes of `signatures` at opcode boundaries of `script_code`
+fn find_and_delete(script_code: &Script, signatures: &[&[u8]]) -> ScriptBuf {
+    let mut script_code = script_code.as_bytes().to_vec();
+    for signature in signatures {
+        let Ok(push) = PushBytesBuf::try_from(signature.to_vec()) else {
+            continue;
+        };
+        let pattern = script::Builder::new().push_slice(push).into_script();
+        let pattern = pattern.as_bytes();
+
+        let mut result = Vec::with_capacity(script_code.len());
+        let (mut position, mut copied) = (0, 0);
+        loop {
+            result.extend_from_slice(&script_code[copied..position]);
+            while script_code[position..].starts_with(pattern) {
+                position += pattern.len();
+            }
+            copied = position;
+            match op_len(&script_code[position..]) {
+                Some(len) => position += len,
+                None => break,
+            }
+        }
+        result.extend_from_slice(&script_code[copied..]);
+        script_code = result;
+    }
+
+    ScriptBuf::from(script_code)
+}
+
+/// Length of the opcode at the start of `bytes` with its push data, `None` at the end or if truncated
+fn op_len(bytes: &[u8]) -> Option<usize> {
+    let (&opcode, rest) = bytes.split_first()?;
+    let (header, data_len) = match opcode {
+        0x01..=0x4b => (0, usize::from(opcode)),
+        0x4c => (1, usize::from(*rest.first()?)),
+        0x4d => (
+            2,
+            usize::from(u16::from_le_bytes(rest.get(..2)?.try_into().ok()?)),
+        ),
+        0x4e => (
+            4,
+            u32::from_le_bytes(rest.get(..4)?.try_into().ok()?) as usize,
+        ),
+        _ => (0, 0),
+    };
+    let len = 1 + header + data_len;
+
+    (len <= bytes.len()).then_some(len)
+}
+
+fn pop(stack: &mut Stack) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::StackUnderflow)
+}
+
+/// Element `depth` positions below the top of the stack
+fn peek(stack: &Stack, depth: usize) -> Result<&Vec<u8>, ScriptError> {
+    stack
+        .len()
+        .checked_sub(depth + 1)
+        .map(|position| &stack[position])
+        .ok_or(ScriptError::StackUnderflow)
+}
+
+fn verify(op: Opcode, condition: bool) -> Result<(), ScriptError> {
+    if condition {
+        Ok(())
+    } else {
+        Err(ScriptError::Verify(op))
+    }
+}
+
+/// Decode a minimally encoded script number of at most `max_size` bytes
+fn read_num(bytes: &[u8], max_size: usize) -> Result<i64, ScriptError> {
+    if bytes.len() > max_size {
+        return Err(ScriptError::InvalidNumber);
+    }
+    if let Some(last) = bytes.last() {
+        // the most significant byte may only be 0x00 or 0x80 when the next one needs its sign bit
+        let needs_sign_byte = bytes.len() > 1 && bytes[bytes.len() - 2] & 0x80 != 0;
+        if last & 0x7f == 0 && !needs_sign_byte {
+            return Err(ScriptError::InvalidNumber);
+        }
+    }
+
+    let mut value = bytes
+        .iter()
+        .rev()
+        .fold(0_i64, |value, byte| (value << 8) | i64::from(*byte));
+    if let Some(last) = bytes.last() {
+        if last & 0x80 != 0 {
+            let sign_bit = 0x80_i64 << (8 * (bytes.len() - 1));
+            value = -(value & !sign_bit);
+        }
+    }
+
+    Ok(value)
+}
+
+fn encode_num(n: i64) -> Vec<u8> {
+    let mut buf = [0; 8];
+    let len = script::write_scriptint(&mut buf, n);
+    buf[..len].to_vec()
+}
+
+fn encode_bool(value: bool) -> Vec<u8> {
+    encode_num(value.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::absolute::LockTime;
+    use bitcoin::opcodes::OP_FALSE;
+    use bitcoin::script::Builder as ScriptBuilder;
+    use bitcoin::taproot::TaprootBuilder;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, Network, OutPoint, PrivateKey, TxIn, Witness};
+
+    use super::*;
+    use crate::secret::SecretPrivateKey;
+    use crate::signer::{Signer, SpentOutput};
+
+    #[test]
+    fn test_should_verify_signed_scripts_and_report_failing_inputs() {
+        let secp = Secp256k1::new();
+        let (keypair, x_only_key) = crate::taproot::generate_keypair(&secp);
+        let private_key =
+            SecretPrivateKey::new(PrivateKey::new(keypair.secret_key(), Network::Regtest));
+        let public_key = private_key.public_key(&secp);
+
+        // pay to a key, a witness script and a taproot leaf
+        let witness_script = ScriptBuilder::new()
+            .push_key(&public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let leaf = ScriptBuilder::new()
+            .push_x_only_key(&x_only_key)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_FALSE)
+            .push_opcode(OP_IF)
+            .push_slice(b"ord")
+            .push_opcode(OP_ENDIF)
+            .into_script();
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(0, leaf.clone())
+            .unwrap()
+            .finalize(&secp, x_only_key)
+            .unwrap();
+        let control_block = spend_info
+            .control_block(&(leaf.clone(), LeafVersion::TapScript))
+            .unwrap();
+
+        let prevout = |script_pubkey| TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey,
+        };
+        let spent = [
+            SpentOutput::new(prevout(ScriptBuf::new_p2wpkh(
+                &public_key.wpubkey_hash().unwrap(),
+            ))),
+            SpentOutput::new(prevout(ScriptBuf::new_p2wsh(
+                &witness_script.wscript_hash(),
+            )))
+            .with_witness_script(witness_script),
+            SpentOutput::new(prevout(ScriptBuf::new_p2tr(
+                &secp,
+                x_only_key,
+                spend_info.merkle_root(),
+            )))
+            .with_tap_leaf(leaf, control_block),
+        ];
+        let prevouts = spent
+            .iter()
+            .map(|spent| spent.prevout.clone())
+            .collect::<Vec<_>>();
+        let unsigned_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: (0..spent.len() as u32)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint {
+                        txid: bitcoin::Txid::all_zeros(),
+                        vout,
+                    },
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![prevout(ScriptBuf::new_p2wpkh(
+                &public_key.wpubkey_hash().unwrap(),
+            ))],
+        };
+
+        let mut signer = Signer::new(&private_key, &secp, unsigned_tx);
+        let signed_tx = signer.sign_transaction(&spent).unwrap();
+        assert_eq!(signer.report().signed, vec![0, 1, 2]);
+        let report = verify_transaction(&secp, &signed_tx, &prevouts);
+        assert!(report.is_valid(), "{report}");
+
+        // a wrong control block and a swapped witness are reported on their own input
+        let mut tampered_tx = signed_tx.clone();
+        let mut witness = tampered_tx.input[2].witness.to_vec();
+        witness.last_mut().unwrap()[0] ^= 1;
+        tampered_tx.input[2].witness = Witness::from_slice(&witness);
+        let mut witness = tampered_tx.input[0].witness.to_vec();
+        witness.swap(0, 1);
+        tampered_tx.input[0].witness = Witness::from_slice(&witness);
+
+        let report = verify_transaction(&secp, &tampered_tx, &prevouts);
+        assert!(!report.is_valid());
+        assert!(report.inputs[0].error.is_some());
+        assert_eq!(report.inputs[1].error, None);
+        assert_eq!(
+            report.inputs[2].error,
+            Some(ScriptError::InvalidControlBlock)
+        );
+        assert!(report.ensure_valid().is_err());
+    }
+
+    #[test]
+    fn test_should_reject_unsatisfied_lock_times() {
+        let secp = Secp256k1::new();
+        let (keypair, _) = crate::taproot::generate_keypair(&secp);
+        let private_key =
+            SecretPrivateKey::new(PrivateKey::new(keypair.secret_key(), Network::Regtest));
+        let public_key = private_key.public_key(&secp);
+
+        // sign a spend of `<lock> OP_CLTV|OP_CSV OP_DROP <key> OP_CHECKSIG` and run its script
+        let verify_spend = |lock_op, lock: i64, lock_time, sequence| {
+            let witness_script = ScriptBuilder::new()
+                .push_int(lock)
+                .push_opcode(lock_op)
+                .push_opcode(OP_DROP)
+                .push_key(&public_key)
+                .push_opcode(OP_CHECKSIG)
+                .into_script();
+            let prevout = TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: ScriptBuf::new_p2wsh(&witness_script.wscript_hash()),
+            };
+            let unsigned_tx = Transaction {
+                version: Version::TWO,
+                lock_time,
+                input: vec![TxIn {
+                    previous_output: OutPoint::null(),
+                    script_sig: ScriptBuf::new(),
+                    sequence,
+                    witness: Witness::new(),
+                }],
+                output: vec![TxOut {
+                    value: Amount::from_sat(9_000),
+                    script_pubkey: prevout.script_pubkey.clone(),
+                }],
+            };
+            let signed_tx = Signer::new(&private_key, &secp, unsigned_tx)
+                .sign_transaction(&[
+                    SpentOutput::new(prevout.clone()).with_witness_script(witness_script)
+                ])
+                .unwrap();
+
+            verify_transaction(&secp, &signed_tx, &[prevout]).inputs[0]
+                .error
+                .clone()
+        };
+
+        let height = |height| LockTime::from_height(height).unwrap();
+        assert_eq!(
+            verify_spend(
+                OP_CLTV,
+                800_000,
+                height(799_999),
+                Sequence::ENABLE_RBF_NO_LOCKTIME
+            ),
+            Some(ScriptError::UnsatisfiedLocktime)
+        );
+        // a final sequence disables the lock time of the transaction
+        assert_eq!(
+            verify_spend(OP_CLTV, 800_000, height(800_000), Sequence::MAX),
+            Some(ScriptError::UnsatisfiedLocktime)
+        );
+        assert_eq!(
+            verify_spend(
+                OP_CLTV,
+                800_000,
+                height(800_000),
+                Sequence::ENABLE_RBF_NO_LOCKTIME
+            ),
+            None
+        );
+
+        assert_eq!(
+            verify_spend(OP_CSV, 144, LockTime::ZERO, Sequence::from_height(143)),
+            Some(ScriptError::UnsatisfiedLocktime)
+        );
+        assert_eq!(
+            verify_spend(OP_CSV, 144, LockTime::ZERO, Sequence::MAX),
+            Some(ScriptError::UnsatisfiedLocktime)
+        );
+        assert_eq!(
+            verify_spend(OP_CSV, 144, LockTime::ZERO, Sequence::from_height(144)),
+            None
+        );
+    }
+
+    fn spending_tx(witness: Witness, script_sig: ScriptBuf) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig,
+                sequence: Sequence::MAX,
+                witness,
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(9_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    fn p2wsh_prevout(witness_script: &Script) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: ScriptBuf::new_p2wsh(&witness_script.wscript_hash()),
+        }
+    }
+
+    /// Run `witness_script` spent through P2WSH with the initial `stack`
+    fn run_p2wsh(witness_script: &Script, mut stack: Vec<Vec<u8>>) -> Option<ScriptError> {
+        let secp = Secp256k1::new();
+        stack.push(witness_script.to_bytes());
+        let tx = spending_tx(Witness::from_slice(&stack), ScriptBuf::new());
+
+        verify_transaction(&secp, &tx, &[p2wsh_prevout(witness_script)]).inputs[0]
+            .error
+            .clone()
+    }
+
+    #[test]
+    fn test_should_enforce_consensus_limits() {
+        let script = |bytes: Vec<u8>| ScriptBuf::from(bytes);
+        let op = |op: Opcode| op.to_u8();
+
+        // pushes and witness items are limited to 520 bytes, even in an unexecuted branch
+        let push = |len: usize| {
+            ScriptBuilder::new()
+                .push_slice(PushBytesBuf::try_from(vec![1; len]).unwrap())
+                .into_script()
+        };
+        let mut oversized_push = push(MAX_SCRIPT_ELEMENT_SIZE + 1).to_bytes();
+        oversized_push.extend([op(OP_DROP), op(OP_PUSHNUM_1)]);
+        assert_eq!(
+            run_p2wsh(&script(oversized_push), vec![]),
+            Some(ScriptError::PushSize)
+        );
+        let mut unexecuted_push = vec![op(OP_PUSHBYTES_0), op(OP_IF)];
+        unexecuted_push.extend(push(MAX_SCRIPT_ELEMENT_SIZE + 1).to_bytes());
+        unexecuted_push.extend([op(OP_ENDIF), op(OP_PUSHNUM_1)]);
+        assert_eq!(
+            run_p2wsh(&script(unexecuted_push), vec![]),
+            Some(ScriptError::PushSize)
+        );
+        let mut largest_push = push(MAX_SCRIPT_ELEMENT_SIZE).to_bytes();
+        largest_push.extend([op(OP_DROP), op(OP_PUSHNUM_1)]);
+        assert_eq!(run_p2wsh(&script(largest_push), vec![]), None);
+        let drop_item = script(vec![op(OP_DROP), op(OP_PUSHNUM_1)]);
+        assert_eq!(
+            run_p2wsh(&drop_item, vec![vec![1; MAX_SCRIPT_ELEMENT_SIZE + 1]]),
+            Some(ScriptError::PushSize)
+        );
+
+        // 201 non push opcodes at most
+        let nops = |count: usize| {
+            let mut bytes = vec![op(OP_NOP); count];
+            bytes.push(op(OP_PUSHNUM_1));
+            script(bytes)
+        };
+        assert_eq!(run_p2wsh(&nops(MAX_OPS_PER_SCRIPT), vec![]), None);
+        assert_eq!(
+            run_p2wsh(&nops(MAX_OPS_PER_SCRIPT + 1), vec![]),
+            Some(ScriptError::OpCount)
+        );
+
+        // 1000 stack elements at most
+        let pushes = |count: usize| script(vec![op(OP_PUSHNUM_1); count]);
+        assert_eq!(
+            run_p2wsh(&pushes(MAX_STACK_SIZE), vec![]),
+            Some(ScriptError::CleanStack)
+        );
+        assert_eq!(
+            run_p2wsh(&pushes(MAX_STACK_SIZE + 1), vec![]),
+            Some(ScriptError::StackSize)
+        );
+
+        // 10000 bytes scripts at most
+        assert_eq!(
+            run_p2wsh(&pushes(MAX_SCRIPT_SIZE + 1), vec![]),
+            Some(ScriptError::ScriptSize)
+        );
+
+        // a number pushed as data instead of with OP_5
+        let non_minimal = script(vec![op(OP_PUSHBYTES_1), 5, op(OP_DROP), op(OP_PUSHNUM_1)]);
+        assert_eq!(
+            run_p2wsh(&non_minimal, vec![]),
+            Some(ScriptError::MinimalData)
+        );
+    }
+
+    #[test]
+    fn test_should_run_stack_opcodes() {
+        let mut builder = ScriptBuilder::new();
+        let expect = |builder: ScriptBuilder, values: &[i64]| {
+            values.iter().rev().fold(builder, |builder, value| {
+                builder.push_int(*value).push_opcode(OP_EQUALVERIFY)
+            })
+        };
+        let push = |builder: ScriptBuilder, values: &[i64]| {
+            values
+                .iter()
+                .fold(builder, |builder, value| builder.push_int(*value))
+        };
+
+        builder = expect(push(builder, &[1, 2, 3]).push_opcode(OP_ROT), &[2, 3, 1]);
+        builder = expect(push(builder, &[1, 2]).push_opcode(OP_2DUP), &[1, 2, 1, 2]);
+        builder = expect(
+            push(builder, &[1, 2, 3]).push_opcode(OP_3DUP),
+            &[1, 2, 3, 1, 2, 3],
+        );
+        builder = expect(
+            push(builder, &[1, 2, 3, 4]).push_opcode(OP_2OVER),
+            &[1, 2, 3, 4, 1, 2],
+        );
+        builder = expect(
+            push(builder, &[1, 2, 3, 4, 5, 6]).push_opcode(OP_2ROT),
+            &[3, 4, 5, 6, 1, 2],
+        );
+        builder = expect(
+            push(builder, &[1, 2, 3, 4]).push_opcode(OP_2SWAP),
+            &[3, 4, 1, 2],
+        );
+        builder = expect(
+            push(builder, &[1, 2, 3, 2]).push_opcode(OP_PICK),
+            &[1, 2, 3, 1],
+        );
+        builder = expect(
+            push(builder, &[1, 2, 3, 2]).push_opcode(OP_ROLL),
+            &[2, 3, 1],
+        );
+        builder = expect(push(builder, &[1, 2]).push_opcode(OP_TUCK), &[2, 1, 2]);
+        builder = expect(push(builder, &[5, 1, 10]).push_opcode(OP_WITHIN), &[1]);
+        builder = expect(push(builder, &[10, 1, 10]).push_opcode(OP_WITHIN), &[0]);
+        let script = builder.push_int(1).into_script();
+        assert_eq!(run_p2wsh(&script, vec![]), None);
+
+        // picking below the bottom of the stack fails
+        let script = push(ScriptBuilder::new(), &[1, 1])
+            .push_opcode(OP_PICK)
+            .into_script();
+        assert_eq!(
+            run_p2wsh(&script, vec![]),
+            Some(ScriptError::StackUnderflow)
+        );
+    }
+
+    #[test]
+    fn test_should_sign_after_code_separator_and_delete_legacy_signatures() {
+        let secp = Secp256k1::new();
+        let (keypair, _) = crate::taproot::generate_keypair(&secp);
+        let public_key = PublicKey::new(keypair.public_key());
+        let sign = |sighash: [u8; 32]| {
+            let signature = secp.sign_ecdsa(&Message::from_digest(sighash), &keypair.secret_key());
+            ecdsa::Signature::sighash_all(signature).to_vec()
+        };
+
+        // witness v0 signatures commit to the script after the executed OP_CODESEPARATOR
+        let signed_code = ScriptBuilder::new()
+            .push_key(&public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let mut witness_script = vec![OP_CODESEPARATOR.to_u8()];
+        witness_script.extend(signed_code.as_bytes());
+        let witness_script = ScriptBuf::from(witness_script);
+        let prevout = p2wsh_prevout(&witness_script);
+        let tx = spending_tx(Witness::new(), ScriptBuf::new());
+        let verify_with = |script_code: &Script| {
+            let sighash = SighashCache::new(&tx)
+                .p2wsh_signature_hash(
+                    0,
+                    script_code,
+                    prevout.value,
+                    bitcoin::EcdsaSighashType::All,
+                )
+                .unwrap();
+            let witness = [sign(sighash.to_byte_array()), witness_script.to_bytes()];
+            let tx = spending_tx(Witness::from_slice(&witness), ScriptBuf::new());
+            verify_transaction(&secp, &tx, std::slice::from_ref(&prevout)).inputs[0]
+                .error
+                .clone()
+        };
+        assert_eq!(verify_with(&signed_code), None);
+        assert_eq!(verify_with(&witness_script), Some(ScriptError::NullFail));
+
+        // a legacy redeem script holding its own signature signs the script without it
+        let signed_code = ScriptBuilder::new()
+            .push_opcode(OP_DROP)
+            .push_key(&public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let mut tx = spending_tx(Witness::new(), ScriptBuf::new());
+        let sighash = SighashCache::new(&tx)
+            .legacy_signature_hash(0, &signed_code, 1)
+            .unwrap();
+        let signature = PushBytesBuf::try_from(sign(sighash.to_byte_array())).unwrap();
+        let mut redeem_script = ScriptBuilder::new()
+            .push_slice(&signature)
+            .into_script()
+            .to_bytes();
+        redeem_script.extend(signed_code.as_bytes());
+        let redeem_script = ScriptBuf::from(redeem_script);
+        tx.input[0].script_sig = ScriptBuilder::new()
+            .push_slice(&signature)
+            .push_slice(PushBytesBuf::try_from(redeem_script.to_bytes()).unwrap())
+            .into_script();
+        let prevout = TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: redeem_script.to_p2sh(),
+        };
+        let report = verify_transaction(&secp, &tx, &[prevout]);
+        assert!(report.is_valid(), "{report}");
+    }
+
+    #[test]
+    fn test_should_accept_unknown_leaf_versions() {
+        let secp = Secp256k1::new();
+        let (_, x_only_key) = crate::taproot::generate_keypair(&secp);
+        // would fail if it ran as a tapscript
+        let leaf = ScriptBuilder::new().push_opcode(OP_RETURN).into_script();
+        let leaf_version = LeafVersion::from_consensus(0xc2).unwrap();
+        let spend_info = TaprootBuilder::new()
+            .add_leaf_with_ver(0, leaf.clone(), leaf_version)
+            .unwrap()
+            .finalize(&secp, x_only_key)
+            .unwrap();
+        let control_block = spend_info
+            .control_block(&(leaf.clone(), leaf_version))
+            .unwrap();
+        let prevout = TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: ScriptBuf::new_p2tr(&secp, x_only_key, spend_info.merkle_root()),
+        };
+
+        let witness = [leaf.to_bytes(), control_block.serialize()];
+        let tx = spending_tx(Witness::from_slice(&witness), ScriptBuf::new());
+        let report = verify_transaction(&secp, &tx, &[prevout]);
+        assert!(report.is_valid(), "{report}");
+    }
+}
@@ -0,0 +1,180 @@
+use bitcoin::bip32::KeySource;
+use bitcoin::key::XOnlyPublicKey;
+use bitcoin::opcodes::all::{
+    OP_CHECKMULTISIG, OP_CHECKMULTISIGVERIFY, OP_CHECKSIG, OP_CHECKSIGADD, OP_CHECKSIGVERIFY,
+};
+use bitcoin::script::Instruction;
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::{PrivateKey, PublicKey, Script, ScriptBuf};
+
+use crate::account::Account;
+use crate::secret::{SecretPrivateKey, SecretXpriv};
+
+/// Number of receive addresses of an account scanned when looking up a key by script
+pub(crate) const ADDRESS_GAP: u32 = 20;
+
+/// Looks up the private key able to sign an input
+pub trait KeyProvider {
+    /// Private key for `public_key`, from the BIP32 origin found in the PSBT derivation data
+    fn key_by_origin(
+        &self,
+        secp: &Secp256k1<All>,
+        public_key: XOnlyPublicKey,
+        key_source: &KeySource,
+    ) -> Option<SecretPrivateKey>;
+
+    /// Private key controlling `script`, either an output script or a witness script
+    fn key_by_script(&self, secp: &Secp256k1<All>, script: &Script) -> Option<SecretPrivateKey>;
+}
+
+/// Whether `script` is an output of `public_key` or a script pushing it
+pub fn controls_script(secp: &Secp256k1<All>, public_key: &PublicKey, script: &Script) -> bool {
+    let x_only_key = XOnlyPublicKey::from(public_key.inner);
+
+    let Some(wpkh) = public_key.wpubkey_hash() else {
+        return false;
+    };
+    let is_own_output = script == ScriptBuf::new_p2wpkh(&wpkh).as_script()
+        || script == ScriptBuf::new_p2pkh(&public_key.pubkey_hash()).as_script()
+        || script == ScriptBuf::new_p2wpkh(&wpkh).to_p2sh().as_script()
+        || script == ScriptBuf::new_p2tr(secp, x_only_key, None).as_script();
+    // witness scripts such as the inscription envelope push the key itself
+    let pushes_key = single_signature_key(script).is_some_and(|key| {
+        key == public_key.to_bytes().as_slice() || key == x_only_key.serialize().as_slice()
+    });
+
+    is_own_output || pushes_key
+}
+
+/// Key of a script checking a single `<key> OP_CHECKSIG`, such as the inscription envelope or
+/// a timelocked key. `None` for multisig and other scripts checking several signatures
+pub fn single_signature_key(script: &Script) -> Option<&[u8]> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let mut checks = instructions.iter().enumerate().filter(|(_, instruction)| {
+        matches!(instruction, Instruction::Op(op) if [
+            OP_CHECKSIG,
+            OP_CHECKSIGVERIFY,
+            OP_CHECKMULTISIG,
+            OP_CHECKMULTISIGVERIFY,
+            OP_CHECKSIGADD,
+        ]
+        .contains(op))
+    });
+    let (position, check) = checks.next()?;
+    if checks.next().is_some() || *check != Instruction::Op(OP_CHECKSIG) {
+        return None;
+    }
+
+    match instructions[..position].last()? {
+        Instruction::PushBytes(key) => Some(key.as_bytes()),
+        Instruction::Op(_) => None,
+    }
+}
+
+impl KeyProvider for PrivateKey {
+    fn key_by_origin(
+        &self,
+        secp: &Secp256k1<All>,
+        public_key: XOnlyPublicKey,
+        _key_source: &KeySource,
+    ) -> Option<SecretPrivateKey> {
+        let own_key = XOnlyPublicKey::from(self.public_key(secp).inner);
+        (own_key == public_key).then(|| SecretPrivateKey::new(*self))
+    }
+
+    fn key_by_script(&self, secp: &Secp256k1<All>, script: &Script) -> Option<SecretPrivateKey> {
+        controls_script(secp, &self.public_key(secp), script).then(|| SecretPrivateKey::new(*self))
+    }
+}
+
+impl KeyProvider for SecretPrivateKey {
+    fn key_by_origin(
+        &self,
+        secp: &Secp256k1<All>,
+        public_key: XOnlyPublicKey,
+        key_source: &KeySource,
+    ) -> Option<SecretPrivateKey> {
+        self.expose_secret()
+            .key_by_origin(secp, public_key, key_source)
+    }
+
+    fn key_by_script(&self, secp: &Secp256k1<All>, script: &Script) -> Option<SecretPrivateKey> {
+        self.expose_secret().key_by_script(secp, script)
+    }
+}
+
+impl KeyProvider for SecretXpriv {
+    fn key_by_origin(
+        &self,
+        secp: &Secp256k1<All>,
+        public_key: XOnlyPublicKey,
+        (fingerprint, path): &KeySource,
+    ) -> Option<SecretPrivateKey> {
+        if self.fingerprint(secp) != *fingerprint {
+            return None;
+        }
+
+        let private_key = self.derive_private_key(secp, path).ok()?;
+        private_key.key_by_origin(secp, public_key, &(*fingerprint, path.clone()))
+    }
+
+    fn key_by_script(&self, _secp: &Secp256k1<All>, _script: &Script) -> Option<SecretPrivateKey> {
+        None
+    }
+}
+
+impl KeyProvider for Account {
+    fn key_by_origin(
+        &self,
+        secp: &Secp256k1<All>,
+        public_key: XOnlyPublicKey,
+        key_source: &KeySource,
+    ) -> Option<SecretPrivateKey> {
+        self.private_key.key_by_origin(secp, public_key, key_source)
+    }
+
+    fn key_by_script(&self, secp: &Secp256k1<All>, script: &Script) -> Option<SecretPrivateKey> {
+        let descriptor = self.descriptor(secp);
+        (0..ADDRESS_GAP).find_map(|index| {
+            let key = descriptor.derive(secp, index).ok()?;
+            let private_key = self
+                .private_key
+                .derive_private_key(secp, &key.key_source.1)
+                .ok()?;
+            private_key.key_by_script(secp, script)
+        })
+    }
+}
+
+impl<T: KeyProvider + ?Sized> KeyProvider for &T {
+    fn key_by_origin(
+        &self,
+        secp: &Secp256k1<All>,
+        public_key: XOnlyPublicKey,
+        key_source: &KeySource,
+    ) -> Option<SecretPrivateKey> {
+        (**self).key_by_origin(secp, public_key, key_source)
+    }
+
+    fn key_by_script(&self, secp: &Secp256k1<All>, script: &Script) -> Option<SecretPrivateKey> {
+        (**self).key_by_script(secp, script)
+    }
+}
+
+/// Keys of several wallets or accounts; the first provider knowing the key wins
+impl<T: KeyProvider> KeyProvider for [T] {
+    fn key_by_origin(
+        &self,
+        secp: &Secp256k1<All>,
+        public_key: XOnlyPublicKey,
+        key_source: &KeySource,
+    ) -> Option<SecretPrivateKey> {
+        self.iter()
+            .find_map(|provider| provider.key_by_origin(secp, public_key, key_source))
+    }
+
+    fn key_by_script(&self, secp: &Secp256k1<All>, script: &Script) -> Option<SecretPrivateKey> {
+        self.iter()
+            .find_map(|provider| provider.key_by_script(secp, script))
+    }
+}
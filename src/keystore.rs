@@ -0,0 +1,458 @@
+use std::fs::OpenOptions;
+use std::io::Write as _;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt as _;
+use std::path::Path;
+use std::str::FromStr;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use bip39::Mnemonic;
+use bitcoin::bip32::{DerivationPath, Fingerprint, Xpriv, Xpub};
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::Network;
+use chacha20poly1305::aead::{Aead as _, KeyInit as _, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore as _;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use zeroize::Zeroizing;
+
+use crate::account::Account;
+use crate::error::{KeyError, Result};
+use crate::secret::SecretXpriv;
+
+const KEYSTORE_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+/// Password-encrypted accounts, saved as JSON.
+///
+/// Only the mnemonic or the master xprv of an account is encrypted; its name, network,
+/// fingerprint, derivation path and xpub stay readable, so the file can be listed and
+/// xpubs exported without the password.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    /// Parameters used to encrypt new secrets
+    pub kdf: KdfParams,
+    pub accounts: Vec<KeystoreEntry>,
+}
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Memory cost in KiB
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+/// Kind of secret an account is restored from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretKind {
+    Mnemonic,
+    Xpriv,
+}
+
+/// Account of the keystore with its plaintext metadata
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeystoreEntry {
+    pub name: String,
+    pub kind: SecretKind,
+    #[serde_as(as = "DisplayFromStr")]
+    pub network: Network,
+    #[serde_as(as = "DisplayFromStr")]
+    pub fingerprint: Fingerprint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub path: DerivationPath,
+    #[serde_as(as = "DisplayFromStr")]
+    pub xpub: Xpub,
+    pub crypto: EncryptedSecret,
+}
+
+/// Secret sealed with ChaCha20-Poly1305 under an Argon2id key, bound to the entry xpub
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedSecret {
+    pub kdf: KdfParams,
+    #[serde(with = "hex_bytes")]
+    pub salt: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub ciphertext: Vec<u8>,
+}
+
+/// Plaintext secret of an account
+pub enum KeystoreSecret {
+    Mnemonic(Zeroizing<String>),
+    Xpriv(SecretXpriv),
+}
+
+impl Default for KdfParams {
+    /// OWASP recommended Argon2id parameters
+    fn default() -> Self {
+        Self {
+            m_cost: 19_456,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+}
+
+impl Default for Keystore {
+    fn default() -> Self {
+        Self {
+            version: KEYSTORE_VERSION,
+            kdf: KdfParams::default(),
+            accounts: Vec::new(),
+        }
+    }
+}
+
+impl Keystore {
+    /// Load the keystore at `path`, or an empty one if the file does not exist
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let keystore: Self = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        if keystore.version != KEYSTORE_VERSION {
+            return Err(KeyError::UnsupportedKeystore {
+                version: keystore.version,
+            }
+            .into());
+        }
+
+        Ok(keystore)
+    }
+
+    /// Write the keystore to a private temporary file, then rename it over `path`,
+    /// so an interrupted save never loses the only copy of the keys
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".tmp");
+        let tmp_path = path.with_file_name(file_name);
+
+        // a leftover of an interrupted save may have other permissions
+        if tmp_path.exists() {
+            std::fs::remove_file(&tmp_path)?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&tmp_path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn entry(&self, name: &str) -> Result<&KeystoreEntry> {
+        self.accounts
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| {
+                KeyError::UnknownAccount {
+                    name: name.to_string(),
+                }
+                .into()
+            })
+    }
+
+    /// Encrypt `secret` with `password` and add it as account `name`.
+    ///
+    /// All the accounts share the keystore password, so `password` must open the existing ones.
+    pub fn add(
+        &mut self,
+        secp: &Secp256k1<All>,
+        name: &str,
+        secret: &KeystoreSecret,
+        password: &str,
+    ) -> Result<&KeystoreEntry> {
+        if self.accounts.iter().any(|entry| entry.name == name) {
+            return Err(KeyError::DuplicateAccount {
+                name: name.to_string(),
+            }
+            .into());
+        }
+        if let Some(entry) = self.accounts.first() {
+            entry.crypto.open(password, &entry.name, &entry.xpub)?;
+        }
+
+        let account = secret.account(secp)?;
+        let xpub = account.input_xpub;
+        let plaintext = secret.to_bytes();
+        self.accounts.push(KeystoreEntry {
+            name: name.to_string(),
+            kind: secret.kind(),
+            network: *account.address.network(),
+            fingerprint: account.private_key.fingerprint(secp),
+            path: account.path,
+            xpub,
+            crypto: EncryptedSecret::seal(&plaintext, password, name, &xpub, self.kdf)?,
+        });
+
+        Ok(self.accounts.last().expect("just pushed"))
+    }
+
+    /// Decrypt account `name` with `password`
+    pub fn account(&self, secp: &Secp256k1<All>, name: &str, password: &str) -> Result<Account> {
+        let entry = self.entry(name)?;
+        let account = entry.secret(password)?.account(secp)?;
+        if account.input_xpub != entry.xpub {
+            return Err(KeyError::AccountMismatch {
+                name: name.to_string(),
+            }
+            .into());
+        }
+
+        Ok(account)
+    }
+
+    /// Re-encrypt every account under `new_password`; nothing changes unless all decrypt
+    pub fn change_password(&mut self, old_password: &str, new_password: &str) -> Result<()> {
+        let mut sealed = Vec::with_capacity(self.accounts.len());
+        for entry in &self.accounts {
+            let plaintext = entry.crypto.open(old_password, &entry.name, &entry.xpub)?;
+            sealed.push(EncryptedSecret::seal(
+                &plaintext,
+                new_password,
+                &entry.name,
+                &entry.xpub,
+                self.kdf,
+            )?);
+        }
+        for (entry, crypto) in self.accounts.iter_mut().zip(sealed) {
+            entry.crypto = crypto;
+        }
+
+        Ok(())
+    }
+}
+
+impl KeystoreEntry {
+    pub fn secret(&self, password: &str) -> Result<KeystoreSecret> {
+        let plaintext = self.crypto.open(password, &self.name, &self.xpub)?;
+        KeystoreSecret::from_bytes(self.kind, &plaintext).map_err(|reason| {
+            KeyError::CorruptedAccount {
+                name: self.name.clone(),
+                reason,
+            }
+            .into()
+        })
+    }
+}
+
+impl KeystoreSecret {
+    /// Parse a BIP39 mnemonic or a base58 master xprv
+    pub fn parse(secret: &str) -> Result<Self> {
+        let secret = secret.trim();
+        if secret.contains(char::is_whitespace) {
+            let mnemonic = Zeroizing::new(Mnemonic::from_str(secret)?);
+            Ok(Self::Mnemonic(Zeroizing::new(mnemonic.to_string())))
+        } else {
+            Ok(Self::Xpriv(SecretXpriv::new(Xpriv::from_str(secret)?)))
+        }
+    }
+
+    /// Generate a new 12 words mnemonic
+    pub fn generate_mnemonic() -> Result<Self> {
+        let mut entropy = Zeroizing::new([0; 16]);
+        rand::thread_rng().fill_bytes(entropy.as_mut());
+        let mnemonic = Zeroizing::new(Mnemonic::from_entropy(entropy.as_ref())?);
+
+        Ok(Self::Mnemonic(Zeroizing::new(mnemonic.to_string())))
+    }
+
+    pub fn kind(&self) -> SecretKind {
+        match self {
+            Self::Mnemonic(_) => SecretKind::Mnemonic,
+            Self::Xpriv(_) => SecretKind::Xpriv,
+        }
+    }
+
+    pub fn account(&self, secp: &Secp256k1<All>) -> Result<Account> {
+        match self {
+            Self::Mnemonic(mnemonic) => Account::from_mnemonic(secp, mnemonic),
+            Self::Xpriv(xpriv) => {
+                Account::from_master_key(secp, SecretXpriv::new(*xpriv.expose_secret()))
+            }
+        }
+    }
+
+    fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        match self {
+            Self::Mnemonic(mnemonic) => Zeroizing::new(mnemonic.as_bytes().to_vec()),
+            Self::Xpriv(xpriv) => Zeroizing::new(xpriv.expose_secret().encode().to_vec()),
+        }
+    }
+
+    fn from_bytes(kind: SecretKind, bytes: &[u8]) -> Result<Self, String> {
+        match kind {
+            SecretKind::Mnemonic => Ok(Self::Mnemonic(Zeroizing::new(
+                std::str::from_utf8(bytes)
+                    .map_err(|err| err.to_string())?
+                    .to_string(),
+            ))),
+            SecretKind::Xpriv => Ok(Self::Xpriv(SecretXpriv::new(
+                Xpriv::decode(bytes).map_err(|err| err.to_string())?,
+            ))),
+        }
+    }
+}
+
+impl EncryptedSecret {
+    fn seal(
+        plaintext: &[u8],
+        password: &str,
+        name: &str,
+        xpub: &Xpub,
+        kdf: KdfParams,
+    ) -> Result<Self> {
+        let mut salt = vec![0; SALT_LEN];
+        let mut nonce = vec![0; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let key = derive_key(password, &salt, kdf)?;
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &xpub.encode(),
+                },
+            )
+            .map_err(|_| KeyError::Encryption {
+                name: name.to_string(),
+            })?;
+
+        Ok(Self {
+            kdf,
+            salt,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    fn open(&self, password: &str, name: &str, xpub: &Xpub) -> Result<Zeroizing<Vec<u8>>> {
+        if self.nonce.len() != NONCE_LEN {
+            return Err(KeyError::CorruptedAccount {
+                name: name.to_string(),
+                reason: format!("invalid nonce length {}", self.nonce.len()),
+            }
+            .into());
+        }
+
+        let key = derive_key(password, &self.salt, self.kdf)?;
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+            .decrypt(
+                Nonce::from_slice(&self.nonce),
+                Payload {
+                    msg: &self.ciphertext,
+                    aad: &xpub.encode(),
+                },
+            )
+            .map_err(|_| KeyError::WrongPassword {
+                name: name.to_string(),
+            })?;
+
+        Ok(Zeroizing::new(plaintext))
+    }
+}
+
+fn derive_key(password: &str, salt: &[u8], kdf: KdfParams) -> Result<Zeroizing<[u8; KEY_LEN]>> {
+    let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(KEY_LEN))
+        .map_err(|err| KeyError::Kdf(format!("invalid parameters: {err}")))?;
+    let mut key = Zeroizing::new([0; KEY_LEN]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, key.as_mut())
+        .map_err(|err| KeyError::Kdf(err.to_string()))?;
+
+    Ok(key)
+}
+
+/// Serialize byte fields as hex strings
+mod hex_bytes {
+    use serde::{Deserialize as _, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        hex::decode(hex).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SENDER_ADDRESS_MNEMONIC;
+
+    /// Cheap parameters so tests do not spend seconds in argon2
+    const TEST_KDF: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn test_should_encrypt_accounts_and_change_password() {
+        let secp = Secp256k1::new();
+        let mut keystore = Keystore {
+            kdf: TEST_KDF,
+            ..Default::default()
+        };
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let secret = KeystoreSecret::parse(SENDER_ADDRESS_MNEMONIC).unwrap();
+        keystore.add(&secp, "sender", &secret, "hunter2").unwrap();
+        let xpriv = KeystoreSecret::parse(&sender.private_key.expose_secret().to_string()).unwrap();
+        keystore.add(&secp, "xpriv", &xpriv, "hunter2").unwrap();
+        assert!(keystore.add(&secp, "sender", &secret, "hunter2").is_err());
+        // a second password would lock the keystore out of `change_password`
+        assert!(matches!(
+            keystore.add(&secp, "other", &secret, "hunter3"),
+            Err(crate::error::Error::Key(KeyError::WrongPassword { .. }))
+        ));
+
+        // the secret is not stored in plaintext, the metadata is
+        let json = serde_json::to_string(&keystore).unwrap();
+        assert!(!json.contains("educate"));
+        assert!(json.contains(&sender.input_xpub.to_string()));
+
+        let path = std::env::temp_dir().join(format!("keystore-{}.json", std::process::id()));
+        keystore.save(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let mut keystore = Keystore::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let entry = keystore.entry("sender").unwrap();
+        assert_eq!(entry.kind, SecretKind::Mnemonic);
+        assert_eq!(entry.fingerprint, sender.private_key.fingerprint(&secp));
+        let account = keystore.account(&secp, "sender", "hunter2").unwrap();
+        assert_eq!(account.address, sender.address);
+        let account = keystore.account(&secp, "xpriv", "hunter2").unwrap();
+        assert_eq!(account.address, sender.address);
+        assert!(keystore.account(&secp, "sender", "hunter3").is_err());
+
+        keystore
+            .change_password("hunter2", "correct horse")
+            .unwrap();
+        assert!(keystore.account(&secp, "sender", "hunter2").is_err());
+        let account = keystore.account(&secp, "xpriv", "correct horse").unwrap();
+        assert_eq!(account.address, sender.address);
+        assert!(keystore.change_password("hunter2", "other").is_err());
+    }
+}
@@ -0,0 +1,41 @@
+#[macro_use]
+extern crate log;
+
+pub mod account;
+pub mod descriptor;
+pub mod error;
+pub mod escrow;
+pub mod external_signer;
+pub mod fee;
+pub mod inspect;
+pub mod interpreter;
+pub mod key_provider;
+pub mod keystore;
+pub mod marketplace;
+pub mod offline;
+pub mod p2wsh;
+pub mod policy;
+pub mod psbt;
+pub mod psbt_v2;
+pub mod rpc_client;
+pub mod secret;
+pub mod signer;
+pub mod signer_backend;
+pub mod taproot;
+pub mod utils;
+pub mod verify;
+
+pub use crate::account::Account;
+
+#[cfg(test)]
+/// tb1qzc8dhpkg5e4t6xyn4zmexxljc4nkje59dg3ark
+const SENDER_ADDRESS_MNEMONIC: &str =
+    "educate loyal echo sphere near family potato proud fresh still hub address";
+#[cfg(test)]
+/// tb1qax89amll2uas5k92tmuc8rdccmqddqw94vrr86
+const RECIPIENT_ADDRESS_MNEMONIC: &str =
+    "yard arctic apart velvet virus flight lemon cable ozone pole course awake";
+#[cfg(test)]
+/// tb1qcwflhw3252daxhj6d40wxpuard5c05lzqptdx7
+const MARKETPLACE_ADDRESS_MNEMONIC: &str =
+    "position goat expect abandon mesh response champion list praise broccoli orange pole";
@@ -1,81 +1,265 @@
 #[macro_use]
 extern crate log;
 
-mod psbt;
-mod rpc_client;
-mod signer;
-mod taproot;
-mod utils;
-
+use std::io::{self, BufRead, Write as _};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use bip39::Mnemonic;
+use argh::FromArgs;
 use bitcoin::absolute::LockTime;
-use bitcoin::bip32::ChildNumber;
-use bitcoin::bip32::DerivationPath;
-use bitcoin::bip32::Xpriv;
-use bitcoin::bip32::Xpub;
-use bitcoin::opcodes::all::{OP_CHECKSIG, OP_ENDIF, OP_IF};
-use bitcoin::opcodes::{OP_0, OP_FALSE};
-use bitcoin::script::Builder as ScriptBuilder;
 use bitcoin::transaction::Version;
 use bitcoin::{
     secp256k1::{All, Secp256k1},
-    Address, Amount, PublicKey, Txid,
+    Address, Amount, FeeRate, PrivateKey, Txid,
 };
 use bitcoin::{Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
-use ord_rs::Inscription as _;
-use ord_rs::{transaction::TxInput, OrdError};
-
-use crate::utils::bytes_to_push_bytes;
-
-/// tb1qzc8dhpkg5e4t6xyn4zmexxljc4nkje59dg3ark
-const SENDER_ADDRESS_MNEMONIC: &str =
-    "educate loyal echo sphere near family potato proud fresh still hub address";
-/// tb1qax89amll2uas5k92tmuc8rdccmqddqw94vrr86
-const RECIPIENT_ADDRESS_MNEMONIC: &str =
-    "yard arctic apart velvet virus flight lemon cable ozone pole course awake";
-/// tb1qcwflhw3252daxhj6d40wxpuard5c05lzqptdx7
-const MARKETPLACE_ADDRESS_MNEMONIC: &str =
-    "position goat expect abandon mesh response champion list praise broccoli orange pole";
-
-const COMMIT_FEE: u64 = 2_500;
-const REVEAL_FEE: u64 = 4_700;
+use ord_rs::transaction::TxInput;
+use ord_rs::Inscription;
+use zeroize::Zeroizing;
+
+use psbt::account::{Account, AccountUtxo, WatchOnlyAccount};
+use psbt::descriptor::{Descriptor, DescriptorKind};
+use psbt::error::FeeError;
+use psbt::fee::FeePolicy;
+use psbt::keystore::{Keystore, KeystoreSecret};
+use psbt::offline::SigningBundle;
+use psbt::secret::SecretPrivateKey;
+use psbt::utils::inscription_script;
+use psbt::{inspect, interpreter, p2wsh, rpc_client, signer, taproot};
+
+/// Keystore file used when no `--keystore` is given
+const DEFAULT_KEYSTORE: &str = "keystore.json";
+
+/// Commit and reveal fee rate in sat/vB used when no `--fee-rate` is given
+const DEFAULT_FEE_RATE: u64 = 10;
 const POSTAGE: u64 = 333;
 
-#[derive(Debug, Clone)]
-pub struct Account {
-    pub address: Address,
-    pub public_key: PublicKey,
-    pub private_key: Xpriv,
-    pub input_xpub: Xpub,
-    pub path: DerivationPath,
-}
-
-impl Account {
-    pub fn from_mnemonic(secp: &Secp256k1<All>, mnemonic: &str) -> anyhow::Result<Self> {
-        let mnemonic = Mnemonic::from_str(mnemonic)?;
-        let seed = mnemonic.to_seed("");
-        let root = Xpriv::new_master(Network::Testnet, &seed)?;
-
-        // derive child xpub
-        let path = DerivationPath::from_str("m/84h/0h/0h")?;
-        let child = root.derive_priv(&secp, &path)?;
-        let xpub = Xpub::from_priv(&secp, &child);
-
-        let zero = ChildNumber::from_normal_idx(0)?;
-        let public_key = xpub.derive_pub(&secp, &[zero, zero])?.public_key;
-
-        let public_key = PublicKey::new(public_key);
-        let address = Address::p2wpkh(&public_key, Network::Testnet)?;
-
-        Ok(Self {
-            address,
-            public_key,
-            private_key: root,
-            input_xpub: xpub,
-            path,
-        })
+#[derive(FromArgs)]
+/// Inscribe BRC-20 tokens through PSBTs
+struct Args {
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Inspect(InspectArgs),
+    Bundle(BundleArgs),
+    SignBundle(SignBundleArgs),
+    BroadcastBundle(BroadcastBundleArgs),
+    Inscribe(InscribeArgs),
+    Keystore(KeystoreArgs),
+}
+
+#[derive(FromArgs)]
+/// Decode and inspect a PSBT (base64 or hex) or a raw transaction (hex)
+#[argh(subcommand, name = "inspect")]
+struct InspectArgs {
+    /// PSBT or transaction to inspect
+    #[argh(positional)]
+    document: String,
+    /// print the report as JSON
+    #[argh(switch)]
+    json: bool,
+    /// network used to render addresses
+    #[argh(option, default = "Network::Testnet")]
+    network: Network,
+}
+
+#[derive(FromArgs)]
+/// Build the unsigned commit and reveal bundle of a BRC-20 deploy from a watch-only account
+#[argh(subcommand, name = "bundle")]
+struct BundleArgs {
+    /// wpkh descriptor of the funding account, e.g. `wpkh([fingerprint/84h/0h/0h]tpub.../0/*)`
+    #[argh(option)]
+    descriptor: String,
+    /// address receiving the inscription
+    #[argh(option)]
+    recipient: String,
+    /// ticker to deploy
+    #[argh(option)]
+    tick: String,
+    /// maximum supply
+    #[argh(option)]
+    max: u64,
+    /// mint limit
+    #[argh(option)]
+    limit: Option<u64>,
+    /// commit and reveal fee rate in sat/vB
+    #[argh(option, default = "DEFAULT_FEE_RATE")]
+    fee_rate: u64,
+    /// file to write the bundle to
+    #[argh(option)]
+    output: PathBuf,
+}
+
+#[derive(FromArgs)]
+/// Verify and sign the commit of a bundle with a keystore account, or a mnemonic read from stdin
+#[argh(subcommand, name = "sign-bundle")]
+struct SignBundleArgs {
+    /// bundle file, signed in place
+    #[argh(positional)]
+    bundle: PathBuf,
+    /// keystore holding the signing account
+    #[argh(option)]
+    keystore: Option<PathBuf>,
+    /// name of the signing account in the keystore
+    #[argh(option)]
+    account: Option<String>,
+    /// sign without asking for confirmation
+    #[argh(switch)]
+    yes: bool,
+}
+
+#[derive(FromArgs)]
+/// Finalize a signed bundle and broadcast the commit and the reveal
+#[argh(subcommand, name = "broadcast-bundle")]
+struct BroadcastBundleArgs {
+    /// signed bundle file
+    #[argh(positional)]
+    bundle: PathBuf,
+    /// refuse transactions paying a fee above this amount in sats
+    #[argh(option)]
+    max_fee: Option<u64>,
+    /// refuse transactions paying a fee rate above this in sat/vB (default 25000)
+    #[argh(option)]
+    max_fee_rate: Option<u64>,
+    /// refuse transactions whose fee exceeds this percentage of the spent amount
+    #[argh(option)]
+    max_inputs_percent: Option<u8>,
+    /// extract the transactions even when their fee exceeds the limits above
+    #[argh(switch)]
+    allow_excessive_fee: bool,
+}
+
+#[derive(FromArgs)]
+/// Inscribe a BRC-20 deploy from the sender account
+#[argh(subcommand, name = "inscribe")]
+struct InscribeArgs {
+    /// output locking the inscription in the commit: `p2tr` (default) or `p2wsh`;
+    /// ord only indexes tapscript envelopes, so a `p2wsh` inscription is not recognised
+    #[argh(option, default = "CommitMode::Taproot")]
+    commit_mode: CommitMode,
+    /// keystore holding the `sender`, `recipient` and `marketplace` accounts
+    #[argh(option, default = "PathBuf::from(DEFAULT_KEYSTORE)")]
+    keystore: PathBuf,
+    /// commit and reveal fee rate in sat/vB
+    #[argh(option, default = "DEFAULT_FEE_RATE")]
+    fee_rate: u64,
+    /// grind ECDSA signatures to a low R value, saving a byte per signature
+    #[argh(switch)]
+    low_r: bool,
+    /// add fresh auxiliary randomness to Schnorr signatures
+    #[argh(switch)]
+    aux_rand: bool,
+    /// refuse transactions paying a fee above this amount in sats
+    #[argh(option)]
+    max_fee: Option<u64>,
+    /// refuse transactions paying a fee rate above this in sat/vB (default 25000)
+    #[argh(option)]
+    max_fee_rate: Option<u64>,
+    /// refuse transactions whose fee exceeds this percentage of the spent amount
+    #[argh(option)]
+    max_inputs_percent: Option<u8>,
+    /// extract the transactions even when their fee exceeds the limits above
+    #[argh(switch)]
+    allow_excessive_fee: bool,
+}
+
+impl Default for InscribeArgs {
+    fn default() -> Self {
+        Self {
+            commit_mode: CommitMode::Taproot,
+            keystore: PathBuf::from(DEFAULT_KEYSTORE),
+            fee_rate: DEFAULT_FEE_RATE,
+            low_r: false,
+            aux_rand: false,
+            max_fee: None,
+            max_fee_rate: None,
+            max_inputs_percent: None,
+            allow_excessive_fee: false,
+        }
+    }
+}
+
+#[derive(FromArgs)]
+/// Manage the password-encrypted keystore of mnemonics and xprvs
+#[argh(subcommand, name = "keystore")]
+struct KeystoreArgs {
+    /// keystore file
+    #[argh(option, default = "PathBuf::from(DEFAULT_KEYSTORE)")]
+    path: PathBuf,
+    #[argh(subcommand)]
+    command: KeystoreCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum KeystoreCommand {
+    Create(KeystoreCreateArgs),
+    Import(KeystoreImportArgs),
+    List(KeystoreListArgs),
+    ExportXpub(KeystoreExportXpubArgs),
+    ChangePassword(KeystoreChangePasswordArgs),
+}
+
+#[derive(FromArgs)]
+/// Generate a new mnemonic account, printed once for backup
+#[argh(subcommand, name = "create")]
+struct KeystoreCreateArgs {
+    /// account name
+    #[argh(positional)]
+    name: String,
+}
+
+#[derive(FromArgs)]
+/// Import a mnemonic or a master xprv, read from stdin
+#[argh(subcommand, name = "import")]
+struct KeystoreImportArgs {
+    /// account name
+    #[argh(positional)]
+    name: String,
+}
+
+#[derive(FromArgs)]
+/// List the accounts of the keystore
+#[argh(subcommand, name = "list")]
+struct KeystoreListArgs {}
+
+#[derive(FromArgs)]
+/// Print the xpub and the wpkh descriptor of an account
+#[argh(subcommand, name = "export-xpub")]
+struct KeystoreExportXpubArgs {
+    /// account name
+    #[argh(positional)]
+    name: String,
+}
+
+#[derive(FromArgs)]
+/// Re-encrypt all the accounts under a new password
+#[argh(subcommand, name = "change-password")]
+struct KeystoreChangePasswordArgs {}
+
+/// Output type the commit locks the inscription envelope into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommitMode {
+    Taproot,
+    /// Reveals the envelope in a P2WSH witness, which ord and other indexers ignore
+    P2wsh,
+}
+
+impl FromStr for CommitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "p2tr" | "taproot" => Ok(Self::Taproot),
+            "p2wsh" => Ok(Self::P2wsh),
+            _ => Err(format!(
+                "unknown commit mode `{s}`, expected `p2tr` or `p2wsh`"
+            )),
+        }
     }
 }
 
@@ -83,11 +267,520 @@ impl Account {
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
 
+    let args: Args = argh::from_env();
+    match args.command {
+        Some(Command::Inspect(args)) => inspect(args),
+        Some(Command::Bundle(args)) => bundle(args).await,
+        Some(Command::SignBundle(args)) => sign_bundle(args),
+        Some(Command::BroadcastBundle(args)) => broadcast_bundle(args).await,
+        Some(Command::Inscribe(args)) => inscribe(args).await,
+        Some(Command::Keystore(args)) => keystore(args),
+        None => inscribe(InscribeArgs::default()).await,
+    }
+}
+
+fn inspect(args: InspectArgs) -> anyhow::Result<()> {
+    let report = inspect::decode(&args.document, args.network)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{report}");
+    }
+
+    Ok(())
+}
+
+async fn bundle(args: BundleArgs) -> anyhow::Result<()> {
+    let secp = Secp256k1::new();
+    let account = WatchOnlyAccount::from_descriptor(&secp, &args.descriptor)?;
+    let network = *account.address.network();
+    let recipient = Address::from_str(&args.recipient)?.require_network(network)?;
+
+    let inscription = ord_rs::brc20::Brc20::deploy(&args.tick, args.max, args.limit, None);
+    let (p2tr_keypair, p2tr_pubkey) = taproot::generate_keypair(&secp);
+    let redeem_script = inscription_script(&p2tr_pubkey.serialize(), &inscription)?;
+    let mut taproot_payload = taproot::TaprootPayload::build(
+        &secp,
+        p2tr_keypair,
+        p2tr_pubkey,
+        &redeem_script,
+        POSTAGE,
+        network,
+    )?;
+
+    // the commit output funds the postage and the fee of the reveal
+    let fee_rate = fee_rate(args.fee_rate)?;
+    let spent = signer::SpentOutput::new(taproot_payload.prevouts.clone())
+        .with_tap_leaf(redeem_script.clone(), taproot_payload.control_block.clone());
+    let options = SignatureOptions {
+        low_r: false,
+        aux_rand: false,
+    };
+    let reveal_fee = estimate_reveal_fee(&secp, spent, &recipient, fee_rate, options);
+    let reveal_balance = Amount::from_sat(POSTAGE) + reveal_fee;
+    taproot_payload.prevouts.value = reveal_balance;
+
+    // pick UTXOs until the commit and its fee are funded
+    let mut inputs = Vec::new();
+    let mut commit_fee = Amount::ZERO;
+    for utxo in account.discover_utxos(&secp).await? {
+        inputs.push(utxo);
+        commit_fee = estimate_commit_fee(&secp, &account, &inputs, &taproot_payload, fee_rate);
+        let available = inputs.iter().map(|utxo| utxo.input.amount).sum::<Amount>();
+        if available >= reveal_balance + commit_fee {
+            break;
+        }
+    }
+
+    let bundle = SigningBundle::build(
+        &secp,
+        &account,
+        &inputs,
+        &taproot_payload,
+        &redeem_script,
+        &recipient,
+        Amount::from_sat(POSTAGE),
+        commit_fee,
+    )?;
+    std::fs::write(&args.output, serde_json::to_string_pretty(&bundle)?)?;
+    println!("Bundle written to {}", args.output.display());
+
+    Ok(())
+}
+
+fn sign_bundle(args: SignBundleArgs) -> anyhow::Result<()> {
+    let secp = Secp256k1::new();
+    let mut bundle: SigningBundle = serde_json::from_str(&std::fs::read_to_string(&args.bundle)?)?;
+
+    let mut stdin = io::stdin().lock();
+    let account = match (&args.keystore, &args.account) {
+        (Some(path), Some(name)) => {
+            let password = read_secret(&mut stdin, "password")?;
+            Keystore::load(path)?.account(&secp, name, &password)?
+        }
+        (None, None) => Account::from_mnemonic(&secp, &read_secret(&mut stdin, "mnemonic")?)?,
+        _ => anyhow::bail!("--keystore and --account must be given together"),
+    };
+
+    println!("{}", bundle.verify(&secp, &account)?);
+    if !args.yes {
+        eprint!("sign? [y/N] ");
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        stdin.read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            anyhow::bail!("signing aborted");
+        }
+    }
+
+    bundle.sign(&secp, &account)?;
+    std::fs::write(&args.bundle, serde_json::to_string_pretty(&bundle)?)?;
+    println!("Bundle signed");
+
+    Ok(())
+}
+
+async fn broadcast_bundle(args: BroadcastBundleArgs) -> anyhow::Result<()> {
+    let bundle: SigningBundle = serde_json::from_str(&std::fs::read_to_string(&args.bundle)?)?;
+    let network = bundle.network;
+    let fee_policy = fee_policy(
+        args.max_fee,
+        args.max_fee_rate,
+        args.max_inputs_percent,
+        args.allow_excessive_fee,
+    )?;
+    let (commit_tx, reveal_tx) = bundle.finalize(&Secp256k1::new(), &fee_policy)?;
+
+    let txid = rpc_client::broadcast_transaction(&commit_tx, network).await?;
+    rpc_client::wait_for_tx(&txid, network).await?;
+    println!("Commit tx: {txid}");
+
+    let txid = rpc_client::broadcast_transaction(&reveal_tx, network).await?;
+    println!("Reveal tx: {txid}");
+
+    Ok(())
+}
+
+fn keystore(args: KeystoreArgs) -> anyhow::Result<()> {
+    let secp = Secp256k1::new();
+    let mut keystore = Keystore::load(&args.path)?;
+    let mut stdin = io::stdin().lock();
+
+    match args.command {
+        KeystoreCommand::Create(create) => {
+            let secret = KeystoreSecret::generate_mnemonic()?;
+            let password = read_keystore_password(&keystore, &mut stdin)?;
+            let entry = keystore.add(&secp, &create.name, &secret, &password)?;
+            println!("Account {} created: {}", entry.name, entry.xpub);
+            if let KeystoreSecret::Mnemonic(mnemonic) = &secret {
+                println!("Write down the mnemonic, it will not be shown again:");
+                println!("{}", mnemonic.as_str());
+            }
+        }
+        KeystoreCommand::Import(import) => {
+            let secret = KeystoreSecret::parse(&read_secret(&mut stdin, "mnemonic or xprv")?)?;
+            let password = read_keystore_password(&keystore, &mut stdin)?;
+            let entry = keystore.add(&secp, &import.name, &secret, &password)?;
+            println!("Account {} imported: {}", entry.name, entry.xpub);
+        }
+        KeystoreCommand::List(_) => {
+            for entry in &keystore.accounts {
+                println!(
+                    "{}\t{:?}\t{}\t[{}/{}]\t{}",
+                    entry.name,
+                    entry.kind,
+                    entry.network,
+                    entry.fingerprint,
+                    entry.path,
+                    entry.xpub
+                );
+            }
+            return Ok(());
+        }
+        KeystoreCommand::ExportXpub(export) => {
+            let entry = keystore.entry(&export.name)?;
+            println!("{}", entry.xpub);
+            println!(
+                "{}",
+                Descriptor::new(
+                    DescriptorKind::Wpkh,
+                    entry.fingerprint,
+                    entry.path.clone(),
+                    entry.xpub
+                )
+            );
+            return Ok(());
+        }
+        KeystoreCommand::ChangePassword(_) => {
+            let old_password = read_secret(&mut stdin, "current password")?;
+            let new_password = read_new_password(&mut stdin)?;
+            keystore.change_password(&old_password, &new_password)?;
+            println!("Password changed");
+        }
+    }
+
+    keystore.save(&args.path)?;
+
+    Ok(())
+}
+
+/// Read a line from stdin after printing `prompt` on stderr
+fn read_secret(stdin: &mut impl BufRead, prompt: &str) -> anyhow::Result<Zeroizing<String>> {
+    eprint!("{prompt}: ");
+    io::stderr().flush()?;
+    let mut line = Zeroizing::new(String::new());
+    stdin.read_line(&mut line)?;
+
+    Ok(Zeroizing::new(line.trim().to_string()))
+}
+
+/// Read the password of `keystore`, or a new one for an empty keystore
+fn read_keystore_password(
+    keystore: &Keystore,
+    stdin: &mut impl BufRead,
+) -> anyhow::Result<Zeroizing<String>> {
+    if keystore.accounts.is_empty() {
+        read_new_password(stdin)
+    } else {
+        read_secret(stdin, "password")
+    }
+}
+
+/// Read a new password twice
+fn read_new_password(stdin: &mut impl BufRead) -> anyhow::Result<Zeroizing<String>> {
+    let password = read_secret(stdin, "new password")?;
+    if password.is_empty() {
+        anyhow::bail!("the password must not be empty");
+    }
+    if read_secret(stdin, "confirm password")? != password {
+        anyhow::bail!("passwords do not match");
+    }
+
+    Ok(password)
+}
+
+/// Fee rate of `sat_per_vb`
+fn fee_rate(sat_per_vb: u64) -> anyhow::Result<FeeRate> {
+    FeeRate::from_sat_per_vb(sat_per_vb)
+        .ok_or_else(|| anyhow::anyhow!("fee rate of {sat_per_vb} sat/vB is too high"))
+}
+
+/// Fee policy of the `--max-fee`, `--max-fee-rate`, `--max-inputs-percent` and
+/// `--allow-excessive-fee` options, on top of the default fee rate cap
+fn fee_policy(
+    max_fee: Option<u64>,
+    max_fee_rate: Option<u64>,
+    max_inputs_percent: Option<u8>,
+    allow_excessive_fee: bool,
+) -> anyhow::Result<FeePolicy> {
+    let mut policy = FeePolicy::default();
+    if let Some(max_fee) = max_fee {
+        policy = policy.with_max_fee(Amount::from_sat(max_fee));
+    }
+    if let Some(max_fee_rate) = max_fee_rate {
+        policy = policy.with_max_fee_rate(fee_rate(max_fee_rate)?);
+    }
+    if let Some(max_percent) = max_inputs_percent {
+        policy = policy.with_max_inputs_percent(max_percent);
+    }
+    if allow_excessive_fee {
+        policy = policy.allow_excessive_fee();
+    }
+
+    Ok(policy)
+}
+
+/// Fee of the commit spending the P2WPKH `inputs` of `account` into the payload and the change
+fn estimate_commit_fee(
+    secp: &Secp256k1<All>,
+    account: &WatchOnlyAccount,
+    inputs: &[AccountUtxo],
+    taproot_payload: &taproot::TaprootPayload,
+    fee_rate: FeeRate,
+) -> Amount {
+    let commit_tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: inputs
+            .iter()
+            .map(|utxo| TxIn {
+                previous_output: OutPoint::new(utxo.input.id, utxo.input.index),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: vec![
+            taproot_payload.prevouts.clone(),
+            TxOut {
+                value: Amount::ZERO,
+                script_pubkey: account.address.script_pubkey(),
+            },
+        ],
+    };
+    // only the witness size matters, which is the same for every address of the account
+    let spent = inputs
+        .iter()
+        .map(|utxo| {
+            signer::SpentOutput::new(TxOut {
+                value: utxo.input.amount,
+                script_pubkey: account.address.script_pubkey(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // a watch-only account has no keys, the signer only estimates the witness sizes
+    let no_keys: &[Account] = &[];
+    signer::Signer::with_keys(&no_keys, secp, commit_tx).estimate_fee(&spent, fee_rate)
+}
+
+/// Fee of the reveal spending the commit output `spent` into the postage of `recipient`
+fn estimate_reveal_fee(
+    secp: &Secp256k1<All>,
+    spent: signer::SpentOutput,
+    recipient: &Address,
+    fee_rate: FeeRate,
+    options: SignatureOptions,
+) -> Amount {
+    let reveal_tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(POSTAGE),
+            script_pubkey: recipient.script_pubkey(),
+        }],
+    };
+
+    // the reveal key is not needed to estimate the witness size
+    let no_keys: &[Account] = &[];
+    options
+        .apply(signer::Signer::with_keys(&no_keys, secp, reveal_tx))
+        .estimate_fee(&[spent], fee_rate)
+}
+
+/// Sender, recipient and marketplace accounts of `keystore`
+fn inscribe_accounts(
+    secp: &Secp256k1<All>,
+    path: &Path,
+) -> anyhow::Result<(Account, Account, Account)> {
+    let keystore = Keystore::load(path)?;
+    if keystore.accounts.is_empty() {
+        anyhow::bail!(
+            "no account in {}, add `sender`, `recipient` and `marketplace` with `keystore import`",
+            path.display()
+        );
+    }
+    let password = read_secret(&mut io::stdin().lock(), "password")?;
+    Ok((
+        keystore.account(secp, "sender", &password)?,
+        keystore.account(secp, "recipient", &password)?,
+        keystore.account(secp, "marketplace", &password)?,
+    ))
+}
+
+/// How the signatures of `inscribe` are produced
+#[derive(Debug, Clone, Copy)]
+struct SignatureOptions {
+    low_r: bool,
+    aux_rand: bool,
+}
+
+impl SignatureOptions {
+    fn apply(self, mut signer: signer::Signer<'_>) -> signer::Signer<'_> {
+        if self.low_r {
+            signer = signer.with_low_r();
+        }
+        if self.aux_rand {
+            signer = signer.with_aux_rand();
+        }
+        signer
+    }
+}
+
+/// Commit output holding the inscription envelope, with what is needed to sign its reveal
+enum RevealPayload {
+    Taproot {
+        payload: Box<taproot::TaprootPayload>,
+        redeem_script: ScriptBuf,
+    },
+    P2wsh(p2wsh::P2wshPayload),
+}
+
+impl RevealPayload {
+    /// Lock the envelope of `inscription` to a fresh key in an output of `commit_mode`
+    fn build(
+        secp: &Secp256k1<All>,
+        commit_mode: CommitMode,
+        inscription: &impl Inscription,
+        reveal_balance: u64,
+        network: Network,
+    ) -> anyhow::Result<Self> {
+        match commit_mode {
+            CommitMode::Taproot => {
+                let (p2tr_keypair, p2tr_pubkey) = taproot::generate_keypair(secp);
+                let redeem_script = inscription_script(&p2tr_pubkey.serialize(), inscription)?;
+                let payload = taproot::TaprootPayload::build(
+                    secp,
+                    p2tr_keypair,
+                    p2tr_pubkey,
+                    &redeem_script,
+                    reveal_balance,
+                    network,
+                )?;
+                Ok(Self::Taproot {
+                    payload: Box::new(payload),
+                    redeem_script,
+                })
+            }
+            CommitMode::P2wsh => {
+                let private_key = p2wsh::generate_private_key(secp, network);
+                let redeem_script =
+                    inscription_script(&private_key.public_key(secp).to_bytes(), inscription)?;
+                Ok(Self::P2wsh(p2wsh::P2wshPayload::build(
+                    private_key,
+                    &redeem_script,
+                    reveal_balance,
+                    network,
+                )?))
+            }
+        }
+    }
+
+    fn redeem_script(&self) -> &ScriptBuf {
+        match self {
+            Self::Taproot { redeem_script, .. } => redeem_script,
+            Self::P2wsh(payload) => &payload.redeem_script,
+        }
+    }
+
+    /// Commit output spent by the reveal
+    fn prevout(&self) -> &TxOut {
+        match self {
+            Self::Taproot { payload, .. } => &payload.prevouts,
+            Self::P2wsh(payload) => &payload.prevouts,
+        }
+    }
+
+    /// Commit output with the script its reveal witness spends it through
+    fn spent_output(&self) -> signer::SpentOutput {
+        let spent = signer::SpentOutput::new(self.prevout().clone());
+        match self {
+            Self::Taproot {
+                payload,
+                redeem_script,
+            } => spent.with_tap_leaf(redeem_script.clone(), payload.control_block.clone()),
+            Self::P2wsh(payload) => spent.with_witness_script(payload.redeem_script.clone()),
+        }
+    }
+
+    /// Lock `value` in the commit output
+    fn set_value(&mut self, value: Amount) {
+        match self {
+            Self::Taproot { payload, .. } => payload.prevouts.value = value,
+            Self::P2wsh(payload) => payload.prevouts.value = value,
+        }
+    }
+
+    /// Sign `reveal_tx`, spending the first output of the commit `txid`
+    fn sign_reveal(
+        &self,
+        secp: &Secp256k1<All>,
+        reveal_tx: Transaction,
+        txid: Txid,
+        fee_policy: &FeePolicy,
+        options: SignatureOptions,
+    ) -> anyhow::Result<Transaction> {
+        match self {
+            Self::Taproot {
+                payload,
+                redeem_script,
+            } => {
+                let mut reveal_psbt = psbt::psbt::reveal_psbt(reveal_tx, payload, redeem_script)?;
+                let reveal_key = SecretPrivateKey::new(PrivateKey::new(
+                    payload.keypair.secret_key(),
+                    Network::Testnet,
+                ));
+                let mut signer = options.apply(signer::Signer::new(
+                    &reveal_key,
+                    secp,
+                    reveal_psbt.unsigned_tx.clone(),
+                ));
+                signer.sign_psbt_tap_script(&mut reveal_psbt)?;
+                Ok(psbt::psbt::finalize_reveal(reveal_psbt, fee_policy)?)
+            }
+            Self::P2wsh(payload) => {
+                let mut signer =
+                    options.apply(signer::Signer::new(&payload.private_key, secp, reveal_tx));
+                Ok(signer.sign_reveal_transaction_ecdsa(
+                    &payload.reveal_input(txid, 0),
+                    &payload.redeem_script,
+                )?)
+            }
+        }
+    }
+}
+
+async fn inscribe(args: InscribeArgs) -> anyhow::Result<()> {
     let secp = Secp256k1::new();
+    let fee_rate = fee_rate(args.fee_rate)?;
+    let fee_policy = fee_policy(
+        args.max_fee,
+        args.max_fee_rate,
+        args.max_inputs_percent,
+        args.allow_excessive_fee,
+    )?;
+    let options = SignatureOptions {
+        low_r: args.low_r,
+        aux_rand: args.aux_rand,
+    };
     // setup accounts
-    let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC)?;
-    let recipient = Account::from_mnemonic(&secp, RECIPIENT_ADDRESS_MNEMONIC)?;
-    let marketplace = Account::from_mnemonic(&secp, MARKETPLACE_ADDRESS_MNEMONIC)?;
+    let (sender, recipient, marketplace) = inscribe_accounts(&secp, &args.keystore)?;
 
     debug!("sender: {}", sender.address);
     debug!("recipient: {}", recipient.address);
@@ -100,57 +793,38 @@ async fn main() -> anyhow::Result<()> {
         index: 0,
         amount: Amount::from_sat(8_000),
     };
+    let commit_prevouts = [TxOut {
+        value: tx_input.amount,
+        script_pubkey: sender.address.script_pubkey(),
+    }];
+    let spent = [signer::SpentOutput::new(commit_prevouts[0].clone())];
     // inscription
     let inscription = ord_rs::brc20::Brc20::deploy("omar", 8_888_000, Some(1_000), None);
 
-    // prepare commit
-    let (p2tr_keypair, p2tr_pubkey) = taproot::generate_keypair(&secp);
-
-    // calc balance
-    // exceeding amount of transaction to send to leftovers recipient
-    let leftover_amount = tx_input
-        .amount
-        .to_sat()
-        .checked_sub(POSTAGE)
-        .and_then(|v| v.checked_sub(COMMIT_FEE))
-        .and_then(|v| v.checked_sub(REVEAL_FEE))
-        .ok_or(OrdError::InsufficientBalance)?;
-    debug!("leftover_amount: {leftover_amount}");
-
-    let reveal_balance = POSTAGE + REVEAL_FEE;
-
-    // prepare redeem script
-    let redeem_script = ScriptBuilder::new()
-        .push_slice(bytes_to_push_bytes(&p2tr_pubkey.serialize())?.as_push_bytes())
-        .push_opcode(OP_CHECKSIG)
-        .push_opcode(OP_FALSE)
-        .push_opcode(OP_IF)
-        .push_slice(b"ord")
-        .push_slice(b"\x01")
-        .push_slice(bytes_to_push_bytes(inscription.content_type().as_bytes())?.as_push_bytes())
-        .push_opcode(OP_0)
-        .push_slice(inscription.data()?.as_push_bytes())
-        .push_opcode(OP_ENDIF)
-        .into_script();
-
-    // make taproot payload
-    let taproot_payload = taproot::TaprootPayload::build(
+    // prepare the commit output locking the redeem script, funding the postage and the reveal fee
+    let mut reveal_payload = RevealPayload::build(
         &secp,
-        p2tr_keypair,
-        p2tr_pubkey,
-        &redeem_script,
-        reveal_balance,
+        args.commit_mode,
+        &inscription,
+        POSTAGE,
         Network::Testnet,
     )?;
+    let reveal_fee = estimate_reveal_fee(
+        &secp,
+        reveal_payload.spent_output(),
+        &recipient.address,
+        fee_rate,
+        options,
+    );
+    let reveal_balance = Amount::from_sat(POSTAGE) + reveal_fee;
+    reveal_payload.set_value(reveal_balance);
+    debug!("reveal_fee: {reveal_fee}");
 
-    // make txout
+    // make txout, the leftovers are set once the fee is known
     let tx_out = vec![
+        reveal_payload.prevout().clone(),
         TxOut {
-            value: Amount::from_sat(reveal_balance),
-            script_pubkey: taproot_payload.address.script_pubkey(),
-        },
-        TxOut {
-            value: Amount::from_sat(leftover_amount),
+            value: Amount::ZERO,
             script_pubkey: sender.address.script_pubkey(),
         },
     ];
@@ -166,31 +840,60 @@ async fn main() -> anyhow::Result<()> {
         witness: Witness::new(),
     }];
 
-    // make transaction and sign it
-    let unsigned_tx = Transaction {
+    let mut unsigned_tx = Transaction {
         version: Version::TWO,
         lock_time: LockTime::ZERO,
         input: tx_in,
         output: tx_out,
     };
-    let partially_signed_tx = psbt::sign_partially(
+
+    // calc balance
+    // exceeding amount of transaction to send to leftovers recipient
+    let commit_fee = options
+        .apply(signer::Signer::with_keys(
+            &sender,
+            &secp,
+            unsigned_tx.clone(),
+        ))
+        .estimate_fee(&spent, fee_rate);
+    let required = reveal_balance + commit_fee;
+    let leftover_amount =
+        tx_input
+            .amount
+            .checked_sub(required)
+            .ok_or(FeeError::InsufficientFunds {
+                purpose: "inscribe",
+                available: tx_input.amount,
+                required,
+            })?;
+    debug!("commit_fee: {commit_fee}, leftover_amount: {leftover_amount}");
+    unsigned_tx.output[1].value = leftover_amount;
+
+    // sign the transaction
+    let partially_signed_tx = psbt::psbt::sign_partially(
         &secp,
         unsigned_tx,
         &marketplace,
-        &[sender.clone(), recipient],
+        &[&sender, &recipient],
         TxOut {
             value: tx_input.amount,
             script_pubkey: sender.address.script_pubkey(),
         },
-        &redeem_script,
+        reveal_payload.redeem_script(),
+        signer::SighashType::All,
+        &fee_policy,
     )?;
     debug!("partially_signed_tx: {partially_signed_tx:?}");
 
     // sign
-    let sign_key = sender.private_key.to_priv();
-    let mut signer = signer::Signer::new(&sign_key, &secp, partially_signed_tx);
-    let signed_tx = signer.sign_commit_transaction(&[tx_input], &sender.address.script_pubkey())?;
+    let mut signer = options.apply(signer::Signer::with_keys(
+        &sender,
+        &secp,
+        partially_signed_tx,
+    ));
+    let signed_tx = signer.sign_transaction(&spent)?;
     debug!("signed_tx: {signed_tx:?}");
+    interpreter::verify_transaction(&secp, &signed_tx, &commit_prevouts).ensure_valid()?;
 
     // broadcast transaction
     let txid = rpc_client::broadcast_transaction(&signed_tx, Network::Testnet).await?;
@@ -198,7 +901,32 @@ async fn main() -> anyhow::Result<()> {
     println!("Commit tx: https://mempool.space/testnet/tx/{txid}");
 
     // make reveal and broadcast it
-    // ...
+    let reveal_tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint { txid, vout: 0 },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::from_consensus(0xffffffff),
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(POSTAGE),
+            script_pubkey: recipient.address.script_pubkey(),
+        }],
+    };
+    let signed_reveal_tx =
+        reveal_payload.sign_reveal(&secp, reveal_tx, txid, &fee_policy, options)?;
+    debug!("signed_reveal_tx: {signed_reveal_tx:?}");
+    interpreter::verify_transaction(
+        &secp,
+        &signed_reveal_tx,
+        std::slice::from_ref(reveal_payload.prevout()),
+    )
+    .ensure_valid()?;
+
+    let txid = rpc_client::broadcast_transaction(&signed_reveal_tx, Network::Testnet).await?;
+    println!("Reveal tx: https://mempool.space/testnet/tx/{txid}");
 
     Ok(())
 }
@@ -0,0 +1,478 @@
+use std::str::FromStr as _;
+
+use bitcoin::bip32::ChildNumber;
+use bitcoin::consensus::encode;
+use bitcoin::psbt::{raw, Input};
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::{
+    absolute::LockTime, transaction::Version, Amount, EcdsaSighashType, OutPoint, Psbt, ScriptBuf,
+    Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use ord_rs::transaction::TxInput;
+
+use crate::error::{FeeError, PsbtError, Result, SigningError};
+use crate::fee::FeePolicy;
+use crate::inspect::inspect_psbt;
+use crate::signer::SighashType;
+use crate::Account;
+
+/// Prefix of the proprietary PSBT keys used by the marketplace
+const PROPRIETARY_PREFIX: &[u8] = b"ordmkt";
+/// Proprietary subtype holding the marketplace fee output
+const FEE_TERMS_SUBTYPE: u8 = 0x00;
+
+/// Fee terms may take at most the whole price
+const MAX_BASIS_POINTS: u64 = 10_000;
+
+/// Number of dummy UTXOs placed before the inscription, so that the seller input and
+/// its `SIGHASH_SINGLE` payment output both end up at index 2
+const DUMMY_UTXOS: usize = 2;
+
+/// Fee the marketplace takes on a sale, expressed in basis points of the price.
+///
+/// The terms live in a proprietary field the seller signature does not commit to, so any
+/// relay of the listing can rewrite them: they are advisory only, and the buyer must check
+/// them against the terms the marketplace publishes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeTerms {
+    pub basis_points: u64,
+    pub script_pubkey: ScriptBuf,
+}
+
+/// Seller listing: a PSBT spending the inscription UTXO at input 0 and paying the asking
+/// price at output 0, signed with `SIGHASH_SINGLE|ANYONECANPAY` so that any buyer can
+/// add its own inputs and outputs around it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub psbt: Psbt,
+}
+
+impl FeeTerms {
+    /// Fee due on a sale at `price`
+    pub fn fee(&self, price: Amount) -> Result<Amount> {
+        if self.basis_points > MAX_BASIS_POINTS {
+            return Err(FeeError::ExcessiveMarketplaceFee {
+                basis_points: self.basis_points,
+            }
+            .into());
+        }
+        let fee = u128::from(price.to_sat()) * u128::from(self.basis_points)
+            / u128::from(MAX_BASIS_POINTS);
+
+        // at most the price, which fits
+        Ok(Amount::from_sat(fee as u64))
+    }
+}
+
+impl Listing {
+    /// Build and sign the listing for the inscription held by `seller` at `inscription_utxo`
+    pub fn new(
+        secp: &Secp256k1<All>,
+        seller: &Account,
+        inscription_utxo: &TxInput,
+        price: Amount,
+    ) -> Result<Self> {
+        let unsigned_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: inscription_utxo.id,
+                    vout: inscription_utxo.index,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_consensus(0xffffffff),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: price,
+                script_pubkey: seller.address.script_pubkey(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)?;
+
+        let zero = ChildNumber::from_normal_idx(0)?;
+        let mut input = Input {
+            witness_utxo: Some(TxOut {
+                value: inscription_utxo.amount,
+                script_pubkey: seller.address.script_pubkey(),
+            }),
+            sighash_type: Some(SighashType::SinglePlusAnyoneCanPay.ecdsa().into()),
+            ..Default::default()
+        };
+        input.bip32_derivation.insert(
+            seller.public_key.inner,
+            (
+                seller.private_key.fingerprint(secp),
+                seller.path.extend([zero, zero]),
+            ),
+        );
+        psbt.inputs = vec![input];
+
+        match psbt.sign(&seller.private_key, secp) {
+            Ok(keys) if keys.len() == 1 => {}
+            Ok(keys) => {
+                return Err(SigningError::KeyCount {
+                    expected: 1,
+                    signed: keys.len(),
+                }
+                .into())
+            }
+            Err((_, errors)) => return Err(SigningError::from_psbt(errors).into()),
+        }
+
+        // finalize the seller input right away, so the buyer only has to deal with its own
+        let input = &mut psbt.inputs[0];
+        let (pubkey, sig) = input
+            .partial_sigs
+            .pop_first()
+            .ok_or(PsbtError::InvalidListing("not signed"))?;
+        if sig.hash_ty != EcdsaSighashType::SinglePlusAnyoneCanPay {
+            return Err(
+                PsbtError::InvalidListing("not signed with SIGHASH_SINGLE|ANYONECANPAY").into(),
+            );
+        }
+        *input = Input {
+            witness_utxo: input.witness_utxo.take(),
+            final_script_witness: Some(Witness::p2wpkh(&sig, &pubkey.inner)),
+            ..Default::default()
+        };
+        debug!(
+            "listing psbt:\n{}",
+            inspect_psbt(&psbt, *seller.address.network())
+        );
+
+        Ok(Self { psbt })
+    }
+
+    /// Asking price
+    pub fn price(&self) -> Amount {
+        self.psbt.unsigned_tx.output[0].value
+    }
+
+    /// Inscription UTXO being sold
+    pub fn inscription_outpoint(&self) -> OutPoint {
+        self.psbt.unsigned_tx.input[0].previous_output
+    }
+
+    /// Let the marketplace attach its fee terms, paid to its address
+    pub fn attach_fee_terms(&mut self, marketplace: &Account, basis_points: u64) -> Result<()> {
+        if basis_points > MAX_BASIS_POINTS {
+            return Err(FeeError::ExcessiveMarketplaceFee { basis_points }.into());
+        }
+        let fee_terms = FeeTerms {
+            basis_points,
+            script_pubkey: marketplace.address.script_pubkey(),
+        };
+        let value = encode::serialize(&(fee_terms.basis_points, fee_terms.script_pubkey));
+        self.psbt.proprietary.insert(fee_terms_key(), value);
+
+        Ok(())
+    }
+
+    /// Fee terms attached by the marketplace, if any; see [`FeeTerms`] on trusting them
+    pub fn fee_terms(&self) -> Result<Option<FeeTerms>> {
+        let Some(value) = self.psbt.proprietary.get(&fee_terms_key()) else {
+            return Ok(None);
+        };
+        let (basis_points, script_pubkey) = encode::deserialize(value)?;
+        if basis_points > MAX_BASIS_POINTS {
+            return Err(FeeError::ExcessiveMarketplaceFee { basis_points }.into());
+        }
+
+        Ok(Some(FeeTerms {
+            basis_points,
+            script_pubkey,
+        }))
+    }
+
+    /// Export the listing as a base64 PSBT
+    pub fn export(&self) -> String {
+        self.psbt.to_string()
+    }
+
+    /// Import a listing exported by [`Listing::export`]
+    pub fn import(listing: &str) -> Result<Self> {
+        let psbt = Psbt::from_str(listing)?;
+        if psbt.unsigned_tx.input.len() != 1 || psbt.unsigned_tx.output.len() != 1 {
+            return Err(
+                PsbtError::InvalidListing("must have exactly one input and one output").into(),
+            );
+        }
+        if psbt.inputs[0].final_script_witness.is_none() {
+            return Err(PsbtError::InvalidListing("input is not signed").into());
+        }
+
+        Ok(Self { psbt })
+    }
+}
+
+/// Complete `listing` on behalf of `buyer` and sign the buyer inputs.
+///
+/// The resulting transaction is laid out as
+///
+/// | index | input          | output                    |
+/// |-------|----------------|---------------------------|
+/// | 0     | dummy UTXO     | dummies merged, to buyer  |
+/// | 1     | dummy UTXO     | inscription, to buyer     |
+/// | 2     | inscription    | seller payment            |
+/// | 3..   | buyer payment  | marketplace fee, change   |
+///
+/// The merged dummies absorb every sat preceding the inscription, which therefore lands
+/// at offset 0 of output 1.
+pub fn complete_listing(
+    secp: &Secp256k1<All>,
+    listing: &Listing,
+    buyer: &Account,
+    dummy_utxos: &[TxInput; DUMMY_UTXOS],
+    payment_utxos: &[TxInput],
+    network_fee: Amount,
+) -> Result<Psbt> {
+    let buyer_script = buyer.address.script_pubkey();
+    let padding = dummy_utxos.iter().map(|utxo| utxo.amount).sum::<Amount>();
+    let dust = buyer_script.dust_value();
+    if padding < dust {
+        return Err(FeeError::DustPadding { padding, dust }.into());
+    }
+
+    let seller_input = &listing.psbt.inputs[0];
+    let inscription_value = seller_input
+        .witness_utxo
+        .as_ref()
+        .map(|utxo| utxo.value)
+        .ok_or(PsbtError::InvalidListing("no witness utxo"))?;
+    let seller_payment = listing.psbt.unsigned_tx.output[0].clone();
+    let marketplace_fee = match listing.fee_terms()? {
+        Some(fee_terms) => Some(TxOut {
+            value: fee_terms.fee(seller_payment.value)?,
+            script_pubkey: fee_terms.script_pubkey,
+        }),
+        None => None,
+    }
+    .filter(|fee| fee.value > Amount::ZERO);
+
+    // exceeding amount of payment utxos goes back to the buyer
+    let available = payment_utxos.iter().map(|utxo| utxo.amount).sum::<Amount>();
+    let required = seller_payment.value
+        + marketplace_fee
+            .as_ref()
+            .map_or(Amount::ZERO, |fee| fee.value)
+        + network_fee;
+    let change = available
+        .checked_sub(required)
+        .ok_or(FeeError::InsufficientFunds {
+            purpose: "buy the inscription",
+            available,
+            required,
+        })?;
+    debug!("change: {change}");
+
+    let buyer_tx_in = |utxo: &TxInput| TxIn {
+        previous_output: OutPoint {
+            txid: utxo.id,
+            vout: utxo.index,
+        },
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::from_consensus(0xffffffff),
+        witness: Witness::new(),
+    };
+    let mut input = dummy_utxos.iter().map(buyer_tx_in).collect::<Vec<_>>();
+    input.push(listing.psbt.unsigned_tx.input[0].clone());
+    input.extend(payment_utxos.iter().map(buyer_tx_in));
+
+    let mut output = vec![
+        TxOut {
+            value: padding,
+            script_pubkey: buyer_script.clone(),
+        },
+        TxOut {
+            value: inscription_value,
+            script_pubkey: buyer_script.clone(),
+        },
+        seller_payment,
+    ];
+    output.extend(marketplace_fee);
+    if change >= buyer_script.dust_value() {
+        output.push(TxOut {
+            value: change,
+            script_pubkey: buyer_script.clone(),
+        });
+    }
+
+    let unsigned_tx = Transaction {
+        version: listing.psbt.unsigned_tx.version,
+        lock_time: listing.psbt.unsigned_tx.lock_time,
+        input,
+        output,
+    };
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)?;
+
+    let zero = ChildNumber::from_normal_idx(0)?;
+    let key_source = (
+        buyer.private_key.fingerprint(secp),
+        buyer.path.extend([zero, zero]),
+    );
+    let buyer_input = |utxo: &TxInput| {
+        let mut input = Input {
+            witness_utxo: Some(TxOut {
+                value: utxo.amount,
+                script_pubkey: buyer_script.clone(),
+            }),
+            ..Default::default()
+        };
+        input
+            .bip32_derivation
+            .insert(buyer.public_key.inner, key_source.clone());
+        input
+    };
+    let mut inputs = dummy_utxos.iter().map(buyer_input).collect::<Vec<_>>();
+    inputs.push(seller_input.clone());
+    inputs.extend(payment_utxos.iter().map(buyer_input));
+    psbt.inputs = inputs;
+
+    let expected = DUMMY_UTXOS + payment_utxos.len();
+    match psbt.sign(&buyer.private_key, secp) {
+        Ok(keys) if keys.values().map(Vec::len).sum::<usize>() == expected => {}
+        Ok(keys) => {
+            return Err(SigningError::KeyCount {
+                expected,
+                signed: keys.values().map(Vec::len).sum(),
+            }
+            .into())
+        }
+        Err((_, errors)) => return Err(SigningError::from_psbt(errors).into()),
+    }
+    debug!(
+        "purchase psbt:\n{}",
+        inspect_psbt(&psbt, *buyer.address.network())
+    );
+
+    Ok(psbt)
+}
+
+/// Finalize the buyer inputs of a purchase PSBT and extract the transaction
+pub fn finalize_purchase(mut psbt: Psbt, fee_policy: &FeePolicy) -> Result<Transaction> {
+    for (index, input) in psbt.inputs.iter_mut().enumerate() {
+        if input.final_script_witness.is_some() {
+            continue;
+        }
+
+        let (pubkey, sig) = input
+            .partial_sigs
+            .pop_first()
+            .ok_or(PsbtError::UnsignedInput { index })?;
+
+        // Clear all the data fields as per the spec.
+        *input = Input {
+            witness_utxo: input.witness_utxo.take(),
+            final_script_witness: Some(Witness::p2wpkh(&sig, &pubkey.inner)),
+            ..Default::default()
+        };
+    }
+
+    Ok(fee_policy.extract_tx(psbt)?)
+}
+
+fn fee_terms_key() -> raw::ProprietaryKey {
+    raw::ProprietaryKey {
+        prefix: PROPRIETARY_PREFIX.to_vec(),
+        subtype: FEE_TERMS_SUBTYPE,
+        key: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::Txid;
+
+    use super::*;
+    use crate::{
+        MARKETPLACE_ADDRESS_MNEMONIC, RECIPIENT_ADDRESS_MNEMONIC, SENDER_ADDRESS_MNEMONIC,
+    };
+
+    fn utxo(index: u32, amount: u64) -> TxInput {
+        TxInput {
+            id: Txid::from_str("14a7109b642b4fca7f10cd9bee89db73770c5a2d107f6a51c6bd7625dcdc2aed")
+                .unwrap(),
+            index,
+            amount: Amount::from_sat(amount),
+        }
+    }
+
+    #[test]
+    fn test_should_export_and_import_listing_with_fee_terms() {
+        let secp = Secp256k1::new();
+        let seller = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let marketplace = Account::from_mnemonic(&secp, MARKETPLACE_ADDRESS_MNEMONIC).unwrap();
+
+        let mut listing =
+            Listing::new(&secp, &seller, &utxo(0, 546), Amount::from_sat(100_000)).unwrap();
+        listing.attach_fee_terms(&marketplace, 250).unwrap();
+        assert!(listing.attach_fee_terms(&marketplace, 10_001).is_err());
+
+        let imported = Listing::import(&listing.export()).unwrap();
+        assert_eq!(imported, listing);
+        assert_eq!(imported.price(), Amount::from_sat(100_000));
+
+        let fee_terms = imported.fee_terms().unwrap().unwrap();
+        assert_eq!(fee_terms.script_pubkey, marketplace.address.script_pubkey());
+        assert_eq!(
+            fee_terms.fee(imported.price()).unwrap(),
+            Amount::from_sat(2_500)
+        );
+
+        // terms rewritten in transit are rejected instead of overflowing
+        let mut tampered = imported.clone();
+        tampered.psbt.proprietary.insert(
+            fee_terms_key(),
+            encode::serialize(&(u64::MAX, fee_terms.script_pubkey.clone())),
+        );
+        assert!(tampered.fee_terms().is_err());
+
+        let witness = imported.psbt.inputs[0]
+            .final_script_witness
+            .as_ref()
+            .unwrap();
+        assert_eq!(
+            *witness.nth(0).unwrap().last().unwrap(),
+            EcdsaSighashType::SinglePlusAnyoneCanPay as u8
+        );
+    }
+
+    #[test]
+    fn test_should_complete_listing_with_dummy_utxos() {
+        let secp = Secp256k1::new();
+        let seller = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let buyer = Account::from_mnemonic(&secp, RECIPIENT_ADDRESS_MNEMONIC).unwrap();
+        let marketplace = Account::from_mnemonic(&secp, MARKETPLACE_ADDRESS_MNEMONIC).unwrap();
+
+        let mut listing =
+            Listing::new(&secp, &seller, &utxo(0, 546), Amount::from_sat(100_000)).unwrap();
+        listing.attach_fee_terms(&marketplace, 250).unwrap();
+        let listing = Listing::import(&listing.export()).unwrap();
+
+        let psbt = complete_listing(
+            &secp,
+            &listing,
+            &buyer,
+            &[utxo(1, 600), utxo(2, 600)],
+            &[utxo(3, 150_000)],
+            Amount::from_sat(3_000),
+        )
+        .unwrap();
+        let tx = finalize_purchase(psbt, &FeePolicy::default()).unwrap();
+
+        assert_eq!(tx.input[2].previous_output, listing.inscription_outpoint());
+        assert_eq!(tx.output[0].value, Amount::from_sat(1_200));
+        assert_eq!(tx.output[1].value, Amount::from_sat(546));
+        assert_eq!(tx.output[1].script_pubkey, buyer.address.script_pubkey());
+        assert_eq!(tx.output[2], listing.psbt.unsigned_tx.output[0]);
+        assert_eq!(tx.output[3].value, Amount::from_sat(2_500));
+        assert_eq!(
+            tx.output[3].script_pubkey,
+            marketplace.address.script_pubkey()
+        );
+        assert_eq!(tx.output[4].value, Amount::from_sat(44_500));
+        assert!(tx.input.iter().all(|input| input.witness.len() == 2));
+    }
+}
@@ -0,0 +1,335 @@
+use std::fmt;
+
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::{Address, Amount, Network, OutPoint, PrivateKey, Psbt, ScriptBuf, Transaction};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::account::{Account, AccountUtxo, WatchOnlyAccount};
+use crate::descriptor::DescriptorKind;
+use crate::error::{Error, PsbtError, Result, SigningError};
+use crate::fee::FeePolicy;
+use crate::interpreter::{psbt_prevouts, verify_transaction};
+use crate::psbt;
+use crate::secret::SecretPrivateKey;
+use crate::signer::Signer;
+use crate::taproot::TaprootPayload;
+use crate::verify::verify_signatures;
+
+/// Commit and reveal PSBTs moved between the online and the offline machine.
+///
+/// The online machine builds the bundle from a watch-only account and signs the reveal
+/// with the ephemeral taproot key; the offline machine only signs the commit inputs.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningBundle {
+    #[serde_as(as = "DisplayFromStr")]
+    pub network: Network,
+    #[serde_as(as = "DisplayFromStr")]
+    pub commit: Psbt,
+    #[serde_as(as = "DisplayFromStr")]
+    pub reveal: Psbt,
+}
+
+/// What the commit and reveal transactions of a bundle do, checked before signing
+#[derive(Debug, Clone)]
+pub struct BundleSummary {
+    pub spent: Amount,
+    pub change: Amount,
+    pub commit_fee: Amount,
+    pub inscription_address: Address,
+    pub reveal_balance: Amount,
+    pub recipient: Address,
+    pub postage: Amount,
+    pub reveal_fee: Amount,
+}
+
+impl SigningBundle {
+    /// Build the unsigned commit PSBT and the signed reveal PSBT spending its first output.
+    ///
+    /// The reveal is signed against the commit txid before the commit is, and the script sig of
+    /// a legacy or nested input would change that txid, so the account must be a `wpkh()` one.
+    #[allow(clippy::too_many_arguments)]
+    pub fn build(
+        secp: &Secp256k1<All>,
+        account: &WatchOnlyAccount,
+        inputs: &[AccountUtxo],
+        taproot: &TaprootPayload,
+        redeem_script: &ScriptBuf,
+        recipient: &Address,
+        postage: Amount,
+        commit_fee: Amount,
+    ) -> Result<Self> {
+        if account.descriptor.kind != DescriptorKind::Wpkh {
+            return Err(PsbtError::InvalidBundle(format!(
+                "cannot fund a bundle from {}, only wpkh accounts are supported",
+                account.descriptor
+            ))
+            .into());
+        }
+        let network = *account.address.network();
+        let commit = account.commit_psbt(secp, inputs, taproot, commit_fee)?;
+
+        // all the commit inputs are segwit, so its txid is final already
+        let mut reveal =
+            account.reveal_psbt(&commit, taproot, redeem_script, recipient, postage)?;
+
+        let reveal_key =
+            SecretPrivateKey::new(PrivateKey::new(taproot.keypair.secret_key(), network));
+        Signer::new(&reveal_key, secp, reveal.unsigned_tx.clone())
+            .sign_psbt_tap_script(&mut reveal)?;
+
+        Ok(Self {
+            network,
+            commit,
+            reveal,
+        })
+    }
+
+    /// Check that the commit only spends from `account` and funds the reveal, and summarize the amounts
+    pub fn verify(&self, secp: &Secp256k1<All>, account: &Account) -> Result<BundleSummary> {
+        let own_script = account.address.script_pubkey();
+        let descriptor = account.descriptor(secp);
+        let invalid = |reason| Error::from(PsbtError::InvalidBundle(reason));
+
+        let mut spent = Amount::ZERO;
+        for (index, input) in self.commit.inputs.iter().enumerate() {
+            let utxo = input
+                .witness_utxo
+                .as_ref()
+                .ok_or_else(|| invalid(format!("commit input {index} has no witness utxo")))?;
+            // the input may spend any receive address, found from its key origin
+            let address_index = input
+                .bip32_derivation
+                .values()
+                .find_map(|key_source| descriptor.address_index(key_source));
+            let spends_own_address = match address_index {
+                Some(address_index) => {
+                    utxo.script_pubkey == descriptor.script_pubkey(secp, address_index)?
+                }
+                None => false,
+            };
+            if !spends_own_address {
+                return Err(invalid(format!(
+                    "commit input {index} does not spend from {}",
+                    account.address
+                )));
+            }
+            spent += utxo.value;
+        }
+
+        let (inscription_output, change_outputs) = self
+            .commit
+            .unsigned_tx
+            .output
+            .split_first()
+            .ok_or_else(|| invalid("commit has no outputs".to_string()))?;
+        let mut change = Amount::ZERO;
+        for (index, output) in change_outputs.iter().enumerate() {
+            if output.script_pubkey != own_script {
+                return Err(invalid(format!(
+                    "commit output {} does not pay back to the account",
+                    index + 1
+                )));
+            }
+            change += output.value;
+        }
+
+        let [reveal_input] = self.reveal.unsigned_tx.input.as_slice() else {
+            return Err(invalid("reveal must have exactly one input".to_string()));
+        };
+        let expected_outpoint = OutPoint {
+            txid: self.commit.unsigned_tx.txid(),
+            vout: 0,
+        };
+        if reveal_input.previous_output != expected_outpoint {
+            return Err(invalid(
+                "reveal does not spend the commit inscription output".to_string(),
+            ));
+        }
+        let reveal_psbt_input = &self.reveal.inputs[0];
+        if reveal_psbt_input.witness_utxo.as_ref() != Some(inscription_output) {
+            return Err(invalid(
+                "reveal prevout does not match the commit inscription output".to_string(),
+            ));
+        }
+        let internal_key = reveal_psbt_input
+            .tap_internal_key
+            .ok_or_else(|| invalid("reveal has no taproot internal key".to_string()))?;
+        let taproot_script =
+            ScriptBuf::new_p2tr(secp, internal_key, reveal_psbt_input.tap_merkle_root);
+        if taproot_script != inscription_output.script_pubkey {
+            return Err(invalid(
+                "commit inscription output does not commit to the reveal script".to_string(),
+            ));
+        }
+        if reveal_psbt_input.tap_script_sigs.is_empty() {
+            return Err(invalid("reveal is not signed".to_string()));
+        }
+
+        let [reveal_output] = self.reveal.unsigned_tx.output.as_slice() else {
+            return Err(invalid("reveal must have exactly one output".to_string()));
+        };
+
+        Ok(BundleSummary {
+            spent,
+            change,
+            commit_fee: self.commit.fee()?,
+            inscription_address: Address::from_script(
+                &inscription_output.script_pubkey,
+                self.network,
+            )?,
+            reveal_balance: inscription_output.value,
+            recipient: Address::from_script(&reveal_output.script_pubkey, self.network)?,
+            postage: reveal_output.value,
+            reveal_fee: self.reveal.fee()?,
+        })
+    }
+
+    /// Verify the bundle and sign the commit inputs with the account keys
+    pub fn sign(&mut self, secp: &Secp256k1<All>, account: &Account) -> Result<BundleSummary> {
+        let summary = self.verify(secp, account)?;
+
+        let mut signer = Signer::with_keys(account, secp, self.commit.unsigned_tx.clone());
+        signer.sign_psbt_ecdsa(&mut self.commit)?;
+        let report = signer.report();
+        if !report.skipped.is_empty() {
+            return Err(SigningError::Skipped {
+                signed: report.signed.len(),
+                inputs: self.commit.inputs.len(),
+                skipped: report.skipped.clone(),
+            }
+            .into());
+        }
+
+        Ok(summary)
+    }
+
+    /// Verify the signatures of both PSBTs, then finalize them and return the commit and the reveal transactions
+    pub fn finalize(
+        self,
+        secp: &Secp256k1<All>,
+        fee_policy: &FeePolicy,
+    ) -> Result<(Transaction, Transaction)> {
+        verify_signatures(secp, &self.commit)?.ensure_complete()?;
+        verify_signatures(secp, &self.reveal)?.ensure_complete()?;
+        let commit_prevouts = psbt_prevouts(&self.commit)?;
+        let reveal_prevouts = psbt_prevouts(&self.reveal)?;
+
+        let commit = psbt::finalize_commit(self.commit, fee_policy)?;
+        let reveal = psbt::finalize_reveal(self.reveal, fee_policy)?;
+        verify_transaction(secp, &commit, &commit_prevouts).ensure_valid()?;
+        verify_transaction(secp, &reveal, &reveal_prevouts).ensure_valid()?;
+
+        Ok((commit, reveal))
+    }
+}
+
+impl fmt::Display for BundleSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "commit")?;
+        writeln!(f, "  spent: {}", self.spent)?;
+        writeln!(
+            f,
+            "  inscription: {} to {}",
+            self.reveal_balance, self.inscription_address
+        )?;
+        writeln!(f, "  change: {}", self.change)?;
+        writeln!(f, "  fee: {}", self.commit_fee)?;
+        writeln!(f, "reveal")?;
+        writeln!(f, "  postage: {} to {}", self.postage, self.recipient)?;
+        write!(f, "  fee: {}", self.reveal_fee)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr as _;
+
+    use bitcoin::opcodes::all::OP_CHECKSIG;
+    use bitcoin::script::Builder as ScriptBuilder;
+    use bitcoin::Txid;
+    use ord_rs::transaction::TxInput;
+
+    use super::*;
+    use crate::{taproot, RECIPIENT_ADDRESS_MNEMONIC, SENDER_ADDRESS_MNEMONIC};
+
+    #[test]
+    fn test_should_sign_bundle_offline() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let recipient = Account::from_mnemonic(&secp, RECIPIENT_ADDRESS_MNEMONIC).unwrap();
+
+        // online
+        let (keypair, x_public_key) = taproot::generate_keypair(&secp);
+        let redeem_script = ScriptBuilder::new()
+            .push_x_only_key(&x_public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let taproot_payload = TaprootPayload::build(
+            &secp,
+            keypair,
+            x_public_key,
+            &redeem_script,
+            5_000,
+            Network::Testnet,
+        )
+        .unwrap();
+        let inputs = [AccountUtxo {
+            input: TxInput {
+                id: Txid::from_str(
+                    "14a7109b642b4fca7f10cd9bee89db73770c5a2d107f6a51c6bd7625dcdc2aed",
+                )
+                .unwrap(),
+                index: 0,
+                amount: Amount::from_sat(8_000),
+            },
+            address_index: 2,
+        }];
+        let bundle = SigningBundle::build(
+            &secp,
+            &sender.watch_only(&secp).unwrap(),
+            &inputs,
+            &taproot_payload,
+            &redeem_script,
+            &recipient.address,
+            Amount::from_sat(333),
+            Amount::from_sat(1_000),
+        )
+        .unwrap();
+        let json = serde_json::to_string(&bundle).unwrap();
+
+        // the offline signer cannot sign taproot inputs yet
+        let mut tr_descriptor = sender.descriptor(&secp);
+        tr_descriptor.kind = DescriptorKind::Tr;
+        assert!(SigningBundle::build(
+            &secp,
+            &WatchOnlyAccount::new(&secp, tr_descriptor).unwrap(),
+            &inputs,
+            &taproot_payload,
+            &redeem_script,
+            &recipient.address,
+            Amount::from_sat(333),
+            Amount::from_sat(1_000),
+        )
+        .is_err());
+
+        // offline
+        let mut bundle: SigningBundle = serde_json::from_str(&json).unwrap();
+        assert!(bundle.verify(&secp, &recipient).is_err());
+        let summary = bundle.sign(&secp, &sender).unwrap();
+        assert_eq!(summary.spent, Amount::from_sat(8_000));
+        assert_eq!(summary.change, Amount::from_sat(2_000));
+        assert_eq!(summary.commit_fee, Amount::from_sat(1_000));
+        assert_eq!(summary.recipient, recipient.address);
+        assert_eq!(summary.reveal_fee, Amount::from_sat(5_000 - 333));
+        let json = serde_json::to_string(&bundle).unwrap();
+
+        // online
+        let bundle: SigningBundle = serde_json::from_str(&json).unwrap();
+        let (commit, reveal) = bundle.finalize(&secp, &FeePolicy::default()).unwrap();
+        assert_eq!(reveal.input[0].previous_output.txid, commit.txid());
+        assert_eq!(commit.input[0].witness.len(), 2);
+        assert_eq!(reveal.input[0].witness.len(), 3);
+    }
+}
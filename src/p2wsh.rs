@@ -0,0 +1,140 @@
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::{Address, Amount, Network, PrivateKey, ScriptBuf, TxOut, Txid};
+use ord_rs::transaction::TxInput;
+
+use crate::error::{InscriptionError, Result};
+use crate::secret::SecretPrivateKey;
+
+/// Largest witness script relayed by Bitcoin Core (`MAX_STANDARD_P2WSH_SCRIPT_SIZE`)
+const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3_600;
+
+/// Commit output locking the inscription envelope into a P2WSH output, the fallback to `TaprootPayload`.
+///
+/// ord only indexes envelopes revealed in a tapscript witness: the envelope of a P2WSH reveal
+/// lands on chain, but no indexer recognises it as an inscription.
+#[derive(Debug)]
+pub struct P2wshPayload {
+    pub address: Address,
+    pub redeem_script: ScriptBuf,
+    pub prevouts: TxOut,
+    pub private_key: SecretPrivateKey,
+}
+
+impl P2wshPayload {
+    /// Build the P2WSH payload of `redeem_script`, which must be locked to `private_key`
+    pub fn build(
+        private_key: SecretPrivateKey,
+        redeem_script: &ScriptBuf,
+        reveal_balance: u64,
+        network: Network,
+    ) -> Result<Self> {
+        if redeem_script.len() > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+            return Err(InscriptionError::ScriptTooLarge {
+                output: "P2WSH",
+                size: redeem_script.len(),
+                limit: MAX_STANDARD_P2WSH_SCRIPT_SIZE,
+            }
+            .into());
+        }
+
+        let address = Address::p2wsh(redeem_script, network);
+        Ok(Self {
+            prevouts: TxOut {
+                value: Amount::from_sat(reveal_balance),
+                script_pubkey: address.script_pubkey(),
+            },
+            address,
+            redeem_script: redeem_script.clone(),
+            private_key,
+        })
+    }
+
+    /// Input of the reveal spending output `vout` of the commit `txid`
+    pub fn reveal_input(&self, txid: Txid, vout: u32) -> TxInput {
+        TxInput {
+            id: txid,
+            index: vout,
+            amount: self.prevouts.value,
+        }
+    }
+}
+
+/// Generate the ephemeral key the P2WSH envelope is locked to
+pub fn generate_private_key(secp: &Secp256k1<All>, network: Network) -> SecretPrivateKey {
+    let (secret_key, _) = secp.generate_keypair(&mut rand::thread_rng());
+    SecretPrivateKey::new(PrivateKey::new(secret_key, network))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::absolute::LockTime;
+    use bitcoin::constants::MAX_SCRIPT_ELEMENT_SIZE;
+    use bitcoin::hashes::Hash as _;
+    use bitcoin::script::Instruction;
+    use bitcoin::transaction::Version;
+    use bitcoin::{OutPoint, Sequence, Transaction, TxIn, Witness};
+
+    use super::*;
+    use crate::interpreter::verify_transaction;
+    use crate::signer::Signer;
+
+    #[test]
+    fn test_should_sign_and_verify_p2wsh_reveal() {
+        let secp = Secp256k1::new();
+        let private_key = generate_private_key(&secp, Network::Regtest);
+        let public_key = private_key.public_key(&secp);
+        // a ticker long enough for the content to need several pushes
+        let inscription = ord_rs::brc20::Brc20::deploy(&"a".repeat(600), 21_000_000, None, None);
+        let redeem_script =
+            crate::utils::inscription_script(&public_key.to_bytes(), &inscription).unwrap();
+        let pushes = redeem_script
+            .instructions()
+            .filter_map(|instruction| match instruction.unwrap() {
+                Instruction::PushBytes(bytes) => Some(bytes.len()),
+                Instruction::Op(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert!(pushes.len() > 5);
+        assert!(pushes.iter().all(|len| *len <= MAX_SCRIPT_ELEMENT_SIZE));
+        let payload =
+            P2wshPayload::build(private_key, &redeem_script, 5_000, Network::Regtest).unwrap();
+        assert!(payload.address.script_pubkey().is_p2wsh());
+
+        let input = payload.reveal_input(Txid::all_zeros(), 0);
+        let reveal_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: input.id,
+                    vout: input.index,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_consensus(0xffffffff),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(333),
+                script_pubkey: payload.address.script_pubkey(),
+            }],
+        };
+
+        let mut signer = Signer::new(&payload.private_key, &secp, reveal_tx);
+        let signed_tx = signer
+            .sign_reveal_transaction_ecdsa(&input, &payload.redeem_script)
+            .unwrap();
+        assert_eq!(signer.report().signed, vec![0]);
+        let witness = &signed_tx.input[0].witness;
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness.nth(1).unwrap(), redeem_script.as_bytes());
+        assert!(
+            verify_transaction(&secp, &signed_tx, std::slice::from_ref(&payload.prevouts))
+                .is_valid()
+        );
+
+        // a script over the standard size is refused
+        let oversized = ScriptBuf::from(vec![0x51; MAX_STANDARD_P2WSH_SCRIPT_SIZE + 1]);
+        let private_key = generate_private_key(&secp, Network::Regtest);
+        assert!(P2wshPayload::build(private_key, &oversized, 5_000, Network::Regtest).is_err());
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::BTreeMap;
+use std::str::FromStr as _;
+use std::sync::Arc;
+
+use bitcoin::absolute::LockTime;
+use bitcoin::hashes::{hash160, ripemd160, sha256};
+use bitcoin::psbt::Input;
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::taproot::{LeafVersion, TapLeafHash};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Address, Amount, Network, OutPoint, Psbt, PublicKey, ScriptBuf, Sequence, Transaction, TxIn,
+    TxOut, Witness, XOnlyPublicKey,
+};
+use miniscript::descriptor::TapTree;
+use miniscript::policy::Concrete;
+use miniscript::psbt::PsbtExt as _;
+use miniscript::{hash256, Descriptor, Translator};
+use ord_rs::transaction::TxInput;
+
+use crate::descriptor::DerivedKey;
+use crate::error::{Error, FeeError, PolicyError, Result};
+use crate::escrow::NUMS_INTERNAL_KEY;
+use crate::fee::FeePolicy;
+
+/// Output type the policy compiles to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyKind {
+    P2wsh,
+    /// Single leaf under the NUMS internal key, so that only the policy can spend it
+    Taproot,
+}
+
+/// Spending conditions compiled from a miniscript policy, e.g. `or(pk(seller),and(pk(buyer),older(144)))`
+#[derive(Debug, Clone)]
+pub struct SpendingPolicy {
+    pub descriptor: Descriptor<PublicKey>,
+    keys: BTreeMap<PublicKey, DerivedKey>,
+}
+
+/// Resolves the key names used in the policy
+struct NamedKeys<'a>(&'a BTreeMap<String, DerivedKey>);
+
+impl Translator<String, PublicKey, Error> for NamedKeys<'_> {
+    fn pk(&mut self, name: &String) -> Result<PublicKey> {
+        self.0
+            .get(name)
+            .map(|key| key.public_key)
+            .ok_or_else(|| PolicyError::UnknownKey { name: name.clone() }.into())
+    }
+
+    fn sha256(&mut self, hash: &String) -> Result<sha256::Hash> {
+        sha256::Hash::from_str(hash).map_err(|_| invalid_hash(hash))
+    }
+
+    fn hash256(&mut self, hash: &String) -> Result<hash256::Hash> {
+        hash256::Hash::from_str(hash).map_err(|_| invalid_hash(hash))
+    }
+
+    fn ripemd160(&mut self, hash: &String) -> Result<ripemd160::Hash> {
+        ripemd160::Hash::from_str(hash).map_err(|_| invalid_hash(hash))
+    }
+
+    fn hash160(&mut self, hash: &String) -> Result<hash160::Hash> {
+        hash160::Hash::from_str(hash).map_err(|_| invalid_hash(hash))
+    }
+}
+
+fn invalid_hash(hash: &str) -> Error {
+    PolicyError::InvalidHash {
+        hash: hash.to_string(),
+    }
+    .into()
+}
+
+/// Parser, compiler and key errors of miniscript, of which only the message is kept
+fn miniscript_error(err: impl std::fmt::Display) -> Error {
+    PolicyError::Miniscript(err.to_string()).into()
+}
+
+impl SpendingPolicy {
+    /// Compile `policy`, whose `pk()` fragments refer to the names in `keys`
+    pub fn compile(
+        policy: &str,
+        keys: &BTreeMap<String, DerivedKey>,
+        kind: PolicyKind,
+    ) -> Result<Self> {
+        let policy = Concrete::<String>::from_str(policy)
+            .map_err(miniscript_error)?
+            .translate_pk(&mut NamedKeys(keys))?;
+
+        let descriptor = match kind {
+            PolicyKind::P2wsh => Descriptor::new_wsh(policy.compile().map_err(miniscript_error)?)
+                .map_err(miniscript_error)?,
+            PolicyKind::Taproot => {
+                let mut internal_key = [0x02; 33];
+                internal_key[1..].copy_from_slice(&NUMS_INTERNAL_KEY);
+                Descriptor::new_tr(
+                    PublicKey::from_slice(&internal_key).map_err(miniscript_error)?,
+                    Some(TapTree::Leaf(Arc::new(
+                        policy.compile().map_err(miniscript_error)?,
+                    ))),
+                )
+                .map_err(miniscript_error)?
+            }
+        };
+        debug!("policy descriptor: {descriptor}");
+
+        Ok(Self {
+            descriptor,
+            keys: keys
+                .values()
+                .map(|key| (key.public_key, key.clone()))
+                .collect(),
+        })
+    }
+
+    pub fn address(&self, network: Network) -> Result<Address> {
+        self.descriptor.address(network).map_err(miniscript_error)
+    }
+
+    pub fn script_pubkey(&self) -> ScriptBuf {
+        self.descriptor.script_pubkey()
+    }
+
+    /// Fill in the scripts and the key origins of a PSBT input spending the policy output
+    pub fn update_input(&self, input: &mut Input) -> Result<()> {
+        match &self.descriptor {
+            Descriptor::Wsh(_) => {
+                input.witness_script = Some(
+                    self.descriptor
+                        .explicit_script()
+                        .map_err(miniscript_error)?,
+                );
+                for key in self.keys.values() {
+                    input
+                        .bip32_derivation
+                        .insert(key.public_key.inner, key.key_source.clone());
+                }
+            }
+            Descriptor::Tr(tr) => {
+                let spend_info = tr.spend_info();
+                input.tap_internal_key = Some(spend_info.internal_key());
+                input.tap_merkle_root = spend_info.merkle_root();
+
+                for (_, leaf) in tr.iter_scripts() {
+                    let script = leaf.encode();
+                    let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+                    let control_block = spend_info
+                        .control_block(&(script.clone(), LeafVersion::TapScript))
+                        .ok_or(PolicyError::MissingControlBlock)?;
+                    input
+                        .tap_scripts
+                        .insert(control_block, (script, LeafVersion::TapScript));
+
+                    for key in self.keys.values() {
+                        input
+                            .tap_key_origins
+                            .entry(XOnlyPublicKey::from(key.public_key.inner))
+                            .or_insert_with(|| (Vec::new(), key.key_source.clone()))
+                            .0
+                            .push(leaf_hash);
+                    }
+                }
+            }
+            _ => {
+                return Err(PolicyError::UnsupportedDescriptor {
+                    descriptor: self.descriptor.to_string(),
+                }
+                .into())
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the PSBT spending the policy output `input` to `destination`.
+    ///
+    /// `sequence` must satisfy the `older()` fragments of the branch that will be used and
+    /// `lock_time` its `after()` fragments, which also need a `sequence` enabling the lock time.
+    pub fn spend_psbt(
+        &self,
+        input: &TxInput,
+        destination: ScriptBuf,
+        fee: Amount,
+        sequence: Sequence,
+        lock_time: LockTime,
+    ) -> Result<Psbt> {
+        let value = input
+            .amount
+            .checked_sub(fee)
+            .ok_or(FeeError::InsufficientFunds {
+                purpose: "pay the policy spend fee",
+                available: input.amount,
+                required: fee,
+            })?;
+
+        let unsigned_tx = Transaction {
+            version: Version::TWO,
+            lock_time,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: input.id,
+                    vout: input.index,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey: destination,
+            }],
+        };
+
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)?;
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: input.amount,
+            script_pubkey: self.script_pubkey(),
+        });
+        self.update_input(&mut psbt.inputs[0])?;
+
+        Ok(psbt)
+    }
+}
+
+/// Build the witnesses satisfying the policies of a signed PSBT and extract the transaction
+pub fn finalize(
+    secp: &Secp256k1<All>,
+    mut psbt: Psbt,
+    fee_policy: &FeePolicy,
+) -> Result<Transaction> {
+    psbt.finalize_mut(secp).map_err(|errors| {
+        let errors = errors
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        PolicyError::Unsatisfiable(errors)
+    })?;
+
+    Ok(fee_policy.extract_tx(psbt)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::Txid;
+
+    use super::*;
+    use crate::account::Account;
+    use crate::signer::Signer;
+    use crate::{RECIPIENT_ADDRESS_MNEMONIC, SENDER_ADDRESS_MNEMONIC};
+
+    const POLICY: &str = "or(pk(seller),and(pk(buyer),older(144)))";
+
+    fn keys(secp: &Secp256k1<All>) -> (Account, Account, BTreeMap<String, DerivedKey>) {
+        let seller = Account::from_mnemonic(secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let buyer = Account::from_mnemonic(secp, RECIPIENT_ADDRESS_MNEMONIC).unwrap();
+        let keys = BTreeMap::from([
+            (
+                "seller".to_string(),
+                seller.descriptor(secp).derive(secp, 0).unwrap(),
+            ),
+            (
+                "buyer".to_string(),
+                buyer.descriptor(secp).derive(secp, 0).unwrap(),
+            ),
+        ]);
+
+        (seller, buyer, keys)
+    }
+
+    fn policy_input() -> TxInput {
+        TxInput {
+            id: Txid::from_str("14a7109b642b4fca7f10cd9bee89db73770c5a2d107f6a51c6bd7625dcdc2aed")
+                .unwrap(),
+            index: 0,
+            amount: Amount::from_sat(10_000),
+        }
+    }
+
+    #[test]
+    fn test_should_satisfy_p2wsh_policy() {
+        let secp = Secp256k1::new();
+        let (seller, buyer, keys) = keys(&secp);
+        let policy = SpendingPolicy::compile(POLICY, &keys, PolicyKind::P2wsh).unwrap();
+        let input = policy_input();
+
+        // the buyer branch is timelocked
+        let mut psbt = policy
+            .spend_psbt(
+                &input,
+                buyer.address.script_pubkey(),
+                Amount::from_sat(1_000),
+                Sequence::MAX,
+                LockTime::ZERO,
+            )
+            .unwrap();
+        psbt.sign(&buyer.private_key, &secp).unwrap();
+        assert!(finalize(&secp, psbt, &FeePolicy::default()).is_err());
+
+        let mut psbt = policy
+            .spend_psbt(
+                &input,
+                buyer.address.script_pubkey(),
+                Amount::from_sat(1_000),
+                Sequence::from_height(144),
+                LockTime::ZERO,
+            )
+            .unwrap();
+        psbt.sign(&buyer.private_key, &secp).unwrap();
+        assert!(finalize(&secp, psbt, &FeePolicy::default()).is_ok());
+
+        let mut psbt = policy
+            .spend_psbt(
+                &input,
+                seller.address.script_pubkey(),
+                Amount::from_sat(1_000),
+                Sequence::MAX,
+                LockTime::ZERO,
+            )
+            .unwrap();
+        psbt.sign(&seller.private_key, &secp).unwrap();
+        assert!(finalize(&secp, psbt, &FeePolicy::default()).is_ok());
+    }
+
+    #[test]
+    fn test_should_satisfy_taproot_policy() {
+        let secp = Secp256k1::new();
+        let (seller, _, keys) = keys(&secp);
+        let policy = SpendingPolicy::compile(POLICY, &keys, PolicyKind::Taproot).unwrap();
+        let input = policy_input();
+
+        let mut psbt = policy
+            .spend_psbt(
+                &input,
+                seller.address.script_pubkey(),
+                Amount::from_sat(1_000),
+                Sequence::MAX,
+                LockTime::ZERO,
+            )
+            .unwrap();
+        let key_source = &keys["seller"].key_source;
+        let private_key = seller
+            .private_key
+            .derive_private_key(&secp, &key_source.1)
+            .unwrap();
+        let signed = Signer::new(&private_key, &secp, psbt.unsigned_tx.clone())
+            .sign_psbt_tap_script(&mut psbt)
+            .unwrap();
+        assert_eq!(signed, 1);
+
+        let tx = finalize(&secp, psbt, &FeePolicy::default()).unwrap();
+        // signature, leaf script and control block
+        assert_eq!(tx.input[0].witness.len(), 3);
+    }
+
+    #[test]
+    fn test_should_satisfy_absolute_timelock_policy() {
+        let secp = Secp256k1::new();
+        let (_, buyer, keys) = keys(&secp);
+        let policy = SpendingPolicy::compile(
+            "or(pk(seller),and(pk(buyer),after(800000)))",
+            &keys,
+            PolicyKind::P2wsh,
+        )
+        .unwrap();
+        let input = policy_input();
+        let spend = |sequence, lock_time| {
+            let mut psbt = policy
+                .spend_psbt(
+                    &input,
+                    buyer.address.script_pubkey(),
+                    Amount::from_sat(1_000),
+                    sequence,
+                    lock_time,
+                )
+                .unwrap();
+            psbt.sign(&buyer.private_key, &secp).unwrap();
+            finalize(&secp, psbt, &FeePolicy::default())
+        };
+
+        let height = |height| LockTime::from_height(height).unwrap();
+        assert!(spend(Sequence::ENABLE_LOCKTIME_NO_RBF, height(799_999)).is_err());
+        // a final sequence disables the lock time
+        assert!(spend(Sequence::MAX, height(800_000)).is_err());
+
+        let tx = spend(Sequence::ENABLE_LOCKTIME_NO_RBF, height(800_000)).unwrap();
+        assert_eq!(tx.lock_time, height(800_000));
+    }
+}
@@ -1,21 +1,33 @@
-use std::{collections::BTreeMap, str::FromStr};
+use std::collections::BTreeMap;
 
 use bitcoin::{
-    psbt::{Input, PsbtSighashType},
+    bip32::{DerivationPath, Fingerprint},
+    blockdata::script::Builder,
+    psbt::Input,
     secp256k1::{All, Secp256k1},
-    Psbt, ScriptBuf, Transaction, TxOut, Witness,
+    taproot::LeafVersion,
+    Psbt, PublicKey, ScriptBuf, TapLeafHash, Transaction, TxOut, Witness,
 };
 
+use crate::error::{PsbtError, Result, SigningError};
+use crate::fee::FeePolicy;
+use crate::inspect::{inspect_psbt, spent_utxo};
+use crate::signer::SighashType;
+use crate::taproot::TaprootPayload;
+use crate::utils::bytes_to_push_bytes;
 use crate::Account;
 
+#[allow(clippy::too_many_arguments)]
 pub fn sign_partially(
     secp: &Secp256k1<All>,
     unsigned_tx: Transaction,
     updater_account: &Account,
-    accounts: &[Account],
+    accounts: &[&Account],
     previous_output: TxOut,
     witness_script: &ScriptBuf,
-) -> anyhow::Result<Transaction> {
+    sighash_type: SighashType,
+    fee_policy: &FeePolicy,
+) -> Result<Transaction> {
     // Creator (https://github.com/rust-bitcoin/rust-bitcoin/blob/master/bitcoin/examples/ecdsa-psbt.rs)
     let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)?;
 
@@ -25,47 +37,41 @@ pub fn sign_partially(
         ..Default::default()
     };
 
-    let pk = updater_account.input_xpub.to_pub();
-    let wpkh = pk.wpubkey_hash().expect("a compressed pubkey");
-
-    let redeem_script = ScriptBuf::new_p2wpkh(&wpkh);
-    input.redeem_script = Some(redeem_script);
-
-    let fingerprint = updater_account.private_key.fingerprint(secp);
-    let mut map = BTreeMap::new();
-    map.insert(pk.inner, (fingerprint, updater_account.path.clone()));
-    input.bip32_derivation = map;
+    // every signer fills in the derivation of its receive key from its account descriptor
+    for account in std::iter::once(updater_account).chain(accounts.iter().copied()) {
+        account.descriptor(secp).update_input(secp, 0, &mut input)?;
+    }
 
-    let ty = PsbtSighashType::from_str("SIGHASH_ALL")?;
-    input.sighash_type = Some(ty);
+    input.sighash_type = Some(sighash_type.ecdsa().into());
 
     psbt.inputs = vec![input];
-    debug!("unsigned psbt: {psbt:#?}");
+    let network = *updater_account.address.network();
+    debug!("unsigned psbt:\n{}", inspect_psbt(&psbt, network));
 
     // sign
-    match psbt.sign(&updater_account.private_key, secp) {
-        Ok(keys) if keys.len() == 1 => {}
-        Ok(_) => anyhow::bail!("unexpected number of keys"),
-        Err(_) => anyhow::bail!("signing failed"),
-    }
-    for account in accounts {
-        match psbt.sign(&account.private_key, secp) {
-            Ok(keys) if keys.len() == 1 => {}
-            Ok(_) => anyhow::bail!("unexpected number of keys"),
-            Err(_) => anyhow::bail!("signing failed"),
+    for account in std::iter::once(updater_account).chain(accounts.iter().copied()) {
+        let keys = psbt
+            .sign(&account.private_key, secp)
+            .map_err(|(_, errors)| SigningError::from_psbt(errors))?;
+        let signed = keys.values().map(Vec::len).sum();
+        if signed != 1 {
+            return Err(SigningError::KeyCount {
+                expected: 1,
+                signed,
+            }
+            .into());
         }
     }
 
-    debug!("signed psbt: {psbt:#?}");
+    debug!("signed psbt:\n{}", inspect_psbt(&psbt, network));
 
     // Push witness
     let sigs: Vec<_> = psbt.inputs[0].partial_sigs.values().collect();
     let mut script_witness = Witness::new();
-    script_witness.push(&sigs[0].to_vec());
+    script_witness.push(sigs[0].to_vec());
     script_witness.push(witness_script);
 
     // Clear all the data fields as per the spec.
-    debug!("finalized psbt: {psbt:#?}");
     psbt.inputs[0].partial_sigs = BTreeMap::new();
     psbt.inputs[0].sighash_type = None;
     psbt.inputs[0].redeem_script = None;
@@ -73,6 +79,238 @@ pub fn sign_partially(
     psbt.inputs[0].bip32_derivation = BTreeMap::new();
 
     psbt.inputs[0].final_script_witness = Some(script_witness);
+    debug!("finalized psbt:\n{}", inspect_psbt(&psbt, network));
+
+    Ok(fee_policy.extract_tx(psbt)?)
+}
+
+/// Finalize the P2WPKH, P2SH-P2WPKH, P2PKH and taproot key path inputs of a signed commit PSBT
+/// and extract the transaction
+pub fn finalize_commit(mut psbt: Psbt, fee_policy: &FeePolicy) -> Result<Transaction> {
+    let prevouts = (0..psbt.inputs.len())
+        .map(|index| spent_utxo(&psbt, index).map(|prevout| prevout.script_pubkey.clone()))
+        .collect::<Vec<_>>();
+    for ((index, input), script_pubkey) in psbt.inputs.iter_mut().enumerate().zip(prevouts) {
+        let is_p2pkh = script_pubkey.is_some_and(|script_pubkey| script_pubkey.is_p2pkh());
+        let (script_sig, witness) = match (input.tap_key_sig, input.partial_sigs.pop_first()) {
+            (Some(sig), _) => (None, Witness::from_slice(&[sig.to_vec()])),
+            (None, Some((pubkey, sig))) if is_p2pkh => {
+                let script_sig = Builder::new()
+                    .push_slice(bytes_to_push_bytes(&sig.to_vec())?)
+                    .push_key(&pubkey)
+                    .into_script();
+                (Some(script_sig), Witness::new())
+            }
+            (None, Some((pubkey, sig))) => {
+                // a nested segwit input pushes its P2WPKH program in the script sig
+                let script_sig = match &input.redeem_script {
+                    Some(redeem_script) => Some(
+                        Builder::new()
+                            .push_slice(bytes_to_push_bytes(redeem_script.as_bytes())?)
+                            .into_script(),
+                    ),
+                    None => None,
+                };
+                (script_sig, Witness::p2wpkh(&sig, &pubkey.inner))
+            }
+            (None, None) => return Err(PsbtError::UnsignedInput { index }.into()),
+        };
+
+        // Clear all the data fields as per the spec.
+        *input = Input {
+            witness_utxo: input.witness_utxo.take(),
+            non_witness_utxo: input.non_witness_utxo.take(),
+            final_script_sig: script_sig,
+            final_script_witness: (!witness.is_empty()).then_some(witness),
+            ..Default::default()
+        };
+    }
+
+    Ok(fee_policy.extract_tx(psbt)?)
+}
+
+/// Build the reveal PSBT, populating the BIP371 fields for the taproot script spend
+pub fn reveal_psbt(
+    unsigned_tx: Transaction,
+    taproot: &TaprootPayload,
+    redeem_script: &ScriptBuf,
+) -> Result<Psbt> {
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)?;
+
+    let internal_key = taproot.taproot_spend_info.internal_key();
+    let leaf_hash = TapLeafHash::from_script(redeem_script, LeafVersion::TapScript);
+
+    // the reveal key is ephemeral: use its own hash as fingerprint and an empty path
+    let pubkey_hash = PublicKey::new(taproot.keypair.public_key()).pubkey_hash();
+    let mut fingerprint = [0; 4];
+    fingerprint.copy_from_slice(&pubkey_hash[..4]);
+    let key_source = (Fingerprint::from(fingerprint), DerivationPath::master());
+
+    let mut input = Input {
+        witness_utxo: Some(taproot.prevouts.clone()),
+        tap_internal_key: Some(internal_key),
+        tap_merkle_root: taproot.taproot_spend_info.merkle_root(),
+        ..Default::default()
+    };
+    input.tap_scripts.insert(
+        taproot.control_block.clone(),
+        (redeem_script.clone(), LeafVersion::TapScript),
+    );
+    input.tap_key_origins.insert(
+        taproot.keypair.x_only_public_key().0,
+        (vec![leaf_hash], key_source),
+    );
 
-    Ok(psbt.extract_tx_fee_rate_limit()?)
+    psbt.inputs = vec![input];
+    debug!(
+        "unsigned reveal psbt:\n{}",
+        inspect_psbt(&psbt, *taproot.address.network())
+    );
+
+    Ok(psbt)
+}
+
+/// Finalize the taproot script spend of a signed reveal PSBT and extract the transaction
+pub fn finalize_reveal(mut psbt: Psbt, fee_policy: &FeePolicy) -> Result<Transaction> {
+    for (index, input) in psbt.inputs.iter_mut().enumerate() {
+        let (control_block, (script, leaf_version)) = input
+            .tap_scripts
+            .iter()
+            .next()
+            .ok_or(PsbtError::MissingTapLeafScript { index })?;
+        let leaf_hash = TapLeafHash::from_script(script, *leaf_version);
+        let signature = input
+            .tap_script_sigs
+            .iter()
+            .find(|((_, sig_leaf_hash), _)| *sig_leaf_hash == leaf_hash)
+            .map(|(_, signature)| signature)
+            .ok_or(PsbtError::UnsignedInput { index })?;
+
+        let mut script_witness = Witness::new();
+        script_witness.push(signature.to_vec());
+        script_witness.push(script.as_bytes());
+        script_witness.push(control_block.serialize());
+
+        // Clear all the data fields as per the spec.
+        *input = Input {
+            witness_utxo: input.witness_utxo.take(),
+            final_script_witness: Some(script_witness),
+            ..Default::default()
+        };
+    }
+
+    Ok(fee_policy.extract_tx(psbt)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::absolute::LockTime;
+    use bitcoin::opcodes::all::OP_CHECKSIG;
+    use bitcoin::script::Builder as ScriptBuilder;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, Network, OutPoint, PrivateKey, Sequence, TxIn};
+
+    use super::*;
+    use crate::secret::SecretPrivateKey;
+    use crate::signer::Signer;
+    use crate::taproot;
+    use crate::{RECIPIENT_ADDRESS_MNEMONIC, SENDER_ADDRESS_MNEMONIC};
+
+    #[test]
+    fn test_should_sign_and_finalize_reveal_psbt() {
+        let secp = Secp256k1::new();
+        let (keypair, x_public_key) = taproot::generate_keypair(&secp);
+        let redeem_script = ScriptBuilder::new()
+            .push_x_only_key(&x_public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let taproot_payload = taproot::TaprootPayload::build(
+            &secp,
+            keypair,
+            x_public_key,
+            &redeem_script,
+            5_000,
+            Network::Testnet,
+        )
+        .unwrap();
+
+        let reveal_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_consensus(0xffffffff),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(333),
+                script_pubkey: taproot_payload.address.script_pubkey(),
+            }],
+        };
+        let psbt = reveal_psbt(reveal_tx, &taproot_payload, &redeem_script).unwrap();
+
+        // BIP371 fields survive serialization
+        let psbt = Psbt::deserialize(&psbt.serialize()).unwrap();
+        let input = &psbt.inputs[0];
+        assert_eq!(input.tap_internal_key, Some(x_public_key));
+        assert!(input.tap_merkle_root.is_some());
+        assert_eq!(input.tap_scripts.len(), 1);
+        assert!(input.tap_key_origins.contains_key(&x_public_key));
+
+        let reveal_key =
+            SecretPrivateKey::new(PrivateKey::new(keypair.secret_key(), Network::Testnet));
+        let mut signer = Signer::new(&reveal_key, &secp, psbt.unsigned_tx.clone());
+        let mut psbt = psbt;
+        assert_eq!(signer.sign_psbt_tap_script(&mut psbt).unwrap(), 1);
+        assert_eq!(psbt.inputs[0].tap_script_sigs.len(), 1);
+
+        let tx = finalize_reveal(psbt, &FeePolicy::default()).unwrap();
+        let witness = &tx.input[0].witness;
+        assert_eq!(witness.len(), 3);
+        assert_eq!(witness.nth(1).unwrap(), redeem_script.as_bytes());
+    }
+
+    #[test]
+    fn test_should_sign_partially_with_one_key_per_account() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let recipient = Account::from_mnemonic(&secp, RECIPIENT_ADDRESS_MNEMONIC).unwrap();
+
+        let unsigned_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(9_000),
+                script_pubkey: recipient.address.script_pubkey(),
+            }],
+        };
+        let witness_script = ScriptBuilder::new()
+            .push_key(&sender.public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let tx = sign_partially(
+            &secp,
+            unsigned_tx,
+            &sender,
+            &[&recipient],
+            TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: sender.address.script_pubkey(),
+            },
+            &witness_script,
+            SighashType::All,
+            &FeePolicy::default(),
+        )
+        .unwrap();
+        let witness = &tx.input[0].witness;
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness.nth(1).unwrap(), witness_script.as_bytes());
+    }
 }
@@ -0,0 +1,766 @@
+//! PSBT version 2 (BIP370)
+//!
+//! rust-bitcoin only knows about version 0, so the per-input and per-output maps are
+//! (de)serialized by `Psbt` and only the BIP370 fields are handled here.
+
+use std::collections::BTreeMap;
+
+use bitcoin::absolute::{Height, LockTime, Time};
+use bitcoin::bip32::{KeySource, Xpub};
+use bitcoin::consensus::encode::{self, Decodable as _, Encodable as _, VarInt};
+use bitcoin::psbt::{raw, GetKey, Input, Output};
+use bitcoin::secp256k1::{Secp256k1, Signing};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Amount, EcdsaSighashType, OutPoint, Psbt, ScriptBuf, Sequence, TapSighashType, Transaction,
+    TxIn, TxOut, Txid, Witness,
+};
+
+use crate::error::{Error, PsbtError, Result, SigningError};
+
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const PSBT_GLOBAL_TX_VERSION: u8 = 0x02;
+const PSBT_GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+const PSBT_GLOBAL_INPUT_COUNT: u8 = 0x04;
+const PSBT_GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+const PSBT_GLOBAL_TX_MODIFIABLE: u8 = 0x06;
+const PSBT_GLOBAL_VERSION: u8 = 0xfb;
+
+const PSBT_IN_PREVIOUS_TXID: u8 = 0x0e;
+const PSBT_IN_OUTPUT_INDEX: u8 = 0x0f;
+const PSBT_IN_SEQUENCE: u8 = 0x10;
+const PSBT_IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+
+const PSBT_OUT_AMOUNT: u8 = 0x03;
+const PSBT_OUT_SCRIPT: u8 = 0x04;
+
+/// Raw key-value map
+type RawMap = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// `PSBT_GLOBAL_TX_MODIFIABLE` flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxModifiable {
+    /// Inputs can be added or removed
+    pub inputs: bool,
+    /// Outputs can be added or removed
+    pub outputs: bool,
+    /// At least one input is signed with `SIGHASH_SINGLE`, so its paired output must stay in place
+    pub has_sighash_single: bool,
+}
+
+impl TxModifiable {
+    fn to_byte(self) -> u8 {
+        (self.inputs as u8) | (self.outputs as u8) << 1 | (self.has_sighash_single as u8) << 2
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        Self {
+            inputs: byte & 0x01 != 0,
+            outputs: byte & 0x02 != 0,
+            has_sighash_single: byte & 0x04 != 0,
+        }
+    }
+}
+
+/// PSBTv2 input: the spent outpoint plus the usual BIP174 input map
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputV2 {
+    pub previous_output: OutPoint,
+    pub sequence: Option<Sequence>,
+    pub required_time_locktime: Option<Time>,
+    pub required_height_locktime: Option<Height>,
+    pub psbt: Input,
+}
+
+/// PSBTv2 output: amount and script plus the usual BIP174 output map
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputV2 {
+    pub amount: Amount,
+    pub script_pubkey: ScriptBuf,
+    pub psbt: Output,
+}
+
+/// Partially signed transaction, version 2
+#[derive(Debug, Clone, PartialEq)]
+pub struct PsbtV2 {
+    pub tx_version: Version,
+    pub fallback_locktime: Option<LockTime>,
+    pub tx_modifiable: TxModifiable,
+    pub xpub: BTreeMap<Xpub, KeySource>,
+    pub inputs: Vec<InputV2>,
+    pub outputs: Vec<OutputV2>,
+}
+
+impl InputV2 {
+    pub fn new(previous_output: OutPoint, psbt: Input) -> Self {
+        Self {
+            previous_output,
+            sequence: None,
+            required_time_locktime: None,
+            required_height_locktime: None,
+            psbt,
+        }
+    }
+
+    fn is_signed(&self) -> bool {
+        !self.psbt.partial_sigs.is_empty()
+            || !self.psbt.tap_script_sigs.is_empty()
+            || self.psbt.tap_key_sig.is_some()
+    }
+}
+
+impl OutputV2 {
+    pub fn new(tx_out: TxOut) -> Self {
+        Self {
+            amount: tx_out.value,
+            script_pubkey: tx_out.script_pubkey,
+            psbt: Output::default(),
+        }
+    }
+}
+
+impl PsbtV2 {
+    /// Create an empty PSBT with both inputs and outputs modifiable
+    pub fn new(tx_version: Version, fallback_locktime: Option<LockTime>) -> Self {
+        Self {
+            tx_version,
+            fallback_locktime,
+            tx_modifiable: TxModifiable {
+                inputs: true,
+                outputs: true,
+                has_sighash_single: false,
+            },
+            xpub: BTreeMap::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Convert a version 0 PSBT. The result is not modifiable.
+    pub fn from_v0(psbt: Psbt) -> Self {
+        let Psbt {
+            unsigned_tx,
+            xpub,
+            inputs,
+            outputs,
+            ..
+        } = psbt;
+
+        let inputs = unsigned_tx
+            .input
+            .into_iter()
+            .zip(inputs)
+            .map(|(tx_in, psbt)| InputV2 {
+                sequence: Some(tx_in.sequence),
+                ..InputV2::new(tx_in.previous_output, psbt)
+            })
+            .collect();
+        let outputs = unsigned_tx
+            .output
+            .into_iter()
+            .zip(outputs)
+            .map(|(tx_out, psbt)| OutputV2 {
+                psbt,
+                ..OutputV2::new(tx_out)
+            })
+            .collect();
+
+        Self {
+            tx_version: unsigned_tx.version,
+            fallback_locktime: Some(unsigned_tx.lock_time),
+            tx_modifiable: TxModifiable::default(),
+            xpub,
+            inputs,
+            outputs,
+        }
+    }
+
+    /// Convert to a version 0 PSBT, freezing the transaction
+    pub fn to_v0(&self) -> Result<Psbt> {
+        Ok(self.as_v0(self.lock_time()?))
+    }
+
+    /// Append an input; requires the inputs to be modifiable
+    pub fn add_input(&mut self, input: InputV2) -> Result<()> {
+        if !self.tx_modifiable.inputs {
+            return Err(PsbtError::NotModifiable("inputs").into());
+        }
+
+        let lock_time = self.lock_time()?;
+        self.inputs.push(input);
+        match self.lock_time() {
+            Ok(new_lock_time) if new_lock_time == lock_time || !self.is_signed() => Ok(()),
+            Ok(_) => {
+                self.inputs.pop();
+                Err(PsbtError::LockTimeChange.into())
+            }
+            Err(err) => {
+                self.inputs.pop();
+                Err(err)
+            }
+        }
+    }
+
+    /// Append an output; requires the outputs to be modifiable
+    pub fn add_output(&mut self, output: OutputV2) -> Result<()> {
+        if !self.tx_modifiable.outputs {
+            return Err(PsbtError::NotModifiable("outputs").into());
+        }
+        self.outputs.push(output);
+
+        Ok(())
+    }
+
+    /// Sign the inputs through `sign` on the equivalent version 0 PSBT, then copy the
+    /// signatures back and update the modifiable flags as required by BIP370.
+    pub fn sign_with<T>(&mut self, sign: impl FnOnce(&mut Psbt) -> T) -> Result<T> {
+        let mut psbt = self.to_v0()?;
+        let result = sign(&mut psbt);
+
+        for (input, signed) in self.inputs.iter_mut().zip(psbt.inputs) {
+            let sighash_types = signed
+                .partial_sigs
+                .iter()
+                .filter(|(key, _)| !input.psbt.partial_sigs.contains_key(key))
+                .map(|(_, sig)| sig.hash_ty)
+                .chain(
+                    signed
+                        .tap_script_sigs
+                        .iter()
+                        .filter(|(key, _)| !input.psbt.tap_script_sigs.contains_key(key))
+                        .map(|(_, sig)| tap_to_ecdsa_sighash_type(sig.hash_ty)),
+                )
+                .chain(
+                    signed
+                        .tap_key_sig
+                        .filter(|_| input.psbt.tap_key_sig.is_none())
+                        .map(|sig| tap_to_ecdsa_sighash_type(sig.hash_ty)),
+                )
+                .collect::<Vec<_>>();
+
+            for sighash_type in sighash_types {
+                let anyone_can_pay = matches!(
+                    sighash_type,
+                    EcdsaSighashType::AllPlusAnyoneCanPay
+                        | EcdsaSighashType::NonePlusAnyoneCanPay
+                        | EcdsaSighashType::SinglePlusAnyoneCanPay
+                );
+                if !anyone_can_pay {
+                    self.tx_modifiable.inputs = false;
+                }
+                // every signature but SIGHASH_NONE commits to some outputs
+                match sighash_type {
+                    EcdsaSighashType::None | EcdsaSighashType::NonePlusAnyoneCanPay => {}
+                    EcdsaSighashType::Single | EcdsaSighashType::SinglePlusAnyoneCanPay => {
+                        self.tx_modifiable.outputs = false;
+                        self.tx_modifiable.has_sighash_single = true;
+                    }
+                    EcdsaSighashType::All | EcdsaSighashType::AllPlusAnyoneCanPay => {
+                        self.tx_modifiable.outputs = false
+                    }
+                }
+            }
+
+            input.psbt = signed;
+        }
+
+        Ok(result)
+    }
+
+    /// Sign every ECDSA input for which `key` provides a key
+    pub fn sign<C: Signing, K: GetKey>(&mut self, key: &K, secp: &Secp256k1<C>) -> Result<usize> {
+        match self.sign_with(|psbt| psbt.sign(key, secp))? {
+            Ok(keys) => Ok(keys.values().map(Vec::len).sum()),
+            Err((_, errors)) => Err(SigningError::from_psbt(errors).into()),
+        }
+    }
+
+    /// Serialize in the BIP370 format
+    pub fn serialize(&self) -> Vec<u8> {
+        let psbt = self.as_v0(LockTime::ZERO).serialize();
+        let mut bytes = &psbt[PSBT_MAGIC.len()..];
+
+        // the v0 serialization is well-formed, so reading it back cannot fail
+        let mut global = read_map(&mut bytes).expect("valid global map");
+        global.retain(|(key, _)| {
+            key[..] != [PSBT_GLOBAL_UNSIGNED_TX] && key[..] != [PSBT_GLOBAL_VERSION]
+        });
+        global.push((
+            vec![PSBT_GLOBAL_TX_VERSION],
+            encode::serialize(&self.tx_version.0),
+        ));
+        if let Some(fallback_locktime) = self.fallback_locktime {
+            global.push((
+                vec![PSBT_GLOBAL_FALLBACK_LOCKTIME],
+                encode::serialize(&fallback_locktime),
+            ));
+        }
+        global.push((
+            vec![PSBT_GLOBAL_INPUT_COUNT],
+            encode::serialize(&VarInt(self.inputs.len() as u64)),
+        ));
+        global.push((
+            vec![PSBT_GLOBAL_OUTPUT_COUNT],
+            encode::serialize(&VarInt(self.outputs.len() as u64)),
+        ));
+        global.push((
+            vec![PSBT_GLOBAL_TX_MODIFIABLE],
+            vec![self.tx_modifiable.to_byte()],
+        ));
+        global.push((vec![PSBT_GLOBAL_VERSION], encode::serialize(&2u32)));
+
+        let mut out = PSBT_MAGIC.to_vec();
+        write_map(&mut out, global);
+
+        for input in &self.inputs {
+            let mut map = read_map(&mut bytes).expect("valid input map");
+            map.push((
+                vec![PSBT_IN_PREVIOUS_TXID],
+                encode::serialize(&input.previous_output.txid),
+            ));
+            map.push((
+                vec![PSBT_IN_OUTPUT_INDEX],
+                encode::serialize(&input.previous_output.vout),
+            ));
+            if let Some(sequence) = input.sequence {
+                map.push((vec![PSBT_IN_SEQUENCE], encode::serialize(&sequence)));
+            }
+            if let Some(time) = input.required_time_locktime {
+                map.push((
+                    vec![PSBT_IN_REQUIRED_TIME_LOCKTIME],
+                    encode::serialize(&time.to_consensus_u32()),
+                ));
+            }
+            if let Some(height) = input.required_height_locktime {
+                map.push((
+                    vec![PSBT_IN_REQUIRED_HEIGHT_LOCKTIME],
+                    encode::serialize(&height.to_consensus_u32()),
+                ));
+            }
+            write_map(&mut out, map);
+        }
+
+        for output in &self.outputs {
+            let mut map = read_map(&mut bytes).expect("valid output map");
+            map.push((
+                vec![PSBT_OUT_AMOUNT],
+                encode::serialize(&output.amount.to_sat()),
+            ));
+            map.push((vec![PSBT_OUT_SCRIPT], output.script_pubkey.to_bytes()));
+            write_map(&mut out, map);
+        }
+
+        out
+    }
+
+    /// Deserialize from the BIP370 format
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        let mut bytes = bytes
+            .strip_prefix(PSBT_MAGIC)
+            .ok_or(PsbtError::Malformed("invalid PSBT magic"))?;
+
+        let mut global = read_map(&mut bytes)?;
+        let version: u32 = take_field(&mut global, PSBT_GLOBAL_VERSION)?
+            .ok_or(PsbtError::Malformed("missing PSBT version"))?;
+        if version != 2 {
+            return Err(PsbtError::UnsupportedVersion { version }.into());
+        }
+        if global
+            .iter()
+            .any(|(key, _)| key[..] == [PSBT_GLOBAL_UNSIGNED_TX])
+        {
+            return Err(
+                PsbtError::Malformed("PSBTv2 must not contain an unsigned transaction").into(),
+            );
+        }
+        let tx_version = Version(
+            take_field(&mut global, PSBT_GLOBAL_TX_VERSION)?
+                .ok_or(PsbtError::Malformed("missing transaction version"))?,
+        );
+        let fallback_locktime = take_field(&mut global, PSBT_GLOBAL_FALLBACK_LOCKTIME)?;
+        let VarInt(input_count) = take_field(&mut global, PSBT_GLOBAL_INPUT_COUNT)?
+            .ok_or(PsbtError::Malformed("missing input count"))?;
+        let VarInt(output_count) = take_field(&mut global, PSBT_GLOBAL_OUTPUT_COUNT)?
+            .ok_or(PsbtError::Malformed("missing output count"))?;
+        let tx_modifiable = take_field::<u8>(&mut global, PSBT_GLOBAL_TX_MODIFIABLE)?
+            .map(TxModifiable::from_byte)
+            .unwrap_or_default();
+
+        // the counts are untrusted, the maps read below bound the allocations
+        let mut inputs = Vec::new();
+        let mut input_maps = Vec::new();
+        for _ in 0..input_count {
+            let mut map = read_map(&mut bytes)?;
+            let txid: Txid = take_field(&mut map, PSBT_IN_PREVIOUS_TXID)?
+                .ok_or(PsbtError::Malformed("missing previous txid"))?;
+            let vout: u32 = take_field(&mut map, PSBT_IN_OUTPUT_INDEX)?
+                .ok_or(PsbtError::Malformed("missing output index"))?;
+            let sequence = take_field(&mut map, PSBT_IN_SEQUENCE)?;
+            let required_time_locktime =
+                take_field::<u32>(&mut map, PSBT_IN_REQUIRED_TIME_LOCKTIME)?
+                    .map(Time::from_consensus)
+                    .transpose()?;
+            let required_height_locktime =
+                take_field::<u32>(&mut map, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME)?
+                    .map(Height::from_consensus)
+                    .transpose()?;
+
+            inputs.push(InputV2 {
+                previous_output: OutPoint { txid, vout },
+                sequence,
+                required_time_locktime,
+                required_height_locktime,
+                psbt: Input::default(),
+            });
+            input_maps.push(map);
+        }
+
+        let mut outputs = Vec::new();
+        let mut output_maps = Vec::new();
+        for _ in 0..output_count {
+            let mut map = read_map(&mut bytes)?;
+            let amount: u64 = take_field(&mut map, PSBT_OUT_AMOUNT)?
+                .ok_or(PsbtError::Malformed("missing output amount"))?;
+            let index = map
+                .iter()
+                .position(|(key, _)| key[..] == [PSBT_OUT_SCRIPT])
+                .ok_or(PsbtError::Malformed("missing output script"))?;
+            let (_, script) = map.remove(index);
+
+            outputs.push(OutputV2 {
+                amount: Amount::from_sat(amount),
+                script_pubkey: ScriptBuf::from_bytes(script),
+                psbt: Output::default(),
+            });
+            output_maps.push(map);
+        }
+
+        let mut psbt_v2 = Self {
+            tx_version,
+            fallback_locktime,
+            tx_modifiable,
+            xpub: BTreeMap::new(),
+            inputs,
+            outputs,
+        };
+
+        // let rust-bitcoin parse the remaining fields as a version 0 PSBT
+        let mut v0 = PSBT_MAGIC.to_vec();
+        global.push((
+            vec![PSBT_GLOBAL_UNSIGNED_TX],
+            encode::serialize(&psbt_v2.unsigned_tx(LockTime::ZERO)),
+        ));
+        write_map(&mut v0, global);
+        for map in input_maps.into_iter().chain(output_maps) {
+            write_map(&mut v0, map);
+        }
+        let psbt = Psbt::deserialize(&v0)?;
+
+        psbt_v2.xpub = psbt.xpub;
+        for (input, psbt) in psbt_v2.inputs.iter_mut().zip(psbt.inputs) {
+            input.psbt = psbt;
+        }
+        for (output, psbt) in psbt_v2.outputs.iter_mut().zip(psbt.outputs) {
+            output.psbt = psbt;
+        }
+
+        Ok(psbt_v2)
+    }
+
+    /// Determine the transaction locktime from the input requirements (BIP370)
+    pub fn lock_time(&self) -> Result<LockTime> {
+        let constrained = self
+            .inputs
+            .iter()
+            .filter(|input| {
+                input.required_time_locktime.is_some() || input.required_height_locktime.is_some()
+            })
+            .collect::<Vec<_>>();
+        if constrained.is_empty() {
+            return Ok(self.fallback_locktime.unwrap_or(LockTime::ZERO));
+        }
+
+        if constrained
+            .iter()
+            .all(|input| input.required_height_locktime.is_some())
+        {
+            let height = constrained
+                .iter()
+                .filter_map(|input| input.required_height_locktime)
+                .max()
+                .expect("at least one input");
+            Ok(LockTime::Blocks(height))
+        } else if constrained
+            .iter()
+            .all(|input| input.required_time_locktime.is_some())
+        {
+            let time = constrained
+                .iter()
+                .filter_map(|input| input.required_time_locktime)
+                .max()
+                .expect("at least one input");
+            Ok(LockTime::Seconds(time))
+        } else {
+            Err(PsbtError::IncompatibleLockTimes.into())
+        }
+    }
+
+    fn is_signed(&self) -> bool {
+        self.inputs.iter().any(InputV2::is_signed)
+    }
+
+    fn unsigned_tx(&self, lock_time: LockTime) -> Transaction {
+        Transaction {
+            version: self.tx_version,
+            lock_time,
+            input: self
+                .inputs
+                .iter()
+                .map(|input| TxIn {
+                    previous_output: input.previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence: input.sequence.unwrap_or(Sequence::MAX),
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: self
+                .outputs
+                .iter()
+                .map(|output| TxOut {
+                    value: output.amount,
+                    script_pubkey: output.script_pubkey.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn as_v0(&self, lock_time: LockTime) -> Psbt {
+        Psbt {
+            unsigned_tx: self.unsigned_tx(lock_time),
+            version: 0,
+            xpub: self.xpub.clone(),
+            proprietary: BTreeMap::<raw::ProprietaryKey, Vec<u8>>::new(),
+            unknown: BTreeMap::<raw::Key, Vec<u8>>::new(),
+            inputs: self.inputs.iter().map(|input| input.psbt.clone()).collect(),
+            outputs: self
+                .outputs
+                .iter()
+                .map(|output| output.psbt.clone())
+                .collect(),
+        }
+    }
+}
+
+impl From<Psbt> for PsbtV2 {
+    fn from(psbt: Psbt) -> Self {
+        Self::from_v0(psbt)
+    }
+}
+
+impl TryFrom<PsbtV2> for Psbt {
+    type Error = Error;
+
+    fn try_from(psbt: PsbtV2) -> Result<Self> {
+        psbt.to_v0()
+    }
+}
+
+/// Map a taproot sighash type onto its ECDSA equivalent for the modifiable flags
+fn tap_to_ecdsa_sighash_type(sighash_type: TapSighashType) -> EcdsaSighashType {
+    match sighash_type {
+        TapSighashType::Default | TapSighashType::All => EcdsaSighashType::All,
+        TapSighashType::None => EcdsaSighashType::None,
+        TapSighashType::Single => EcdsaSighashType::Single,
+        TapSighashType::AllPlusAnyoneCanPay => EcdsaSighashType::AllPlusAnyoneCanPay,
+        TapSighashType::NonePlusAnyoneCanPay => EcdsaSighashType::NonePlusAnyoneCanPay,
+        TapSighashType::SinglePlusAnyoneCanPay => EcdsaSighashType::SinglePlusAnyoneCanPay,
+    }
+}
+
+fn read_map(bytes: &mut &[u8]) -> Result<RawMap> {
+    let mut map = RawMap::new();
+    loop {
+        let VarInt(key_len) = VarInt::consensus_decode(bytes)?;
+        if key_len == 0 {
+            return Ok(map);
+        }
+        let key = read_bytes(bytes, key_len as usize)?;
+        let VarInt(value_len) = VarInt::consensus_decode(bytes)?;
+        let value = read_bytes(bytes, value_len as usize)?;
+        if map.iter().any(|(existing, _)| *existing == key) {
+            return Err(PsbtError::DuplicateKey {
+                key: hex::encode(&key),
+            }
+            .into());
+        }
+        map.push((key, value));
+    }
+}
+
+fn read_bytes(bytes: &mut &[u8], len: usize) -> Result<Vec<u8>> {
+    if bytes.len() < len {
+        return Err(PsbtError::Malformed("unexpected end of PSBT").into());
+    }
+    let (head, tail) = bytes.split_at(len);
+    *bytes = tail;
+    Ok(head.to_vec())
+}
+
+fn write_map(out: &mut Vec<u8>, map: RawMap) {
+    for (key, value) in map {
+        VarInt(key.len() as u64)
+            .consensus_encode(out)
+            .expect("in-memory writers don't error");
+        out.extend(key);
+        VarInt(value.len() as u64)
+            .consensus_encode(out)
+            .expect("in-memory writers don't error");
+        out.extend(value);
+    }
+    out.push(0x00);
+}
+
+/// Remove the field with the given key type and no key data from the map and decode it
+fn take_field<T: encode::Decodable>(map: &mut RawMap, key_type: u8) -> Result<Option<T>> {
+    let Some(index) = map.iter().position(|(key, _)| key[..] == [key_type]) else {
+        return Ok(None);
+    };
+    let (_, value) = map.remove(index);
+
+    Ok(Some(encode::deserialize(&value)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr as _;
+
+    use bitcoin::bip32::ChildNumber;
+    use bitcoin::secp256k1::All;
+
+    use super::*;
+    use crate::{Account, SENDER_ADDRESS_MNEMONIC};
+
+    fn p2wpkh_input(secp: &Secp256k1<All>, account: &Account, vout: u32) -> InputV2 {
+        let zero = ChildNumber::from_normal_idx(0).unwrap();
+        let mut input = Input {
+            witness_utxo: Some(TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: account.address.script_pubkey(),
+            }),
+            ..Default::default()
+        };
+        input.bip32_derivation.insert(
+            account.public_key.inner,
+            (
+                account.private_key.fingerprint(secp),
+                account.path.extend([zero, zero]),
+            ),
+        );
+
+        InputV2::new(
+            OutPoint {
+                txid: Txid::from_str(
+                    "14a7109b642b4fca7f10cd9bee89db73770c5a2d107f6a51c6bd7625dcdc2aed",
+                )
+                .unwrap(),
+                vout,
+            },
+            input,
+        )
+    }
+
+    #[test]
+    fn test_should_roundtrip_v2_serialization() {
+        let secp = Secp256k1::new();
+        let account = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+
+        let mut psbt = PsbtV2::new(Version::TWO, None);
+        let mut input = p2wpkh_input(&secp, &account, 0);
+        input.required_height_locktime = Some(Height::from_consensus(800_000).unwrap());
+        psbt.add_input(input).unwrap();
+        psbt.add_output(OutputV2::new(TxOut {
+            value: Amount::from_sat(9_000),
+            script_pubkey: account.address.script_pubkey(),
+        }))
+        .unwrap();
+
+        let decoded = PsbtV2::deserialize(&psbt.serialize()).unwrap();
+        assert_eq!(decoded, psbt);
+        assert_eq!(
+            decoded.lock_time().unwrap(),
+            LockTime::from_height(800_000).unwrap()
+        );
+
+        let v0 = decoded.to_v0().unwrap();
+        assert_eq!(v0.unsigned_tx.input.len(), 1);
+        assert_eq!(PsbtV2::from_v0(v0.clone()).to_v0().unwrap(), v0);
+    }
+
+    #[test]
+    fn test_should_reject_counts_beyond_the_maps() {
+        let mut bytes = PSBT_MAGIC.to_vec();
+        write_map(
+            &mut bytes,
+            vec![
+                (vec![PSBT_GLOBAL_VERSION], encode::serialize(&2u32)),
+                (
+                    vec![PSBT_GLOBAL_TX_VERSION],
+                    encode::serialize(&Version::TWO),
+                ),
+                (
+                    vec![PSBT_GLOBAL_INPUT_COUNT],
+                    encode::serialize(&VarInt(u64::MAX)),
+                ),
+                (
+                    vec![PSBT_GLOBAL_OUTPUT_COUNT],
+                    encode::serialize(&VarInt(0)),
+                ),
+            ],
+        );
+        assert!(PsbtV2::deserialize(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_should_keep_inputs_modifiable_after_anyone_can_pay_signature() {
+        let secp = Secp256k1::new();
+        let account = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+
+        let mut psbt = PsbtV2::new(Version::TWO, None);
+        let mut input = p2wpkh_input(&secp, &account, 0);
+        input.psbt.sighash_type = Some(EcdsaSighashType::SinglePlusAnyoneCanPay.into());
+        psbt.add_input(input).unwrap();
+        psbt.add_output(OutputV2::new(TxOut {
+            value: Amount::from_sat(9_000),
+            script_pubkey: account.address.script_pubkey(),
+        }))
+        .unwrap();
+
+        assert_eq!(psbt.sign(&account.private_key, &secp).unwrap(), 1);
+        assert!(psbt.tx_modifiable.inputs);
+        assert!(!psbt.tx_modifiable.outputs);
+        assert!(psbt.tx_modifiable.has_sighash_single);
+        assert!(psbt
+            .add_output(OutputV2::new(TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: account.address.script_pubkey(),
+            }))
+            .is_err());
+
+        // a buyer appends its own input and signs it with SIGHASH_ALL
+        psbt.add_input(p2wpkh_input(&secp, &account, 1)).unwrap();
+        assert_eq!(psbt.sign(&account.private_key, &secp).unwrap(), 2);
+        assert!(!psbt.tx_modifiable.inputs);
+        assert!(!psbt.tx_modifiable.outputs);
+        assert!(psbt.add_input(p2wpkh_input(&secp, &account, 2)).is_err());
+
+        let decoded = PsbtV2::deserialize(&psbt.serialize()).unwrap();
+        assert_eq!(decoded.inputs[0].psbt.partial_sigs.len(), 1);
+        assert_eq!(decoded.tx_modifiable, psbt.tx_modifiable);
+        assert!(decoded.to_v0().unwrap().unsigned_tx.output[0]
+            .script_pubkey
+            .is_p2wpkh());
+    }
+}
@@ -1,12 +1,12 @@
 use std::str::FromStr;
 use std::time::Duration;
 
-use bitcoin::{Network, Transaction, Txid};
+use bitcoin::{Address, Amount, Network, Transaction, Txid};
+use ord_rs::transaction::TxInput;
 
-pub async fn broadcast_transaction(
-    transaction: &Transaction,
-    network: Network,
-) -> anyhow::Result<Txid> {
+use crate::error::{Result, RpcError};
+
+pub async fn broadcast_transaction(transaction: &Transaction, network: Network) -> Result<Txid> {
     let network_str = match network {
         Network::Testnet => "/testnet",
         Network::Regtest => "/regtest",
@@ -26,19 +26,21 @@ pub async fn broadcast_transaction(
 
     debug!("result: {:?}", result);
 
-    if result.status().is_success() {
+    let status = result.status();
+    if status.is_success() {
         let txid = result.text().await?;
         debug!("txid: {txid}");
-        Ok(Txid::from_str(&txid)?)
+        parse_txid(&txid)
     } else {
-        Err(anyhow::anyhow!(
-            "failed to broadcast transaction: {}",
-            result.text().await?
-        ))
+        Err(RpcError::Broadcast {
+            status,
+            body: result.text().await?,
+        }
+        .into())
     }
 }
 
-pub async fn get_tx_by_hash(txid: &Txid, network: Network) -> anyhow::Result<ApiTransaction> {
+pub async fn get_tx_by_hash(txid: &Txid, network: Network) -> Result<ApiTransaction> {
     let network_str = match network {
         Network::Testnet => "/testnet",
         Network::Regtest => "/regtest",
@@ -51,8 +53,32 @@ pub async fn get_tx_by_hash(txid: &Txid, network: Network) -> anyhow::Result<Api
     Ok(tx)
 }
 
+pub async fn get_utxos(address: &Address, network: Network) -> Result<Vec<TxInput>> {
+    let network_str = match network {
+        Network::Testnet => "/testnet",
+        Network::Regtest => "/regtest",
+        Network::Signet => "/signet",
+        Network::Bitcoin | _ => "",
+    };
+
+    let url = format!("https://blockstream.info{network_str}/api/address/{address}/utxo");
+    let utxos: Vec<ApiUtxo> = reqwest::get(&url).await?.json().await?;
+    debug!("utxos of {address}: {utxos:?}");
+
+    utxos
+        .into_iter()
+        .map(|utxo| {
+            Ok(TxInput {
+                id: parse_txid(&utxo.txid)?,
+                index: utxo.vout,
+                amount: Amount::from_sat(utxo.value),
+            })
+        })
+        .collect()
+}
+
 #[allow(dead_code)]
-pub async fn wait_for_tx(txid: &Txid, network: Network) -> anyhow::Result<()> {
+pub async fn wait_for_tx(txid: &Txid, network: Network) -> Result<()> {
     loop {
         info!("waiting for transaction to be confirmed...");
         tokio::time::sleep(Duration::from_secs(10)).await;
@@ -65,12 +91,29 @@ pub async fn wait_for_tx(txid: &Txid, network: Network) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn parse_txid(txid: &str) -> Result<Txid> {
+    Txid::from_str(txid).map_err(|source| {
+        RpcError::InvalidTxid {
+            txid: txid.to_string(),
+            source,
+        }
+        .into()
+    })
+}
+
 #[derive(Debug, serde::Deserialize)]
 pub struct ApiTransaction {
-    vout: Vec<ApiVout>,
+    pub vout: Vec<ApiVout>,
 }
 
 #[derive(Debug, serde::Deserialize)]
 pub struct ApiVout {
+    pub value: u64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ApiUtxo {
+    txid: String,
+    vout: u32,
     value: u64,
 }
@@ -0,0 +1,147 @@
+use std::fmt;
+
+use bitcoin::bip32::{self, ChildNumber, Fingerprint, Xpriv};
+use bitcoin::psbt::{GetKey, GetKeyError, KeyRequest};
+use bitcoin::secp256k1::{All, Secp256k1, Signing};
+use bitcoin::{PrivateKey, PublicKey};
+use zeroize::Zeroize as _;
+
+/// Extended private key erased on drop and redacted from `Debug`.
+///
+/// It is not `Clone`: the key is borrowed through `expose_secret` where it is needed.
+pub struct SecretXpriv(Xpriv);
+
+/// Private key erased on drop and redacted from `Debug`; not `Clone`
+pub struct SecretPrivateKey(PrivateKey);
+
+impl SecretXpriv {
+    pub fn new(xpriv: Xpriv) -> Self {
+        Self(xpriv)
+    }
+
+    /// Borrow the key, e.g. for `Psbt::sign`; it must not be copied out
+    pub fn expose_secret(&self) -> &Xpriv {
+        &self.0
+    }
+
+    pub fn fingerprint(&self, secp: &Secp256k1<All>) -> Fingerprint {
+        self.0.fingerprint(secp)
+    }
+
+    /// Private key at `path` below this key
+    pub fn derive_private_key<P: AsRef<[ChildNumber]>>(
+        &self,
+        secp: &Secp256k1<All>,
+        path: &P,
+    ) -> Result<SecretPrivateKey, bip32::Error> {
+        let mut child = self.0.derive_priv(secp, path)?;
+        let private_key = SecretPrivateKey::new(child.to_priv());
+        erase_xpriv(&mut child);
+
+        Ok(private_key)
+    }
+}
+
+impl SecretPrivateKey {
+    pub fn new(private_key: PrivateKey) -> Self {
+        Self(private_key)
+    }
+
+    /// Borrow the key to sign with; it must not be copied out
+    pub fn expose_secret(&self) -> &PrivateKey {
+        &self.0
+    }
+
+    pub fn public_key(&self, secp: &Secp256k1<All>) -> PublicKey {
+        self.0.public_key(secp)
+    }
+}
+
+/// Lets `Psbt::sign` look up keys without exposing the extended key
+impl GetKey for SecretXpriv {
+    type Error = GetKeyError;
+
+    fn get_key<C: Signing>(
+        &self,
+        key_request: KeyRequest,
+        secp: &Secp256k1<C>,
+    ) -> Result<Option<PrivateKey>, Self::Error> {
+        self.0.get_key(key_request, secp)
+    }
+}
+
+/// Overwrite the secret parts of `xpriv`.
+///
+/// secp256k1 only offers `non_secure_erase` on its keys, the chain code is zeroized.
+fn erase_xpriv(xpriv: &mut Xpriv) {
+    xpriv.private_key.non_secure_erase();
+    let chain_code: &mut [u8] = xpriv.chain_code.as_mut();
+    chain_code.zeroize();
+}
+
+impl Drop for SecretXpriv {
+    fn drop(&mut self) {
+        erase_xpriv(&mut self.0);
+    }
+}
+
+impl Drop for SecretPrivateKey {
+    fn drop(&mut self) {
+        self.0.inner.non_secure_erase();
+    }
+}
+
+impl fmt::Debug for SecretXpriv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretXpriv({}, [redacted])", self.0.network)
+    }
+}
+
+impl fmt::Debug for SecretPrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretPrivateKey({}, [redacted])", self.0.network)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Account;
+    use crate::SENDER_ADDRESS_MNEMONIC;
+
+    #[test]
+    fn test_should_redact_secrets_in_debug_output() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let xpriv = sender.private_key.expose_secret().to_string();
+        let private_key = sender
+            .private_key
+            .derive_private_key(&secp, &sender.path)
+            .unwrap();
+        let wif = private_key.expose_secret().to_wif();
+
+        let account_debug = format!("{sender:?}");
+        assert!(account_debug.contains("[redacted]"));
+        assert!(!account_debug.contains(&xpriv));
+        let key_debug = format!("{private_key:?}");
+        assert!(!key_debug.contains(&wif));
+        assert!(!key_debug.contains(
+            &private_key
+                .expose_secret()
+                .inner
+                .display_secret()
+                .to_string()
+        ));
+
+        // erasing leaves the public parts of the key
+        let mut child = sender
+            .private_key
+            .expose_secret()
+            .derive_priv(&secp, &sender.path)
+            .unwrap();
+        let public_key = child.to_priv().public_key(&secp);
+        erase_xpriv(&mut child);
+        assert_ne!(child.to_priv().public_key(&secp), public_key);
+        assert_eq!(child.chain_code.as_bytes(), &[0; 32]);
+    }
+}
@@ -1,14 +1,30 @@
+use std::collections::BTreeMap;
+
 use bitcoin::hashes::Hash as _;
+use bitcoin::key::{Keypair, TapTweak as _, XOnlyPublicKey};
+use bitcoin::script::Builder as ScriptBuilder;
 use bitcoin::secp256k1::{All, Secp256k1};
 use bitcoin::sighash::{Prevouts, SighashCache};
-use bitcoin::taproot::{ControlBlock, LeafVersion};
+use bitcoin::taproot::{ControlBlock, LeafVersion, TapNodeHash};
 use bitcoin::{
-    secp256k1, PrivateKey, ScriptBuf, TapLeafHash, TapSighashType, Transaction, Witness,
+    secp256k1, Amount, EcdsaSighashType, FeeRate, PrivateKey, Psbt, Script, ScriptBuf, TapLeafHash,
+    TapSighashType, Transaction, TxOut, Witness,
 };
 use ord_rs::transaction::TxInput;
-use ord_rs::{OrdError, OrdResult};
+use rand::RngCore as _;
 
 use super::taproot::TaprootPayload;
+use crate::error::{PsbtError, Result, SigningError};
+use crate::inspect::{multisig_threshold, spent_utxo};
+use crate::key_provider::{controls_script, single_signature_key, KeyProvider};
+use crate::secret::SecretPrivateKey;
+use crate::signer_backend::{SignerBackend, TaprootTweak};
+use crate::utils::bytes_to_push_bytes;
+
+/// Largest usual DER ECDSA signature with its sighash byte, as assumed by Bitcoin Core
+const ECDSA_SIGNATURE_SIZE: usize = 72;
+/// ECDSA signature size with a low R, which takes one byte less
+const LOW_R_ECDSA_SIGNATURE_SIZE: usize = 71;
 
 /// Type of the transaction to sign
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -17,6 +33,43 @@ enum TransactionType {
     Reveal,
 }
 
+/// Sighash type to sign an input with, for both ECDSA and Schnorr signatures
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SighashType {
+    #[default]
+    All,
+    None,
+    Single,
+    AllPlusAnyoneCanPay,
+    NonePlusAnyoneCanPay,
+    SinglePlusAnyoneCanPay,
+}
+
+impl SighashType {
+    pub fn ecdsa(self) -> EcdsaSighashType {
+        match self {
+            Self::All => EcdsaSighashType::All,
+            Self::None => EcdsaSighashType::None,
+            Self::Single => EcdsaSighashType::Single,
+            Self::AllPlusAnyoneCanPay => EcdsaSighashType::AllPlusAnyoneCanPay,
+            Self::NonePlusAnyoneCanPay => EcdsaSighashType::NonePlusAnyoneCanPay,
+            Self::SinglePlusAnyoneCanPay => EcdsaSighashType::SinglePlusAnyoneCanPay,
+        }
+    }
+
+    /// Taproot sighash type; `All` maps to `Default`, which saves a byte in the signature
+    pub fn taproot(self) -> TapSighashType {
+        match self {
+            Self::All => TapSighashType::Default,
+            Self::None => TapSighashType::None,
+            Self::Single => TapSighashType::Single,
+            Self::AllPlusAnyoneCanPay => TapSighashType::AllPlusAnyoneCanPay,
+            Self::NonePlusAnyoneCanPay => TapSighashType::NonePlusAnyoneCanPay,
+            Self::SinglePlusAnyoneCanPay => TapSighashType::SinglePlusAnyoneCanPay,
+        }
+    }
+}
+
 enum Signature {
     Schnorr(bitcoin::taproot::Signature),
     Ecdsa(bitcoin::ecdsa::Signature),
@@ -34,33 +87,259 @@ impl From<bitcoin::ecdsa::Signature> for Signature {
     }
 }
 
+/// Inputs signed and skipped by the last signing call
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SigningReport {
+    pub signed: Vec<usize>,
+    pub skipped: Vec<SkippedInput>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedInput {
+    pub index: usize,
+    pub reason: SkipReason,
+}
+
+/// Why the signer left an input unsigned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// No key of the provider controls the input
+    UnknownKey,
+    /// The PSBT input has no UTXO to sign against
+    MissingUtxo,
+    /// The spent script pubkey is not one the signer knows how to spend
+    UnsupportedScript,
+    /// Taproot input of a PSBT, left to `sign_psbt_tap_key` and `sign_psbt_tap_script`
+    Taproot,
+    /// The witness script or tap leaf checks more signatures than the single one the signer adds
+    MultipleSignatures,
+}
+
+/// Output spent by an input of the transaction to sign
+#[derive(Debug, Clone)]
+pub struct SpentOutput {
+    pub prevout: TxOut,
+    /// Script needed when the script pubkey only commits to it
+    pub spend_script: Option<SpendScript>,
+    /// Path of the key in the `SignerBackend`, when signing through one
+    pub derivation_path: Option<Vec<Vec<u8>>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SpendScript {
+    /// Witness script of a P2WSH output
+    WitnessScript(ScriptBuf),
+    /// Redeem script of a P2SH output, the P2WPKH program of a nested segwit output
+    RedeemScript(ScriptBuf),
+    /// Leaf of a taproot output, spent through the script path
+    TapLeaf {
+        script: ScriptBuf,
+        control_block: ControlBlock,
+    },
+    /// Taproot output with a script tree, spent through the key path of its internal key
+    TapKey {
+        internal_key: XOnlyPublicKey,
+        merkle_root: Option<TapNodeHash>,
+    },
+}
+
+impl SpentOutput {
+    /// P2PKH, P2WPKH or BIP86 taproot output
+    pub fn new(prevout: TxOut) -> Self {
+        Self {
+            prevout,
+            spend_script: None,
+            derivation_path: None,
+        }
+    }
+
+    pub fn with_derivation_path(mut self, derivation_path: Vec<Vec<u8>>) -> Self {
+        self.derivation_path = Some(derivation_path);
+        self
+    }
+
+    pub fn with_witness_script(mut self, witness_script: ScriptBuf) -> Self {
+        self.spend_script = Some(SpendScript::WitnessScript(witness_script));
+        self
+    }
+
+    /// Spend a P2SH-P2WPKH output, whose `redeem_script` is the P2WPKH program
+    pub fn with_redeem_script(mut self, redeem_script: ScriptBuf) -> Self {
+        self.spend_script = Some(SpendScript::RedeemScript(redeem_script));
+        self
+    }
+
+    pub fn with_tap_leaf(mut self, script: ScriptBuf, control_block: ControlBlock) -> Self {
+        self.spend_script = Some(SpendScript::TapLeaf {
+            script,
+            control_block,
+        });
+        self
+    }
+
+    /// Spend a taproot output through the key path of `internal_key`, tweaked with `merkle_root`
+    pub fn with_internal_key(
+        mut self,
+        internal_key: XOnlyPublicKey,
+        merkle_root: Option<TapNodeHash>,
+    ) -> Self {
+        self.spend_script = Some(SpendScript::TapKey {
+            internal_key,
+            merkle_root,
+        });
+        self
+    }
+}
+
+/// Signature needed by an input, with what its witness is built from
+struct SignatureRequest<'s> {
+    index: usize,
+    /// Script the signing key is looked up by
+    key_script: ScriptBuf,
+    redeem_script: Option<&'s ScriptBuf>,
+    control_block: Option<&'s ControlBlock>,
+    message: secp256k1::Message,
+    kind: SignatureKind,
+    encoding: InputEncoding,
+}
+
+/// Where the signature of an input goes
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum InputEncoding {
+    /// Witness only, the script sig stays empty
+    Witness,
+    /// P2PKH script sig, without witness
+    Legacy,
+    /// P2WPKH witness, with a script sig pushing the redeem script
+    NestedWitness,
+}
+
+#[derive(Clone, Copy)]
+enum SignatureKind {
+    Ecdsa(EcdsaSighashType),
+    Schnorr {
+        hash_ty: TapSighashType,
+        /// Set for key path spends
+        tweak: Option<TaprootTweak>,
+    },
+}
+
+impl SigningReport {
+    fn skip(&mut self, index: usize, reason: SkipReason) {
+        warn!("skipping input {index}: {reason:?}");
+        self.skipped.push(SkippedInput { index, reason });
+    }
+}
+
 /// Transaction signer
 pub struct Signer<'a> {
-    private_key: &'a PrivateKey,
+    keys: &'a dyn KeyProvider,
     secp: &'a Secp256k1<All>,
     transaction: Transaction,
+    sighash_types: BTreeMap<usize, SighashType>,
+    low_r: bool,
+    aux_rand: bool,
+    report: SigningReport,
 }
 
 impl<'a> Signer<'a> {
+    /// Signer of the inputs controlled by a single key
     pub fn new(
-        private_key: &'a PrivateKey,
+        private_key: &'a SecretPrivateKey,
+        secp: &'a Secp256k1<All>,
+        transaction: Transaction,
+    ) -> Self {
+        Self::with_keys(private_key, secp, transaction)
+    }
+
+    /// Signer looking up the key of each input, e.g. among the addresses of an account
+    pub fn with_keys(
+        keys: &'a dyn KeyProvider,
         secp: &'a Secp256k1<All>,
         transaction: Transaction,
     ) -> Self {
         Self {
-            private_key,
+            keys,
             secp,
             transaction,
+            sighash_types: BTreeMap::new(),
+            low_r: false,
+            aux_rand: false,
+            report: SigningReport::default(),
         }
     }
 
+    /// Signer holding no key, for `sign_transaction_with_backend`
+    pub fn without_keys(secp: &'a Secp256k1<All>, transaction: Transaction) -> Self {
+        const NO_KEYS: &[PrivateKey] = &[];
+        Self::with_keys(&NO_KEYS, secp, transaction)
+    }
+
+    /// Inputs signed and skipped by the last signing call
+    pub fn report(&self) -> &SigningReport {
+        &self.report
+    }
+
+    /// Sign the input at `index` with the given sighash type instead of `SIGHASH_ALL`
+    pub fn with_sighash_type(mut self, index: usize, sighash_type: SighashType) -> Self {
+        self.sighash_types.insert(index, sighash_type);
+        self
+    }
+
+    /// Grind ECDSA signatures until R is low, saving a byte per signature like Bitcoin Core
+    pub fn with_low_r(mut self) -> Self {
+        self.low_r = true;
+        self
+    }
+
+    /// Add fresh BIP340 auxiliary randomness to Schnorr signatures instead of signing deterministically
+    pub fn with_aux_rand(mut self) -> Self {
+        self.aux_rand = true;
+        self
+    }
+
+    /// Virtual size of the transaction once every input of `spent` is signed by this signer
+    pub fn estimate_vsize(&self, spent: &[SpentOutput]) -> usize {
+        let mut transaction = self.transaction.clone();
+        for (index, (tx_in, spent)) in transaction.input.iter_mut().zip(spent).enumerate() {
+            tx_in.script_sig = self.estimated_script_sig(spent);
+            tx_in.witness = self.estimated_witness(index, spent);
+        }
+
+        transaction.vsize()
+    }
+
+    /// Fee paying `fee_rate` for the signed transaction
+    pub fn estimate_fee(&self, spent: &[SpentOutput], fee_rate: FeeRate) -> Amount {
+        fee_rate
+            .fee_vb(self.estimate_vsize(spent) as u64)
+            .unwrap_or(Amount::MAX_MONEY)
+    }
+
     /// Sign the commit transaction with the given txin script
     pub fn sign_commit_transaction(
         &mut self,
         inputs: &[TxInput],
         txin_script: &ScriptBuf,
-    ) -> OrdResult<Transaction> {
-        self.sign_ecdsa(inputs, txin_script, TransactionType::Commit)
+    ) -> Result<Transaction> {
+        let txin_scripts = vec![txin_script.as_script(); inputs.len()];
+        self.sign_ecdsa(inputs, &txin_scripts, TransactionType::Commit)
+    }
+
+    /// Sign the commit transaction spending P2WPKH outputs of several addresses.
+    ///
+    /// `txin_scripts` holds the script pubkey spent by each input of the transaction; inputs without
+    /// a known key are skipped.
+    pub fn sign_commit_transaction_with_scripts(
+        &mut self,
+        inputs: &[TxInput],
+        txin_scripts: &[ScriptBuf],
+    ) -> Result<Transaction> {
+        let txin_scripts = txin_scripts
+            .iter()
+            .map(ScriptBuf::as_script)
+            .collect::<Vec<_>>();
+        self.sign_ecdsa(inputs, &txin_scripts, TransactionType::Commit)
     }
 
     /// Sign the reveal transaction with the given redeem script using ECDSA (for P2WSH)
@@ -68,8 +347,12 @@ impl<'a> Signer<'a> {
         &mut self,
         input: &TxInput,
         redeem_script: &ScriptBuf,
-    ) -> OrdResult<Transaction> {
-        self.sign_ecdsa(&[input.clone()], redeem_script, TransactionType::Reveal)
+    ) -> Result<Transaction> {
+        self.sign_ecdsa(
+            std::slice::from_ref(input),
+            &[redeem_script.as_script()],
+            TransactionType::Reveal,
+        )
     }
 
     /// Sign the reveal transaction with the given redeem script (for P2TR)
@@ -77,20 +360,21 @@ impl<'a> Signer<'a> {
         &mut self,
         taproot: &TaprootPayload,
         redeem_script: &ScriptBuf,
-    ) -> OrdResult<Transaction> {
+    ) -> Result<Transaction> {
         let prevouts_array = vec![taproot.prevouts.clone()];
         let prevouts = Prevouts::All(&prevouts_array);
 
+        let sighash_type = self.sighash_type(0).taproot();
         let mut sighash_cache = SighashCache::new(self.transaction.clone());
         let sighash_sig = sighash_cache.taproot_script_spend_signature_hash(
             0,
             &prevouts,
             TapLeafHash::from_script(redeem_script, LeafVersion::TapScript),
-            TapSighashType::Default,
+            sighash_type,
         )?;
 
         let msg = secp256k1::Message::from_digest(sighash_sig.to_byte_array());
-        let sig = self.secp.sign_schnorr_no_aux_rand(&msg, &taproot.keypair);
+        let sig = self.schnorr_signature(&msg, &taproot.keypair);
 
         // verify
         self.secp
@@ -99,7 +383,7 @@ impl<'a> Signer<'a> {
         // append witness
         let signature = bitcoin::taproot::Signature {
             sig,
-            hash_ty: TapSighashType::Default,
+            hash_ty: sighash_type,
         }
         .into();
         self.append_witness_to_input(
@@ -107,47 +391,640 @@ impl<'a> Signer<'a> {
             signature,
             0,
             &taproot.keypair.public_key(),
-            Some(redeem_script),
+            Some(redeem_script.as_script()),
             Some(&taproot.control_block),
         )?;
 
         Ok(sighash_cache.into_transaction())
     }
 
+    /// Sign every input of the transaction according to the type of the output it spends.
+    ///
+    /// `spent` holds the output spent by each input, in input order; all of them are needed
+    /// for the taproot sighash. Inputs which cannot be signed are skipped and reported.
+    pub fn sign_transaction(&mut self, spent: &[SpentOutput]) -> Result<Transaction> {
+        let requests = self.signature_requests(spent)?;
+
+        let mut signatures = Vec::with_capacity(requests.len());
+        for request in requests {
+            let Some(private_key) = self.keys.key_by_script(self.secp, &request.key_script) else {
+                self.report.skip(request.index, SkipReason::UnknownKey);
+                continue;
+            };
+            let pubkey = private_key.expose_secret().inner.public_key(self.secp);
+
+            let signature = match request.kind {
+                SignatureKind::Ecdsa(hash_ty) => {
+                    let sig =
+                        self.ecdsa_signature(&request.message, &private_key.expose_secret().inner);
+                    // verify
+                    self.secp.verify_ecdsa(&request.message, &sig, &pubkey)?;
+
+                    bitcoin::ecdsa::Signature { sig, hash_ty }.into()
+                }
+                SignatureKind::Schnorr { hash_ty, tweak } => {
+                    let mut keypair =
+                        Keypair::from_secret_key(self.secp, &private_key.expose_secret().inner);
+                    if let Some(tweak) = tweak {
+                        keypair = keypair.tap_tweak(self.secp, tweak.merkle_root).to_inner();
+                    }
+                    let sig = self.schnorr_signature(&request.message, &keypair);
+                    // verify
+                    self.secp.verify_schnorr(
+                        &sig,
+                        &request.message,
+                        &keypair.x_only_public_key().0,
+                    )?;
+
+                    bitcoin::taproot::Signature { sig, hash_ty }.into()
+                }
+            };
+            signatures.push((request, signature, pubkey));
+        }
+
+        self.append_witnesses(signatures)
+    }
+
+    /// Sign every input of the transaction like `sign_transaction`, with the keys of `backend`.
+    ///
+    /// Each input is signed by the key at the `derivation_path` of its spent output; inputs
+    /// without one, or whose backend key does not control the spent script, are skipped.
+    pub async fn sign_transaction_with_backend<B: SignerBackend>(
+        &mut self,
+        backend: &B,
+        spent: &[SpentOutput],
+    ) -> Result<Transaction> {
+        let requests = self.signature_requests(spent)?;
+
+        let mut signatures = Vec::with_capacity(requests.len());
+        for request in requests {
+            let Some(derivation_path) = spent[request.index].derivation_path.as_deref() else {
+                self.report.skip(request.index, SkipReason::UnknownKey);
+                continue;
+            };
+            let pubkey = match request.kind {
+                SignatureKind::Ecdsa(_) => backend.ecdsa_public_key(derivation_path).await?,
+                SignatureKind::Schnorr { .. } => {
+                    backend.schnorr_public_key(derivation_path).await?
+                }
+            };
+            if !controls_script(
+                self.secp,
+                &bitcoin::PublicKey::new(pubkey),
+                &request.key_script,
+            ) {
+                self.report.skip(request.index, SkipReason::UnknownKey);
+                continue;
+            }
+
+            let signature = match request.kind {
+                SignatureKind::Ecdsa(hash_ty) => {
+                    let sig = backend
+                        .sign_with_ecdsa(derivation_path, &request.message)
+                        .await?;
+                    // verify
+                    self.secp.verify_ecdsa(&request.message, &sig, &pubkey)?;
+
+                    bitcoin::ecdsa::Signature { sig, hash_ty }.into()
+                }
+                SignatureKind::Schnorr { hash_ty, tweak } => {
+                    let sig = backend
+                        .sign_with_schnorr(derivation_path, &request.message, tweak)
+                        .await?;
+                    let mut x_only_key = XOnlyPublicKey::from(pubkey);
+                    if let Some(tweak) = tweak {
+                        x_only_key = x_only_key
+                            .tap_tweak(self.secp, tweak.merkle_root)
+                            .0
+                            .to_inner();
+                    }
+                    // verify
+                    self.secp
+                        .verify_schnorr(&sig, &request.message, &x_only_key)?;
+
+                    bitcoin::taproot::Signature { sig, hash_ty }.into()
+                }
+            };
+            signatures.push((request, signature, pubkey));
+        }
+
+        self.append_witnesses(signatures)
+    }
+
+    /// Compute the sighash of every input, skipping the ones spending unsupported scripts
+    fn signature_requests<'s>(
+        &mut self,
+        spent: &'s [SpentOutput],
+    ) -> Result<Vec<SignatureRequest<'s>>> {
+        self.report = SigningReport::default();
+        if spent.len() != self.transaction.input.len() {
+            return Err(SigningError::SpentOutputCount {
+                inputs: self.transaction.input.len(),
+                spent: spent.len(),
+            }
+            .into());
+        }
+        let prevouts_array = spent
+            .iter()
+            .map(|spent| spent.prevout.clone())
+            .collect::<Vec<_>>();
+
+        let mut hash = SighashCache::new(&self.transaction);
+        let mut requests = Vec::with_capacity(spent.len());
+        for (index, spent) in spent.iter().enumerate() {
+            let script_pubkey = &spent.prevout.script_pubkey;
+            let mut encoding = InputEncoding::Witness;
+            let (key_script, redeem_script, control_block, tweak) = match &spent.spend_script {
+                None if script_pubkey.is_p2wpkh() => (script_pubkey.clone(), None, None, None),
+                None if script_pubkey.is_p2pkh() => {
+                    encoding = InputEncoding::Legacy;
+                    (script_pubkey.clone(), None, None, None)
+                }
+                Some(SpendScript::RedeemScript(redeem_script))
+                    if redeem_script.is_p2wpkh() && *script_pubkey == redeem_script.to_p2sh() =>
+                {
+                    // signed as the P2WPKH output of the program
+                    encoding = InputEncoding::NestedWitness;
+                    (redeem_script.clone(), None, None, None)
+                }
+                // the witness would only hold one signature
+                Some(SpendScript::WitnessScript(script) | SpendScript::TapLeaf { script, .. })
+                    if single_signature_key(script).is_none() =>
+                {
+                    self.report.skip(index, SkipReason::MultipleSignatures);
+                    continue;
+                }
+                Some(SpendScript::WitnessScript(witness_script))
+                    if *script_pubkey == witness_script.to_p2wsh() =>
+                {
+                    (witness_script.clone(), Some(witness_script), None, None)
+                }
+                Some(SpendScript::TapLeaf {
+                    script,
+                    control_block,
+                }) if script_pubkey.is_p2tr() => {
+                    (script.clone(), Some(script), Some(control_block), None)
+                }
+                // BIP86 key path
+                None if script_pubkey.is_p2tr() => (
+                    script_pubkey.clone(),
+                    None,
+                    None,
+                    Some(TaprootTweak { merkle_root: None }),
+                ),
+                Some(SpendScript::TapKey {
+                    internal_key,
+                    merkle_root,
+                }) if *script_pubkey
+                    == ScriptBuf::new_p2tr(self.secp, *internal_key, *merkle_root) =>
+                {
+                    // the key of the internal key is the one of its BIP86 output
+                    let key_script = ScriptBuf::new_p2tr(self.secp, *internal_key, None);
+                    let tweak = TaprootTweak {
+                        merkle_root: *merkle_root,
+                    };
+                    (key_script, None, None, Some(tweak))
+                }
+                _ => {
+                    self.report.skip(index, SkipReason::UnsupportedScript);
+                    continue;
+                }
+            };
+
+            let (sighash, kind) = if script_pubkey.is_p2tr() {
+                let hash_ty = self.sighash_type(index).taproot();
+                let prevouts = match hash_ty {
+                    TapSighashType::AllPlusAnyoneCanPay
+                    | TapSighashType::NonePlusAnyoneCanPay
+                    | TapSighashType::SinglePlusAnyoneCanPay => {
+                        Prevouts::One(index, spent.prevout.clone())
+                    }
+                    _ => Prevouts::All(&prevouts_array),
+                };
+                let sighash = match control_block {
+                    Some(control_block) => hash.taproot_script_spend_signature_hash(
+                        index,
+                        &prevouts,
+                        TapLeafHash::from_script(&key_script, control_block.leaf_version),
+                        hash_ty,
+                    )?,
+                    None => hash.taproot_key_spend_signature_hash(index, &prevouts, hash_ty)?,
+                };
+
+                (
+                    sighash.to_byte_array(),
+                    SignatureKind::Schnorr { hash_ty, tweak },
+                )
+            } else {
+                let hash_ty = self.sighash_type(index).ecdsa();
+                let value = spent.prevout.value;
+                let sighash = match (encoding, redeem_script) {
+                    (InputEncoding::Legacy, _) => hash
+                        .legacy_signature_hash(index, &key_script, hash_ty.to_u32())?
+                        .to_byte_array(),
+                    (_, Some(witness_script)) => hash
+                        .p2wsh_signature_hash(index, witness_script, value, hash_ty)?
+                        .to_byte_array(),
+                    (_, None) => hash
+                        .p2wpkh_signature_hash(index, &key_script, value, hash_ty)?
+                        .to_byte_array(),
+                };
+
+                (sighash, SignatureKind::Ecdsa(hash_ty))
+            };
+
+            requests.push(SignatureRequest {
+                index,
+                key_script,
+                redeem_script,
+                control_block,
+                message: secp256k1::Message::from_digest(sighash),
+                kind,
+                encoding,
+            });
+        }
+
+        Ok(requests)
+    }
+
+    /// Build the witnesses and script sigs of the signed inputs
+    fn append_witnesses(
+        &mut self,
+        signatures: Vec<(SignatureRequest, Signature, secp256k1::PublicKey)>,
+    ) -> Result<Transaction> {
+        let mut hash = SighashCache::new(self.transaction.clone());
+        let mut script_sigs = Vec::new();
+        for (request, signature, pubkey) in signatures {
+            match (request.encoding, signature) {
+                (InputEncoding::Legacy, Signature::Ecdsa(signature)) => {
+                    let script_sig = ScriptBuilder::new()
+                        .push_slice(signature.serialize())
+                        .push_key(&bitcoin::PublicKey::new(pubkey))
+                        .into_script();
+                    script_sigs.push((request.index, script_sig));
+                }
+                (encoding, signature) => {
+                    if encoding == InputEncoding::NestedWitness {
+                        let script_sig = ScriptBuilder::new()
+                            .push_slice(bytes_to_push_bytes(request.key_script.as_bytes())?)
+                            .into_script();
+                        script_sigs.push((request.index, script_sig));
+                    }
+                    self.append_witness_to_input(
+                        &mut hash,
+                        signature,
+                        request.index,
+                        &pubkey,
+                        request.redeem_script.map(|script| script.as_script()),
+                        request.control_block,
+                    )?;
+                }
+            }
+            self.report.signed.push(request.index);
+        }
+
+        let mut transaction = hash.into_transaction();
+        for (index, script_sig) in script_sigs {
+            transaction.input[index].script_sig = script_sig;
+        }
+
+        Ok(transaction)
+    }
+
+    /// Sign the taproot key path inputs of the PSBT with the key of their `tap_internal_key`.
+    ///
+    /// The key is tweaked with `tap_merkle_root`; the signature is stored in `tap_key_sig`.
+    /// Returns the number of signatures produced.
+    pub fn sign_psbt_tap_key(&mut self, psbt: &mut Psbt) -> Result<usize> {
+        self.report = SigningReport::default();
+        let prevouts_array = psbt
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(index, input)| {
+                input
+                    .witness_utxo
+                    .clone()
+                    .ok_or_else(|| PsbtError::MissingUtxo { index }.into())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let prevouts = Prevouts::All(&prevouts_array);
+
+        let mut sighash_cache = SighashCache::new(&psbt.unsigned_tx);
+        let mut signed = 0;
+        for (index, input) in psbt.inputs.iter_mut().enumerate() {
+            let Some(internal_key) = input.tap_internal_key else {
+                continue;
+            };
+            // script path only outputs, such as the inscription commit, have no key origin for the internal key
+            let Some((_, key_source)) = input.tap_key_origins.get(&internal_key) else {
+                continue;
+            };
+            let Some(private_key) = self.keys.key_by_origin(self.secp, internal_key, key_source)
+            else {
+                self.report.skip(index, SkipReason::UnknownKey);
+                continue;
+            };
+            let sighash_type = match input.sighash_type {
+                Some(sighash_type) => {
+                    sighash_type
+                        .taproot_hash_ty()
+                        .map_err(|_| SigningError::SighashType {
+                            index,
+                            sighash_type,
+                        })?
+                }
+                None => self.sighash_type(index).taproot(),
+            };
+
+            let keypair = Keypair::from_secret_key(self.secp, &private_key.expose_secret().inner)
+                .tap_tweak(self.secp, input.tap_merkle_root)
+                .to_inner();
+            let sighash_sig =
+                sighash_cache.taproot_key_spend_signature_hash(index, &prevouts, sighash_type)?;
+            let msg = secp256k1::Message::from_digest(sighash_sig.to_byte_array());
+            let sig = self.schnorr_signature(&msg, &keypair);
+
+            // verify
+            self.secp
+                .verify_schnorr(&sig, &msg, &keypair.x_only_public_key().0)?;
+
+            input.tap_key_sig = Some(bitcoin::taproot::Signature {
+                sig,
+                hash_ty: sighash_type,
+            });
+            signed += 1;
+            self.report.signed.push(index);
+        }
+
+        Ok(signed)
+    }
+
+    /// Sign the taproot script path inputs of the PSBT, looking up the key of every `tap_key_origins` entry.
+    ///
+    /// The sighash type of the PSBT input takes precedence over the one configured on the signer.
+    /// Signatures are stored in `tap_script_sigs`; returns the number of signatures produced.
+    pub fn sign_psbt_tap_script(&mut self, psbt: &mut Psbt) -> Result<usize> {
+        self.report = SigningReport::default();
+        let prevouts_array = psbt
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(index, input)| {
+                input
+                    .witness_utxo
+                    .clone()
+                    .ok_or_else(|| PsbtError::MissingUtxo { index }.into())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let prevouts = Prevouts::All(&prevouts_array);
+
+        let mut sighash_cache = SighashCache::new(&psbt.unsigned_tx);
+        let mut signed = 0;
+        for (index, input) in psbt.inputs.iter_mut().enumerate() {
+            let origins = input
+                .tap_key_origins
+                .iter()
+                .filter(|(_, (leaf_hashes, _))| !leaf_hashes.is_empty())
+                .map(|(x_only_key, (leaf_hashes, key_source))| {
+                    (*x_only_key, leaf_hashes.clone(), key_source.clone())
+                })
+                .collect::<Vec<_>>();
+            if origins.is_empty() {
+                continue;
+            }
+            let sighash_type = match input.sighash_type {
+                Some(sighash_type) => {
+                    sighash_type
+                        .taproot_hash_ty()
+                        .map_err(|_| SigningError::SighashType {
+                            index,
+                            sighash_type,
+                        })?
+                }
+                None => self.sighash_type(index).taproot(),
+            };
+
+            let mut input_signed = false;
+            for (x_only_key, leaf_hashes, key_source) in origins {
+                let Some(private_key) = self.keys.key_by_origin(self.secp, x_only_key, &key_source)
+                else {
+                    continue;
+                };
+                let keypair =
+                    Keypair::from_secret_key(self.secp, &private_key.expose_secret().inner);
+
+                for leaf_hash in leaf_hashes {
+                    let sighash_sig = sighash_cache.taproot_script_spend_signature_hash(
+                        index,
+                        &prevouts,
+                        leaf_hash,
+                        sighash_type,
+                    )?;
+
+                    let msg = secp256k1::Message::from_digest(sighash_sig.to_byte_array());
+                    let sig = self.schnorr_signature(&msg, &keypair);
+
+                    // verify
+                    self.secp.verify_schnorr(&sig, &msg, &x_only_key)?;
+
+                    input.tap_script_sigs.insert(
+                        (x_only_key, leaf_hash),
+                        bitcoin::taproot::Signature {
+                            sig,
+                            hash_ty: sighash_type,
+                        },
+                    );
+                    signed += 1;
+                    input_signed = true;
+                }
+            }
+
+            if input_signed {
+                self.report.signed.push(index);
+            } else {
+                self.report.skip(index, SkipReason::UnknownKey);
+            }
+        }
+
+        Ok(signed)
+    }
+
+    /// Sign the P2WPKH, P2WSH, P2SH-P2WPKH and P2PKH inputs of the PSBT.
+    ///
+    /// The spent output comes from `witness_utxo` or `non_witness_utxo`, and a P2WPKH
+    /// `redeem_script` marks a nested segwit input. A `witness_script` is only signed when it
+    /// hashes to the spent output, directly or through a P2WSH `redeem_script`. Keys are looked up from `bip32_derivation`,
+    /// falling back to the spent script. Signatures are stored in `partial_sigs`; returns the
+    /// number of signatures produced. Taproot inputs are reported as skipped.
+    pub fn sign_psbt_ecdsa(&mut self, psbt: &mut Psbt) -> Result<usize> {
+        self.report = SigningReport::default();
+        let prevouts = (0..psbt.inputs.len())
+            .map(|index| spent_utxo(psbt, index).cloned())
+            .collect::<Vec<_>>();
+        let mut sighash_cache = SighashCache::new(&psbt.unsigned_tx);
+        let mut signed = 0;
+        for ((index, input), prevout) in psbt.inputs.iter_mut().enumerate().zip(prevouts) {
+            let spends_taproot = prevout
+                .as_ref()
+                .is_some_and(|prevout| prevout.script_pubkey.is_p2tr());
+            if spends_taproot
+                || input.tap_internal_key.is_some()
+                || !input.tap_key_origins.is_empty()
+            {
+                self.report.skip(index, SkipReason::Taproot);
+                continue;
+            }
+            let Some(prevout) = prevout else {
+                self.report.skip(index, SkipReason::MissingUtxo);
+                continue;
+            };
+            let sighash_type = match input.sighash_type {
+                Some(sighash_type) => {
+                    sighash_type
+                        .ecdsa_hash_ty()
+                        .map_err(|_| SigningError::SighashType {
+                            index,
+                            sighash_type,
+                        })?
+                }
+                None => self.sighash_type(index).ecdsa(),
+            };
+
+            // script the keys sign for: the witness script, the P2SH program or the output itself
+            let script = match (&input.witness_script, &input.redeem_script) {
+                (Some(witness_script), None)
+                    if witness_script.to_p2wsh() == prevout.script_pubkey =>
+                {
+                    witness_script.clone()
+                }
+                // P2SH-P2WSH, the redeem script is the P2WSH program
+                (Some(witness_script), Some(redeem_script))
+                    if *redeem_script == witness_script.to_p2wsh()
+                        && redeem_script.to_p2sh() == prevout.script_pubkey =>
+                {
+                    witness_script.clone()
+                }
+                (Some(_), _) => {
+                    self.report.skip(index, SkipReason::UnsupportedScript);
+                    continue;
+                }
+                (None, Some(redeem_script)) if redeem_script.to_p2sh() == prevout.script_pubkey => {
+                    redeem_script.clone()
+                }
+                (None, Some(_)) => {
+                    self.report.skip(index, SkipReason::UnsupportedScript);
+                    continue;
+                }
+                (None, None) => prevout.script_pubkey.clone(),
+            };
+            let signature_hash = if input.witness_script.is_some() {
+                sighash_cache
+                    .p2wsh_signature_hash(index, &script, prevout.value, sighash_type)?
+                    .to_byte_array()
+            } else if script.is_p2wpkh() {
+                sighash_cache
+                    .p2wpkh_signature_hash(index, &script, prevout.value, sighash_type)?
+                    .to_byte_array()
+            } else if script.is_p2pkh() {
+                sighash_cache
+                    .legacy_signature_hash(index, &script, sighash_type.to_u32())?
+                    .to_byte_array()
+            } else {
+                self.report.skip(index, SkipReason::UnsupportedScript);
+                continue;
+            };
+
+            let mut private_keys = input
+                .bip32_derivation
+                .iter()
+                .filter_map(|(public_key, key_source)| {
+                    self.keys
+                        .key_by_origin(self.secp, XOnlyPublicKey::from(*public_key), key_source)
+                        .filter(|private_key| {
+                            private_key.expose_secret().inner.public_key(self.secp) == *public_key
+                        })
+                })
+                .collect::<Vec<_>>();
+            if private_keys.is_empty() {
+                private_keys.extend(self.keys.key_by_script(self.secp, &script));
+            }
+            if private_keys.is_empty() {
+                self.report.skip(index, SkipReason::UnknownKey);
+                continue;
+            }
+
+            let message = secp256k1::Message::from_digest(signature_hash);
+            for private_key in private_keys {
+                let signature = self.ecdsa_signature(&message, &private_key.expose_secret().inner);
+                let public_key = private_key.public_key(self.secp);
+                self.secp
+                    .verify_ecdsa(&message, &signature, &public_key.inner)?;
+
+                input.partial_sigs.insert(
+                    public_key,
+                    bitcoin::ecdsa::Signature {
+                        sig: signature,
+                        hash_ty: sighash_type,
+                    },
+                );
+                signed += 1;
+            }
+            self.report.signed.push(index);
+        }
+
+        Ok(signed)
+    }
+
     fn sign_ecdsa(
         &mut self,
         inputs: &[TxInput],
-        script: &ScriptBuf,
+        scripts: &[&Script],
         transaction_type: TransactionType,
-    ) -> OrdResult<Transaction> {
+    ) -> Result<Transaction> {
+        self.report = SigningReport::default();
+        for spent in [inputs.len(), scripts.len()] {
+            if spent != self.transaction.input.len() {
+                return Err(SigningError::SpentOutputCount {
+                    inputs: self.transaction.input.len(),
+                    spent,
+                }
+                .into());
+            }
+        }
+
         let mut hash = SighashCache::new(self.transaction.clone());
-        for (index, input) in inputs.iter().enumerate() {
+        for (index, (input, script)) in inputs.iter().zip(scripts).enumerate() {
+            let Some(private_key) = self.keys.key_by_script(self.secp, script) else {
+                self.report.skip(index, SkipReason::UnknownKey);
+                continue;
+            };
+
+            let sighash_type = self.sighash_type(index).ecdsa();
             let signature_hash = match transaction_type {
-                TransactionType::Commit => hash.p2wpkh_signature_hash(
-                    index,
-                    script,
-                    input.amount,
-                    bitcoin::EcdsaSighashType::All,
-                )?,
-                TransactionType::Reveal => hash.p2wsh_signature_hash(
-                    index,
-                    script,
-                    input.amount,
-                    bitcoin::EcdsaSighashType::All,
-                )?,
+                TransactionType::Commit => {
+                    hash.p2wpkh_signature_hash(index, script, input.amount, sighash_type)?
+                }
+                TransactionType::Reveal => {
+                    hash.p2wsh_signature_hash(index, script, input.amount, sighash_type)?
+                }
             };
 
             let message = secp256k1::Message::from_digest(signature_hash.to_byte_array());
-            let signature = self.secp.sign_ecdsa(&message, &self.private_key.inner);
+            let signature = self.ecdsa_signature(&message, &private_key.expose_secret().inner);
             debug!("signature: {}", signature.serialize_der());
 
-            let pubkey = self.private_key.inner.public_key(self.secp);
+            let pubkey = private_key.expose_secret().inner.public_key(self.secp);
             // verify signature
             debug!("verifying signature");
             self.secp.verify_ecdsa(&message, &signature, &pubkey)?;
             debug!("signature verified");
             // append witness
-            let signature = bitcoin::ecdsa::Signature::sighash_all(signature).into();
+            let signature = bitcoin::ecdsa::Signature {
+                sig: signature,
+                hash_ty: sighash_type,
+            }
+            .into();
             match transaction_type {
                 TransactionType::Commit => {
                     self.append_witness_to_input(&mut hash, signature, index, &pubkey, None, None)?;
@@ -163,11 +1040,119 @@ impl<'a> Signer<'a> {
                     )?;
                 }
             }
+            self.report.signed.push(index);
         }
 
         Ok(hash.into_transaction())
     }
 
+    fn ecdsa_signature(
+        &self,
+        message: &secp256k1::Message,
+        secret_key: &secp256k1::SecretKey,
+    ) -> secp256k1::ecdsa::Signature {
+        if self.low_r {
+            self.secp.sign_ecdsa_low_r(message, secret_key)
+        } else {
+            self.secp.sign_ecdsa(message, secret_key)
+        }
+    }
+
+    fn schnorr_signature(
+        &self,
+        message: &secp256k1::Message,
+        keypair: &Keypair,
+    ) -> secp256k1::schnorr::Signature {
+        if self.aux_rand {
+            let mut aux_rand = [0; 32];
+            rand::thread_rng().fill_bytes(&mut aux_rand);
+            self.secp
+                .sign_schnorr_with_aux_rand(message, keypair, &aux_rand)
+        } else {
+            self.secp.sign_schnorr_no_aux_rand(message, keypair)
+        }
+    }
+
+    /// Script sig of the size the signer will produce for the input
+    fn estimated_script_sig(&self, spent: &SpentOutput) -> ScriptBuf {
+        let ecdsa_signature = if self.low_r {
+            LOW_R_ECDSA_SIGNATURE_SIZE
+        } else {
+            ECDSA_SIGNATURE_SIZE
+        };
+
+        match &spent.spend_script {
+            None if spent.prevout.script_pubkey.is_p2pkh() => {
+                let mut script_sig = vec![ecdsa_signature as u8];
+                script_sig.resize(1 + ecdsa_signature, 0);
+                script_sig.push(33);
+                script_sig.resize(script_sig.len() + 33, 0);
+                ScriptBuf::from(script_sig)
+            }
+            Some(SpendScript::RedeemScript(redeem_script)) => {
+                let mut script_sig = vec![redeem_script.len() as u8];
+                script_sig.extend_from_slice(redeem_script.as_bytes());
+                ScriptBuf::from(script_sig)
+            }
+            _ => ScriptBuf::new(),
+        }
+    }
+
+    /// Witness of the size the signer will produce for the input
+    fn estimated_witness(&self, index: usize, spent: &SpentOutput) -> Witness {
+        let ecdsa_signature = if self.low_r {
+            LOW_R_ECDSA_SIGNATURE_SIZE
+        } else {
+            ECDSA_SIGNATURE_SIZE
+        };
+        // the default sighash type is implied, others add a byte
+        let schnorr_signature = match self.sighash_type(index) {
+            SighashType::All => 64,
+            _ => 65,
+        };
+
+        let mut witness = Witness::new();
+        match &spent.spend_script {
+            None if spent.prevout.script_pubkey.is_p2pkh() => {}
+            None if spent.prevout.script_pubkey.is_p2wpkh() => {
+                witness.push(vec![0; ecdsa_signature]);
+                witness.push([0; 33]);
+            }
+            Some(SpendScript::RedeemScript(_)) => {
+                witness.push(vec![0; ecdsa_signature]);
+                witness.push([0; 33]);
+            }
+            Some(SpendScript::WitnessScript(witness_script)) => {
+                // a multisig script is finalized by the cosigners, after the CHECKMULTISIG dummy
+                match multisig_threshold(witness_script) {
+                    Some(threshold) => {
+                        witness.push([]);
+                        for _ in 0..threshold {
+                            witness.push(vec![0; ecdsa_signature]);
+                        }
+                    }
+                    None => witness.push(vec![0; ecdsa_signature]),
+                }
+                witness.push(witness_script.as_bytes());
+            }
+            Some(SpendScript::TapLeaf {
+                script,
+                control_block,
+            }) => {
+                witness.push(vec![0; schnorr_signature]);
+                witness.push(script.as_bytes());
+                witness.push(control_block.serialize());
+            }
+            None | Some(SpendScript::TapKey { .. }) => witness.push(vec![0; schnorr_signature]),
+        }
+
+        witness
+    }
+
+    fn sighash_type(&self, index: usize) -> SighashType {
+        self.sighash_types.get(&index).copied().unwrap_or_default()
+    }
+
     /// Build and append witness to the transaction input
     fn append_witness_to_input(
         &self,
@@ -175,9 +1160,9 @@ impl<'a> Signer<'a> {
         signature: Signature,
         index: usize,
         pubkey: &bitcoin::secp256k1::PublicKey,
-        redeem_script: Option<&ScriptBuf>,
+        redeem_script: Option<&Script>,
         control_block: Option<&ControlBlock>,
-    ) -> OrdResult<()> {
+    ) -> Result<()> {
         // push redeem script if necessary
         let witness = if let Some(redeem_script) = redeem_script {
             let mut witness = Witness::new();
@@ -191,10 +1176,10 @@ impl<'a> Signer<'a> {
             }
             witness
         } else {
-            // otherwise, push pubkey
+            // otherwise, push pubkey, or only the signature for a taproot key path spend
             match signature {
                 Signature::Ecdsa(signature) => Witness::p2wpkh(&signature, pubkey),
-                Signature::Schnorr(_) => return Err(OrdError::UnexpectedSignature),
+                Signature::Schnorr(signature) => Witness::from_slice(&[signature.to_vec()]),
             }
         };
         debug!("witness: {witness:?}");
@@ -202,8 +1187,571 @@ impl<'a> Signer<'a> {
         // append witness
         *sighasher
             .witness_mut(index)
-            .ok_or(OrdError::InputNotFound(index))? = witness;
+            .ok_or(SigningError::InputNotFound { index })? = witness;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr as _;
+
+    use bitcoin::absolute::LockTime;
+    use bitcoin::opcodes::all::{OP_CHECKMULTISIG, OP_CHECKSIG};
+    use bitcoin::script::Builder as ScriptBuilder;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Address, Amount, Network, OutPoint, Sequence, TxIn, TxOut, Txid};
+
+    use super::*;
+    use crate::account::{Account, AccountUtxo};
+    use crate::{taproot, RECIPIENT_ADDRESS_MNEMONIC, SENDER_ADDRESS_MNEMONIC};
+
+    fn transaction() -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_str(
+                        "14a7109b642b4fca7f10cd9bee89db73770c5a2d107f6a51c6bd7625dcdc2aed",
+                    )
+                    .unwrap(),
+                    vout: 0,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_consensus(0xffffffff),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_should_sign_commit_with_configured_sighash_type() {
+        let secp = Secp256k1::new();
+        let (keypair, _) = taproot::generate_keypair(&secp);
+        let private_key =
+            SecretPrivateKey::new(PrivateKey::new(keypair.secret_key(), Network::Testnet));
+        let address = Address::p2wpkh(&private_key.public_key(&secp), Network::Testnet).unwrap();
+        let input = TxInput {
+            id: Txid::all_zeros(),
+            index: 0,
+            amount: Amount::from_sat(8_000),
+        };
+
+        let tx = transaction();
+        let signed_tx = Signer::new(&private_key, &secp, tx.clone())
+            .with_sighash_type(0, SighashType::SinglePlusAnyoneCanPay)
+            .sign_commit_transaction(&[input], &address.script_pubkey())
+            .unwrap();
+
+        let signature =
+            bitcoin::ecdsa::Signature::from_slice(signed_tx.input[0].witness.nth(0).unwrap())
+                .unwrap();
+        assert_eq!(signature.hash_ty, EcdsaSighashType::SinglePlusAnyoneCanPay);
+
+        // the signature commits to the chosen sighash type
+        let sighash = SighashCache::new(&tx)
+            .p2wpkh_signature_hash(
+                0,
+                &address.script_pubkey(),
+                Amount::from_sat(8_000),
+                EcdsaSighashType::SinglePlusAnyoneCanPay,
+            )
+            .unwrap();
+        let msg = secp256k1::Message::from_digest(sighash.to_byte_array());
+        secp.verify_ecdsa(&msg, &signature.sig, &private_key.public_key(&secp).inner)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_should_sign_reveal_with_configured_sighash_type() {
+        let secp = Secp256k1::new();
+        let (keypair, x_public_key) = taproot::generate_keypair(&secp);
+        let redeem_script = ScriptBuilder::new()
+            .push_x_only_key(&x_public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let taproot_payload = taproot::TaprootPayload::build(
+            &secp,
+            keypair,
+            x_public_key,
+            &redeem_script,
+            5_000,
+            Network::Testnet,
+        )
+        .unwrap();
+
+        let private_key =
+            SecretPrivateKey::new(PrivateKey::new(keypair.secret_key(), Network::Testnet));
+        let default_tx = Signer::new(&private_key, &secp, transaction())
+            .sign_reveal_transaction_schnorr(&taproot_payload, &redeem_script)
+            .unwrap();
+        assert_eq!(default_tx.input[0].witness.nth(0).unwrap().len(), 64);
+
+        let signed_tx = Signer::new(&private_key, &secp, transaction())
+            .with_sighash_type(0, SighashType::NonePlusAnyoneCanPay)
+            .sign_reveal_transaction_schnorr(&taproot_payload, &redeem_script)
+            .unwrap();
+        let signature = signed_tx.input[0].witness.nth(0).unwrap();
+        assert_eq!(signature.len(), 65);
+        assert_eq!(signature[64], TapSighashType::NonePlusAnyoneCanPay as u8);
+    }
+
+    #[test]
+    fn test_should_sign_inputs_from_several_addresses_and_skip_unknown() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let recipient = Account::from_mnemonic(&secp, RECIPIENT_ADDRESS_MNEMONIC).unwrap();
+        let descriptor = sender.descriptor(&secp);
+
+        let mut tx = transaction();
+        tx.input = vec![tx.input[0].clone(); 3];
+        for (vout, input) in tx.input.iter_mut().enumerate() {
+            input.previous_output.vout = vout as u32;
+        }
+        let inputs = (0..3)
+            .map(|index| TxInput {
+                id: Txid::all_zeros(),
+                index,
+                amount: Amount::from_sat(8_000),
+            })
+            .collect::<Vec<_>>();
+        let txin_scripts = [
+            descriptor.script_pubkey(&secp, 0).unwrap(),
+            descriptor.script_pubkey(&secp, 5).unwrap(),
+            recipient.address.script_pubkey(),
+        ];
+
+        let mut signer = Signer::with_keys(&sender, &secp, tx);
+        // every input needs its script
+        assert!(signer
+            .sign_commit_transaction_with_scripts(&inputs, &txin_scripts[..2])
+            .is_err());
+        let signed_tx = signer
+            .sign_commit_transaction_with_scripts(&inputs, &txin_scripts)
+            .unwrap();
+        assert_eq!(signed_tx.input[0].witness.len(), 2);
+        assert_eq!(signed_tx.input[1].witness.len(), 2);
+        assert!(signed_tx.input[2].witness.is_empty());
+        assert_eq!(signer.report().signed, vec![0, 1]);
+        assert_eq!(
+            signer.report().skipped,
+            vec![SkippedInput {
+                index: 2,
+                reason: SkipReason::UnknownKey,
+            }]
+        );
+
+        // both accounts together sign everything
+        let accounts = [sender, recipient];
+        let keys = &accounts[..];
+        let mut signer = Signer::with_keys(&keys, &secp, signed_tx);
+        signer
+            .sign_commit_transaction_with_scripts(&inputs, &txin_scripts)
+            .unwrap();
+        assert!(signer.report().skipped.is_empty());
+    }
+
+    #[test]
+    fn test_should_sign_mixed_input_types_with_all_prevouts() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let x_only_key = XOnlyPublicKey::from(sender.public_key.inner);
+
+        let witness_script = ScriptBuilder::new()
+            .push_key(&sender.public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let leaf_script = ScriptBuilder::new()
+            .push_x_only_key(&x_only_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let (keypair, internal_key) = taproot::generate_keypair(&secp);
+        let taproot_payload = taproot::TaprootPayload::build(
+            &secp,
+            keypair,
+            internal_key,
+            &leaf_script,
+            5_000,
+            Network::Testnet,
+        )
+        .unwrap();
+
+        let prevout = |script_pubkey| TxOut {
+            value: Amount::from_sat(8_000),
+            script_pubkey,
+        };
+        let spent = [
+            SpentOutput::new(prevout(sender.address.script_pubkey())),
+            SpentOutput::new(prevout(witness_script.to_p2wsh()))
+                .with_witness_script(witness_script),
+            SpentOutput::new(taproot_payload.prevouts.clone())
+                .with_tap_leaf(leaf_script.clone(), taproot_payload.control_block.clone()),
+            SpentOutput::new(prevout(ScriptBuf::new_p2tr(&secp, x_only_key, None))),
+        ];
+
+        let mut tx = transaction();
+        tx.input = vec![tx.input[0].clone(); spent.len()];
+        for (vout, input) in tx.input.iter_mut().enumerate() {
+            input.previous_output.vout = vout as u32;
+        }
+
+        let mut signer = Signer::with_keys(&sender, &secp, tx.clone());
+        let signed_tx = signer.sign_transaction(&spent).unwrap();
+        assert_eq!(signer.report().signed, vec![0, 1, 2, 3]);
+        assert_eq!(signed_tx.input[0].witness.len(), 2);
+        assert_eq!(signed_tx.input[1].witness.len(), 2);
+        assert_eq!(signed_tx.input[2].witness.len(), 3);
+        assert_eq!(signed_tx.input[3].witness.len(), 1);
+
+        // the script path signature commits to every prevout
+        let prevouts = spent
+            .iter()
+            .map(|spent| spent.prevout.clone())
+            .collect::<Vec<_>>();
+        let sighash = SighashCache::new(&tx)
+            .taproot_script_spend_signature_hash(
+                2,
+                &Prevouts::All(&prevouts),
+                TapLeafHash::from_script(&leaf_script, LeafVersion::TapScript),
+                TapSighashType::Default,
+            )
+            .unwrap();
+        let msg = secp256k1::Message::from_digest(sighash.to_byte_array());
+        let signature =
+            secp256k1::schnorr::Signature::from_slice(signed_tx.input[2].witness.nth(0).unwrap())
+                .unwrap();
+        secp.verify_schnorr(&signature, &msg, &x_only_key).unwrap();
+    }
+
+    #[test]
+    fn test_should_sign_legacy_and_nested_segwit_inputs() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let wpkh = ScriptBuf::new_p2wpkh(&sender.public_key.wpubkey_hash().unwrap());
+
+        let prevout = |script_pubkey| TxOut {
+            value: Amount::from_sat(8_000),
+            script_pubkey,
+        };
+        let spent = [
+            SpentOutput::new(prevout(ScriptBuf::new_p2pkh(
+                &sender.public_key.pubkey_hash(),
+            ))),
+            SpentOutput::new(prevout(wpkh.to_p2sh())).with_redeem_script(wpkh.clone()),
+            SpentOutput::new(prevout(wpkh.clone())),
+        ];
+
+        let mut tx = transaction();
+        tx.input = vec![tx.input[0].clone(); spent.len()];
+        for (vout, input) in tx.input.iter_mut().enumerate() {
+            input.previous_output.vout = vout as u32;
+        }
+
+        let mut signer = Signer::with_keys(&sender, &secp, tx);
+        let estimated_vsize = signer.estimate_vsize(&spent);
+        let signed_tx = signer.sign_transaction(&spent).unwrap();
+        assert_eq!(signer.report().signed, vec![0, 1, 2]);
+
+        // P2PKH: signature and key in the script sig
+        assert!(signed_tx.input[0].witness.is_empty());
+        assert_eq!(signed_tx.input[0].script_sig.instructions().count(), 2);
+        // P2SH-P2WPKH: the script sig pushes the program, the witness is the P2WPKH one
+        assert_eq!(
+            signed_tx.input[1].script_sig.as_bytes()[1..],
+            *wpkh.as_bytes()
+        );
+        assert_eq!(signed_tx.input[1].witness.len(), 2);
+        assert!(signed_tx.input[2].script_sig.is_empty());
+
+        let prevouts = spent
+            .iter()
+            .map(|spent| spent.prevout.clone())
+            .collect::<Vec<_>>();
+        let report = crate::interpreter::verify_transaction(&secp, &signed_tx, &prevouts);
+        assert!(report.is_valid(), "{report}");
+        // DER signatures may be a byte shorter than the estimate
+        assert!(estimated_vsize >= signed_tx.vsize());
+        assert!(estimated_vsize - signed_tx.vsize() <= spent.len());
+    }
+
+    #[test]
+    fn test_should_sign_legacy_and_nested_segwit_psbt_inputs() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let wpkh = ScriptBuf::new_p2wpkh(&sender.public_key.wpubkey_hash().unwrap());
+        let prevout = |script_pubkey| TxOut {
+            value: Amount::from_sat(8_000),
+            script_pubkey,
+        };
+
+        // the P2PKH input only carries its previous transaction
+        let mut previous_tx = transaction();
+        previous_tx.output = vec![prevout(ScriptBuf::new_p2pkh(
+            &sender.public_key.pubkey_hash(),
+        ))];
+        let mut tx = transaction();
+        tx.input = vec![tx.input[0].clone(); 3];
+        for (vout, input) in tx.input.iter_mut().enumerate() {
+            input.previous_output.vout = vout as u32;
+        }
+        tx.input[0].previous_output = OutPoint::new(previous_tx.txid(), 0);
+        tx.output[0].value = Amount::from_sat(22_000);
+
+        let mut psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(previous_tx);
+        psbt.inputs[1].witness_utxo = Some(prevout(wpkh.to_p2sh()));
+        psbt.inputs[1].redeem_script = Some(wpkh.clone());
+        psbt.inputs[2].witness_utxo = Some(prevout(wpkh.clone()));
+
+        let mut signer = Signer::with_keys(&sender, &secp, psbt.unsigned_tx.clone());
+        assert_eq!(signer.sign_psbt_ecdsa(&mut psbt).unwrap(), 3);
+        assert_eq!(signer.report().signed, vec![0, 1, 2]);
+
+        let prevouts = crate::interpreter::psbt_prevouts(&psbt).unwrap();
+        let signed_tx =
+            crate::psbt::finalize_commit(psbt, &crate::fee::FeePolicy::default()).unwrap();
+        assert!(signed_tx.input[0].witness.is_empty());
+        assert_eq!(
+            signed_tx.input[1].script_sig.as_bytes()[1..],
+            *wpkh.as_bytes()
+        );
+        assert!(signed_tx.input[2].script_sig.is_empty());
+        let report = crate::interpreter::verify_transaction(&secp, &signed_tx, &prevouts);
+        assert!(report.is_valid(), "{report}");
+
+        // a redeem script that does not match the spent output is not signed
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        for input in psbt.inputs.iter_mut() {
+            input.witness_utxo = Some(prevout(wpkh.to_p2sh()));
+            input.redeem_script = Some(ScriptBuf::new_p2pkh(&sender.public_key.pubkey_hash()));
+        }
+        assert_eq!(signer.sign_psbt_ecdsa(&mut psbt).unwrap(), 0);
+        assert!(signer
+            .report()
+            .skipped
+            .iter()
+            .all(|skipped| skipped.reason == SkipReason::UnsupportedScript));
+    }
+
+    #[test]
+    fn test_should_only_sign_single_key_witness_scripts_of_the_spent_output() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let recipient = Account::from_mnemonic(&secp, RECIPIENT_ADDRESS_MNEMONIC).unwrap();
+        let prevout = |script_pubkey| TxOut {
+            value: Amount::from_sat(8_000),
+            script_pubkey,
+        };
+
+        // a 2-of-2 needs the cosigner, a single signature witness would be invalid
+        let multisig = ScriptBuilder::new()
+            .push_int(2)
+            .push_key(&sender.public_key)
+            .push_key(&recipient.public_key)
+            .push_int(2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let spent = [SpentOutput::new(prevout(multisig.to_p2wsh())).with_witness_script(multisig)];
+        let mut signer = Signer::with_keys(&sender, &secp, transaction());
+        signer.sign_transaction(&spent).unwrap();
+        assert_eq!(
+            signer.report().skipped,
+            vec![SkippedInput {
+                index: 0,
+                reason: SkipReason::MultipleSignatures,
+            }]
+        );
+        // the estimate still accounts for both signatures
+        let estimated = signer.estimated_witness(0, &spent[0]);
+        assert_eq!(estimated.len(), 4);
+
+        // the witness script of a PSBT input must be the one of the spent output
+        let witness_script = ScriptBuilder::new()
+            .push_key(&sender.public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let other_script = ScriptBuilder::new()
+            .push_key(&recipient.public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let mut tx = transaction();
+        tx.input = vec![tx.input[0].clone(); 3];
+        for (vout, input) in tx.input.iter_mut().enumerate() {
+            input.previous_output.vout = vout as u32;
+        }
+        let mut psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
+        psbt.inputs[0].witness_utxo = Some(prevout(witness_script.to_p2wsh()));
+        psbt.inputs[1].witness_utxo = Some(prevout(witness_script.to_p2wsh().to_p2sh()));
+        psbt.inputs[1].redeem_script = Some(witness_script.to_p2wsh());
+        psbt.inputs[2].witness_utxo = Some(prevout(other_script.to_p2wsh()));
+        for input in psbt.inputs.iter_mut() {
+            input.witness_script = Some(witness_script.clone());
+        }
+
+        let mut signer = Signer::with_keys(&sender, &secp, tx);
+        assert_eq!(signer.sign_psbt_ecdsa(&mut psbt).unwrap(), 2);
+        assert_eq!(signer.report().signed, vec![0, 1]);
+        assert_eq!(
+            signer.report().skipped,
+            vec![SkippedInput {
+                index: 2,
+                reason: SkipReason::UnsupportedScript,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_should_spend_taproot_key_path() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let private_key = sender
+            .private_key
+            .derive_private_key(
+                &secp,
+                &sender
+                    .descriptor(&secp)
+                    .derive(&secp, 0)
+                    .unwrap()
+                    .key_source
+                    .1,
+            )
+            .unwrap();
+        let keypair = Keypair::from_secret_key(&secp, &private_key.expose_secret().inner);
+        let x_only_key = keypair.x_only_public_key().0;
+
+        // recover a commit output through its internal key
+        let redeem_script = ScriptBuilder::new()
+            .push_x_only_key(&x_only_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let taproot_payload = taproot::TaprootPayload::build(
+            &secp,
+            keypair,
+            x_only_key,
+            &redeem_script,
+            5_000,
+            Network::Testnet,
+        )
+        .unwrap();
+        let spent = [SpentOutput::new(taproot_payload.prevouts.clone())
+            .with_internal_key(x_only_key, taproot_payload.taproot_spend_info.merkle_root())];
+
+        let tx = transaction();
+        let signed_tx = Signer::with_keys(&sender, &secp, tx.clone())
+            .sign_transaction(&spent)
+            .unwrap();
+        assert_eq!(signed_tx.input[0].witness.len(), 1);
+
+        let sighash = SighashCache::new(&tx)
+            .taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::All(std::slice::from_ref(&taproot_payload.prevouts)),
+                TapSighashType::Default,
+            )
+            .unwrap();
+        let msg = secp256k1::Message::from_digest(sighash.to_byte_array());
+        let signature =
+            secp256k1::schnorr::Signature::from_slice(signed_tx.input[0].witness.nth(0).unwrap())
+                .unwrap();
+        let output_key =
+            XOnlyPublicKey::from_slice(&taproot_payload.prevouts.script_pubkey.as_bytes()[2..])
+                .unwrap();
+        secp.verify_schnorr(&signature, &msg, &output_key).unwrap();
+
+        // fund a commit from a BIP86 wallet
+        let tr = crate::descriptor::Descriptor {
+            kind: crate::descriptor::DescriptorKind::Tr,
+            ..sender.descriptor(&secp)
+        };
+        let watch_only = crate::account::WatchOnlyAccount::new(&secp, tr).unwrap();
+        let inputs = [AccountUtxo {
+            input: TxInput {
+                id: Txid::all_zeros(),
+                index: 0,
+                amount: Amount::from_sat(8_000),
+            },
+            address_index: 0,
+        }];
+        let mut psbt = watch_only
+            .commit_psbt(&secp, &inputs, &taproot_payload, Amount::from_sat(1_000))
+            .unwrap();
+        let mut signer = Signer::with_keys(&sender, &secp, psbt.unsigned_tx.clone());
+        assert_eq!(signer.sign_psbt_ecdsa(&mut psbt).unwrap(), 0);
+        assert_eq!(
+            signer.report().skipped,
+            vec![SkippedInput {
+                index: 0,
+                reason: SkipReason::Taproot,
+            }]
+        );
+        let signed = Signer::with_keys(&sender, &secp, psbt.unsigned_tx.clone())
+            .sign_psbt_tap_key(&mut psbt)
+            .unwrap();
+        assert_eq!(signed, 1);
+        assert!(crate::verify::verify_signatures(&secp, &psbt)
+            .unwrap()
+            .is_complete());
+
+        let tx = crate::psbt::finalize_commit(psbt, &crate::fee::FeePolicy::default()).unwrap();
+        assert_eq!(tx.input[0].witness.len(), 1);
+    }
+
+    #[test]
+    fn test_should_grind_low_r_and_estimate_witness_sizes() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let x_only_key = XOnlyPublicKey::from(sender.public_key.inner);
+        let spent = [
+            SpentOutput::new(TxOut {
+                value: Amount::from_sat(8_000),
+                script_pubkey: sender.address.script_pubkey(),
+            }),
+            SpentOutput::new(TxOut {
+                value: Amount::from_sat(8_000),
+                script_pubkey: ScriptBuf::new_p2tr(&secp, x_only_key, None),
+            }),
+        ];
+        let mut tx = transaction();
+        tx.input = vec![tx.input[0].clone(); spent.len()];
+        tx.input[1].previous_output.vout = 1;
+
+        let mut signer = Signer::with_keys(&sender, &secp, tx.clone())
+            .with_low_r()
+            .with_aux_rand();
+        let estimated_vsize = signer.estimate_vsize(&spent);
+        let signed_tx = signer.sign_transaction(&spent).unwrap();
+
+        // DER encoding with a 32 bytes R
+        let ecdsa_signature = signed_tx.input[0].witness.nth(0).unwrap();
+        assert!(ecdsa_signature.len() <= LOW_R_ECDSA_SIGNATURE_SIZE);
+        assert_eq!(ecdsa_signature[3], 32);
+        assert!(signed_tx.vsize() <= estimated_vsize);
+        assert!(estimated_vsize - signed_tx.vsize() <= 1);
+        assert!(
+            Signer::with_keys(&sender, &secp, tx.clone()).estimate_vsize(&spent) >= estimated_vsize
+        );
+        assert_eq!(
+            signer.estimate_fee(&spent, FeeRate::from_sat_per_vb(2).unwrap()),
+            Amount::from_sat(2 * estimated_vsize as u64)
+        );
+
+        // aux randomness makes every schnorr signature different
+        let again = signer.sign_transaction(&spent).unwrap();
+        assert_ne!(signed_tx.input[1].witness, again.input[1].witness);
+        let deterministic = |tx: Transaction| {
+            Signer::with_keys(&sender, &secp, tx)
+                .sign_transaction(&spent)
+                .unwrap()
+        };
+        assert_eq!(
+            deterministic(tx.clone()).input[1].witness,
+            deterministic(tx).input[1].witness
+        );
+    }
+}
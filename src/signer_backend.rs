@@ -0,0 +1,198 @@
+use std::future::Future;
+
+use bitcoin::bip32::ChildNumber;
+use bitcoin::hashes::{sha256, Hash as _};
+use bitcoin::key::{Keypair, TapTweak as _};
+use bitcoin::secp256k1::{ecdsa, schnorr, All, Message, PublicKey, Secp256k1};
+use bitcoin::TapNodeHash;
+
+use crate::error::Result;
+use crate::secret::{SecretPrivateKey, SecretXpriv};
+
+/// BIP341 tweak of the key signing a taproot key path spend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaprootTweak {
+    /// Script tree root, `None` for BIP86 outputs
+    pub merkle_root: Option<TapNodeHash>,
+}
+
+/// Service producing the signatures of a `Signer` without handing out the keys.
+///
+/// The target is the IC management canister threshold keys (`ecdsa_public_key`, `sign_with_ecdsa`,
+/// `schnorr_public_key` and `sign_with_schnorr`), which address keys by a derivation path of
+/// arbitrary byte strings.
+pub trait SignerBackend {
+    fn ecdsa_public_key(
+        &self,
+        derivation_path: &[Vec<u8>],
+    ) -> impl Future<Output = Result<PublicKey>>;
+
+    /// ECDSA signature of the sighash `message`
+    fn sign_with_ecdsa(
+        &self,
+        derivation_path: &[Vec<u8>],
+        message: &Message,
+    ) -> impl Future<Output = Result<ecdsa::Signature>>;
+
+    fn schnorr_public_key(
+        &self,
+        derivation_path: &[Vec<u8>],
+    ) -> impl Future<Output = Result<PublicKey>>;
+
+    /// BIP340 signature of the sighash `message`, by the key tweaked with `tweak` if any
+    fn sign_with_schnorr(
+        &self,
+        derivation_path: &[Vec<u8>],
+        message: &Message,
+        tweak: Option<TaprootTweak>,
+    ) -> impl Future<Output = Result<schnorr::Signature>>;
+}
+
+/// Backend signing in process with keys derived from a master key, one per derivation path.
+///
+/// Stands in for the canister in tests and local runs.
+pub struct LocalBackend {
+    master_key: SecretXpriv,
+    secp: Secp256k1<All>,
+}
+
+impl LocalBackend {
+    pub fn new(master_key: SecretXpriv) -> Self {
+        Self {
+            master_key,
+            secp: Secp256k1::new(),
+        }
+    }
+
+    /// Key at `derivation_path`, each path component being hashed into a non hardened BIP32 step
+    pub fn private_key(&self, derivation_path: &[Vec<u8>]) -> Result<SecretPrivateKey> {
+        let path = derivation_path
+            .iter()
+            .map(|component| {
+                let hash = sha256::Hash::hash(component).to_byte_array();
+                let index = u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) & 0x7fff_ffff;
+                ChildNumber::from_normal_idx(index)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(self.master_key.derive_private_key(&self.secp, &path)?)
+    }
+}
+
+impl SignerBackend for LocalBackend {
+    async fn ecdsa_public_key(&self, derivation_path: &[Vec<u8>]) -> Result<PublicKey> {
+        Ok(self
+            .private_key(derivation_path)?
+            .public_key(&self.secp)
+            .inner)
+    }
+
+    async fn sign_with_ecdsa(
+        &self,
+        derivation_path: &[Vec<u8>],
+        message: &Message,
+    ) -> Result<ecdsa::Signature> {
+        let private_key = self.private_key(derivation_path)?;
+        Ok(self
+            .secp
+            .sign_ecdsa(message, &private_key.expose_secret().inner))
+    }
+
+    async fn schnorr_public_key(&self, derivation_path: &[Vec<u8>]) -> Result<PublicKey> {
+        self.ecdsa_public_key(derivation_path).await
+    }
+
+    async fn sign_with_schnorr(
+        &self,
+        derivation_path: &[Vec<u8>],
+        message: &Message,
+        tweak: Option<TaprootTweak>,
+    ) -> Result<schnorr::Signature> {
+        let private_key = self.private_key(derivation_path)?;
+        let mut keypair = Keypair::from_secret_key(&self.secp, &private_key.expose_secret().inner);
+        if let Some(tweak) = tweak {
+            keypair = keypair.tap_tweak(&self.secp, tweak.merkle_root).to_inner();
+        }
+
+        Ok(self.secp.sign_schnorr_no_aux_rand(message, &keypair))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr as _;
+
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version;
+    use bitcoin::{
+        Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+        XOnlyPublicKey,
+    };
+
+    use super::*;
+    use crate::account::Account;
+    use crate::signer::{Signer, SkipReason, SpentOutput};
+    use crate::SENDER_ADDRESS_MNEMONIC;
+
+    #[tokio::test]
+    async fn test_should_sign_with_backend_keys() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let backend = LocalBackend::new(sender.private_key);
+
+        let wpkh_path = vec![b"wallet".to_vec(), b"1".to_vec()];
+        let tr_path = vec![b"wallet".to_vec(), b"2".to_vec()];
+        let wpkh_key = bitcoin::PublicKey::new(backend.ecdsa_public_key(&wpkh_path).await.unwrap());
+        let tr_key = XOnlyPublicKey::from(backend.schnorr_public_key(&tr_path).await.unwrap());
+
+        let prevout = |script_pubkey| TxOut {
+            value: Amount::from_sat(8_000),
+            script_pubkey,
+        };
+        let spent = [
+            SpentOutput::new(prevout(ScriptBuf::new_p2wpkh(
+                &wpkh_key.wpubkey_hash().unwrap(),
+            )))
+            .with_derivation_path(wpkh_path),
+            SpentOutput::new(prevout(ScriptBuf::new_p2tr(&secp, tr_key, None)))
+                .with_derivation_path(tr_path),
+            // the backend key at this path does not control the output
+            SpentOutput::new(prevout(sender.address.script_pubkey()))
+                .with_derivation_path(vec![b"wallet".to_vec(), b"3".to_vec()]),
+        ];
+        let unsigned_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: (0..spent.len() as u32)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint {
+                        txid: Txid::from_str(
+                            "14a7109b642b4fca7f10cd9bee89db73770c5a2d107f6a51c6bd7625dcdc2aed",
+                        )
+                        .unwrap(),
+                        vout,
+                    },
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::from_consensus(0xffffffff),
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: Amount::from_sat(20_000),
+                script_pubkey: sender.address.script_pubkey(),
+            }],
+        };
+
+        let mut signer = Signer::without_keys(&secp, unsigned_tx);
+        let signed_tx = signer
+            .sign_transaction_with_backend(&backend, &spent)
+            .await
+            .unwrap();
+        assert_eq!(signer.report().signed, vec![0, 1]);
+        assert_eq!(signer.report().skipped[0].index, 2);
+        assert_eq!(signer.report().skipped[0].reason, SkipReason::UnknownKey);
+        assert_eq!(signed_tx.input[0].witness.len(), 2);
+        assert_eq!(signed_tx.input[1].witness.len(), 1);
+        assert!(signed_tx.input[2].witness.is_empty());
+    }
+}
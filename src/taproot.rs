@@ -2,7 +2,8 @@ use bitcoin::key::UntweakedKeypair;
 use bitcoin::secp256k1::{All, Secp256k1};
 use bitcoin::taproot::{ControlBlock, LeafVersion, TaprootBuilder, TaprootSpendInfo};
 use bitcoin::{Address, Amount, Network, ScriptBuf, TxOut, XOnlyPublicKey};
-use ord_rs::{OrdError, OrdResult};
+
+use crate::error::{InscriptionError, Result};
 
 #[derive(Debug)]
 pub struct TaprootPayload {
@@ -22,20 +23,21 @@ impl TaprootPayload {
         redeem_script: &ScriptBuf,
         reveal_balance: u64,
         network: Network,
-    ) -> OrdResult<Self> {
+    ) -> Result<Self> {
         let taproot_spend_info = TaprootBuilder::new()
             .add_leaf(0, redeem_script.clone())
             .expect("adding leaf should work")
             .finalize(secp, x_public_key)
-            .ok()
-            .ok_or(OrdError::TaprootCompute)?;
+            .map_err(|_| InscriptionError::TaprootCompute("incomplete script tree"))?;
 
         let address = Address::p2tr_tweaked(taproot_spend_info.output_key(), network);
 
         Ok(Self {
             control_block: taproot_spend_info
                 .control_block(&(redeem_script.clone(), LeafVersion::TapScript))
-                .ok_or(OrdError::TaprootCompute)?,
+                .ok_or(InscriptionError::TaprootCompute(
+                    "redeem script is not in the script tree",
+                ))?,
             keypair,
             taproot_spend_info,
             prevouts: TxOut {
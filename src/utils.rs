@@ -1,14 +1,44 @@
-use bitcoin::script::PushBytesBuf;
+use bitcoin::constants::MAX_SCRIPT_ELEMENT_SIZE;
+use bitcoin::opcodes::all::{OP_CHECKSIG, OP_ENDIF, OP_IF};
+use bitcoin::opcodes::{OP_0, OP_FALSE};
+use bitcoin::script::{Builder as ScriptBuilder, PushBytesBuf};
+use bitcoin::ScriptBuf;
+use ord_rs::Inscription;
 
-use ord_rs::OrdResult;
+use crate::error::Result;
 
-pub fn bytes_to_push_bytes(bytes: &[u8]) -> OrdResult<PushBytesBuf> {
+pub fn bytes_to_push_bytes(bytes: &[u8]) -> Result<PushBytesBuf> {
     let mut push_bytes = PushBytesBuf::with_capacity(bytes.len());
     push_bytes.extend_from_slice(bytes)?;
 
     Ok(push_bytes)
 }
 
+/// Build the inscription envelope script locked to `public_key`,
+/// an x-only key for a tapscript or a compressed key for a P2WSH witness script.
+///
+/// The content is split in pushes of at most `MAX_SCRIPT_ELEMENT_SIZE` bytes, as ord does.
+pub fn inscription_script(public_key: &[u8], inscription: &impl Inscription) -> Result<ScriptBuf> {
+    let mut builder = ScriptBuilder::new()
+        .push_slice(bytes_to_push_bytes(public_key)?.as_push_bytes())
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_FALSE)
+        .push_opcode(OP_IF)
+        .push_slice(b"ord")
+        .push_slice(b"\x01")
+        .push_slice(bytes_to_push_bytes(inscription.content_type().as_bytes())?.as_push_bytes())
+        .push_opcode(OP_0);
+    for chunk in inscription
+        .data()?
+        .as_bytes()
+        .chunks(MAX_SCRIPT_ELEMENT_SIZE)
+    {
+        builder = builder.push_slice(bytes_to_push_bytes(chunk)?.as_push_bytes());
+    }
+
+    Ok(builder.push_opcode(OP_ENDIF).into_script())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,596 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bitcoin::hashes::Hash as _;
+use bitcoin::key::XOnlyPublicKey;
+use bitcoin::psbt::Input;
+use bitcoin::script::Instruction;
+use bitcoin::secp256k1::{Message, Secp256k1, Verification};
+use bitcoin::sighash::{Prevouts, SighashCache};
+use bitcoin::{Psbt, PublicKey, Script, ScriptBuf, TapLeafHash, TapSighashType, TxOut};
+use serde::Serialize;
+
+use crate::error::{PsbtError, Result, SigningError};
+use crate::inspect::{checksigadd_threshold, multisig_threshold, spent_utxo};
+
+/// Result of checking every signature of a PSBT against the recomputed sighashes
+#[derive(Debug, Serialize)]
+pub struct VerificationReport {
+    pub inputs: Vec<InputVerification>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InputVerification {
+    pub index: usize,
+    pub state: InputState,
+    /// Number of valid signatures
+    pub valid: usize,
+    /// Number of signatures needed to finalize the input
+    pub required: usize,
+    pub invalid: Vec<InvalidSignature>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputState {
+    /// Already finalized, nothing left to verify
+    Finalized,
+    /// Enough valid signatures to finalize
+    Complete,
+    MissingSignatures,
+    InvalidSignatures,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InvalidSignature {
+    /// Public key (or x-only key) the signature claims to be from
+    pub public_key: String,
+    pub reason: String,
+}
+
+impl VerificationReport {
+    /// Whether every input is finalized or has enough valid signatures
+    pub fn is_complete(&self) -> bool {
+        self.inputs
+            .iter()
+            .all(|input| matches!(input.state, InputState::Finalized | InputState::Complete))
+    }
+
+    /// Fail with the report if some input cannot be finalized
+    pub fn ensure_complete(self) -> Result<Self> {
+        if self.is_complete() {
+            Ok(self)
+        } else {
+            Err(PsbtError::Incomplete(self).into())
+        }
+    }
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (position, input) in self.inputs.iter().enumerate() {
+            if position > 0 {
+                writeln!(f)?;
+            }
+            let state = match input.state {
+                InputState::Finalized => "finalized",
+                InputState::Complete => "complete",
+                InputState::MissingSignatures => "missing signatures",
+                InputState::InvalidSignatures => "invalid signatures",
+            };
+            write!(
+                f,
+                "input {}: {state} ({}/{} valid)",
+                input.index, input.valid, input.required
+            )?;
+            for invalid in &input.invalid {
+                write!(f, "\n  {}: {}", invalid.public_key, invalid.reason)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Verify every partial signature of the PSBT against the sighash recomputed from its prevouts
+pub fn verify_signatures<C: Verification>(
+    secp: &Secp256k1<C>,
+    psbt: &Psbt,
+) -> Result<VerificationReport> {
+    let prevouts = (0..psbt.inputs.len())
+        .map(|index| spent_utxo(psbt, index).cloned())
+        .collect::<Option<Vec<_>>>();
+    let mut sighash_cache = SighashCache::new(&psbt.unsigned_tx);
+
+    let mut inputs = Vec::with_capacity(psbt.inputs.len());
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        if input.final_script_witness.is_some() || input.final_script_sig.is_some() {
+            inputs.push(InputVerification {
+                index,
+                state: InputState::Finalized,
+                valid: 0,
+                required: 0,
+                invalid: Vec::new(),
+            });
+            continue;
+        }
+
+        let prevout = spent_utxo(psbt, index).ok_or(PsbtError::MissingUtxo { index })?;
+        let mut valid = 0;
+        let mut invalid = Vec::new();
+        let mut key_path_valid = false;
+        let mut leaf_valid = BTreeMap::<TapLeafHash, usize>::new();
+
+        for (public_key, signature) in &input.partial_sigs {
+            match verify_ecdsa(
+                secp,
+                &mut sighash_cache,
+                index,
+                input,
+                prevout,
+                public_key,
+                signature,
+            ) {
+                Ok(()) => valid += 1,
+                Err(err) => invalid.push(InvalidSignature {
+                    public_key: public_key.to_string(),
+                    reason: err.to_string(),
+                }),
+            }
+        }
+
+        if let Some(signature) = input.tap_key_sig {
+            let result = taproot_output_key(index, prevout).and_then(|output_key| {
+                check_taproot_sighash_type(index, input, signature.hash_ty)?;
+                let prevouts = taproot_prevouts(&prevouts, index, prevout, signature.hash_ty)?;
+                let sighash = sighash_cache.taproot_key_spend_signature_hash(
+                    index,
+                    &prevouts,
+                    signature.hash_ty,
+                )?;
+                let msg = Message::from_digest(sighash.to_byte_array());
+                secp.verify_schnorr(&signature.sig, &msg, &output_key)?;
+                Ok(())
+            });
+            match result {
+                Ok(()) => key_path_valid = true,
+                Err(err) => invalid.push(InvalidSignature {
+                    public_key: "key path".to_string(),
+                    reason: err.to_string(),
+                }),
+            }
+        }
+
+        for ((x_only_key, leaf_hash), signature) in &input.tap_script_sigs {
+            let result = check_leaf_key(index, input, x_only_key, leaf_hash)
+                .and_then(|()| check_taproot_sighash_type(index, input, signature.hash_ty))
+                .and_then(|()| taproot_prevouts(&prevouts, index, prevout, signature.hash_ty))
+                .and_then(|prevouts| {
+                    let sighash = sighash_cache.taproot_script_spend_signature_hash(
+                        index,
+                        &prevouts,
+                        *leaf_hash,
+                        signature.hash_ty,
+                    )?;
+                    let msg = Message::from_digest(sighash.to_byte_array());
+                    secp.verify_schnorr(&signature.sig, &msg, x_only_key)?;
+                    Ok(())
+                });
+            match result {
+                Ok(()) => *leaf_valid.entry(*leaf_hash).or_default() += 1,
+                Err(err) => invalid.push(InvalidSignature {
+                    public_key: x_only_key.to_string(),
+                    reason: err.to_string(),
+                }),
+            }
+        }
+
+        let (valid, required) = if !prevout.script_pubkey.is_p2tr() {
+            (valid, required_signatures(input))
+        } else if key_path_valid {
+            (1, 1)
+        } else {
+            closest_leaf(input, &leaf_valid)
+        };
+        let state = if !invalid.is_empty() {
+            InputState::InvalidSignatures
+        } else if valid < required {
+            InputState::MissingSignatures
+        } else {
+            InputState::Complete
+        };
+
+        inputs.push(InputVerification {
+            index,
+            state,
+            valid,
+            required,
+            invalid,
+        });
+    }
+
+    Ok(VerificationReport { inputs })
+}
+
+fn verify_ecdsa<C: Verification>(
+    secp: &Secp256k1<C>,
+    sighash_cache: &mut SighashCache<&bitcoin::Transaction>,
+    index: usize,
+    input: &Input,
+    prevout: &TxOut,
+    public_key: &PublicKey,
+    signature: &bitcoin::ecdsa::Signature,
+) -> Result<()> {
+    // a valid signature over the right sighash is still useless from a key the script ignores
+    let script = input
+        .witness_script
+        .as_ref()
+        .or(input.redeem_script.as_ref())
+        .unwrap_or(&prevout.script_pubkey);
+    if !commits_to_key(script, public_key) {
+        return Err(SigningError::ForeignKey { index }.into());
+    }
+
+    let hash_ty = signature.hash_ty;
+    if let Some(expected) = input.sighash_type {
+        if expected.ecdsa_hash_ty().ok() != Some(hash_ty) {
+            return Err(SigningError::SighashMismatch { index, expected }.into());
+        }
+    }
+    let msg = if let Some(witness_script) = &input.witness_script {
+        let sighash =
+            sighash_cache.p2wsh_signature_hash(index, witness_script, prevout.value, hash_ty)?;
+        Message::from_digest(sighash.to_byte_array())
+    } else if prevout.script_pubkey.is_p2wpkh() {
+        let sighash = sighash_cache.p2wpkh_signature_hash(
+            index,
+            &prevout.script_pubkey,
+            prevout.value,
+            hash_ty,
+        )?;
+        Message::from_digest(sighash.to_byte_array())
+    } else if let Some(redeem_script) = input.redeem_script.as_ref().filter(|s| s.is_p2wpkh()) {
+        // P2SH-P2WPKH
+        let sighash =
+            sighash_cache.p2wpkh_signature_hash(index, redeem_script, prevout.value, hash_ty)?;
+        Message::from_digest(sighash.to_byte_array())
+    } else {
+        let script_code = input
+            .redeem_script
+            .as_ref()
+            .unwrap_or(&prevout.script_pubkey);
+        let sighash = sighash_cache.legacy_signature_hash(index, script_code, hash_ty.to_u32())?;
+        Message::from_digest(sighash.to_byte_array())
+    };
+
+    secp.verify_ecdsa(&msg, &signature.sig, &public_key.inner)?;
+    Ok(())
+}
+
+/// Output key of the taproot output spent by input `index`
+fn taproot_output_key(index: usize, prevout: &TxOut) -> Result<XOnlyPublicKey> {
+    if !prevout.script_pubkey.is_p2tr() {
+        return Err(SigningError::NotTaproot { index }.into());
+    }
+
+    Ok(XOnlyPublicKey::from_slice(
+        &prevout.script_pubkey.as_bytes()[2..],
+    )?)
+}
+
+/// Whether `script` pays to `public_key`, by its hash or by pushing it
+fn commits_to_key(script: &Script, public_key: &PublicKey) -> bool {
+    if script.is_p2wpkh() {
+        public_key
+            .wpubkey_hash()
+            .is_some_and(|hash| ScriptBuf::new_p2wpkh(&hash) == *script)
+    } else if script.is_p2pkh() {
+        ScriptBuf::new_p2pkh(&public_key.pubkey_hash()) == *script
+    } else {
+        let key = public_key.to_bytes();
+        script.instructions().any(|instruction| {
+            matches!(instruction, Ok(Instruction::PushBytes(bytes)) if bytes.as_bytes() == key)
+        })
+    }
+}
+
+/// Prevouts committed to by a taproot signature: all of them, or only the signed one with ANYONECANPAY
+fn taproot_prevouts<'a>(
+    prevouts: &'a Option<Vec<TxOut>>,
+    index: usize,
+    prevout: &'a TxOut,
+    hash_ty: TapSighashType,
+) -> Result<Prevouts<'a, TxOut>> {
+    match hash_ty {
+        TapSighashType::AllPlusAnyoneCanPay
+        | TapSighashType::NonePlusAnyoneCanPay
+        | TapSighashType::SinglePlusAnyoneCanPay => Ok(Prevouts::One(index, prevout.clone())),
+        _ => prevouts
+            .as_deref()
+            .map(Prevouts::All)
+            .ok_or_else(|| SigningError::TaprootPrevouts { index }.into()),
+    }
+}
+
+/// The signature of `x_only_key` must be for a leaf of the input which pushes that key
+fn check_leaf_key(
+    index: usize,
+    input: &Input,
+    x_only_key: &XOnlyPublicKey,
+    leaf_hash: &TapLeafHash,
+) -> Result<()> {
+    let (script, _) = input
+        .tap_scripts
+        .values()
+        .find(|(script, version)| TapLeafHash::from_script(script, *version) == *leaf_hash)
+        .ok_or(SigningError::UnknownLeaf { index })?;
+    let key = x_only_key.serialize();
+    let pushes_key = script.instructions().any(|instruction| {
+        matches!(instruction, Ok(Instruction::PushBytes(bytes)) if bytes.as_bytes() == key)
+    });
+    if !pushes_key {
+        return Err(SigningError::ForeignKey { index }.into());
+    }
+
+    Ok(())
+}
+
+/// A taproot signature must use the sighash type the input asks for, if any
+fn check_taproot_sighash_type(index: usize, input: &Input, hash_ty: TapSighashType) -> Result<()> {
+    match input.sighash_type {
+        Some(expected) if expected.taproot_hash_ty().ok() != Some(hash_ty) => {
+            Err(SigningError::SighashMismatch { index, expected }.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn required_signatures(input: &Input) -> usize {
+    input
+        .witness_script
+        .as_ref()
+        .and_then(|witness_script| multisig_threshold(witness_script))
+        .unwrap_or(1)
+}
+
+/// Valid and required signatures of the script path leaf closest to its threshold
+fn closest_leaf(input: &Input, leaf_valid: &BTreeMap<TapLeafHash, usize>) -> (usize, usize) {
+    input
+        .tap_scripts
+        .values()
+        .map(|(script, version)| {
+            let leaf_hash = TapLeafHash::from_script(script, *version);
+            let valid = leaf_valid.get(&leaf_hash).copied().unwrap_or_default();
+            (valid, checksigadd_threshold(script).unwrap_or(1))
+        })
+        .min_by_key(|(valid, required)| required.saturating_sub(*valid))
+        .unwrap_or((0, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr as _;
+
+    use bitcoin::{ecdsa, taproot, Amount, EcdsaSighashType, Network, PrivateKey, Txid};
+    use ord_rs::transaction::TxInput;
+
+    use super::*;
+    use crate::account::{Account, AccountUtxo};
+    use crate::taproot::{generate_keypair, TaprootPayload};
+    use crate::{RECIPIENT_ADDRESS_MNEMONIC, SENDER_ADDRESS_MNEMONIC};
+
+    #[test]
+    fn test_should_report_missing_and_invalid_signatures() {
+        let secp = Secp256k1::new();
+        let sender = Account::from_mnemonic(&secp, SENDER_ADDRESS_MNEMONIC).unwrap();
+        let recipient = Account::from_mnemonic(&secp, RECIPIENT_ADDRESS_MNEMONIC).unwrap();
+
+        let (keypair, x_public_key) = generate_keypair(&secp);
+        let taproot_payload = TaprootPayload::build(
+            &secp,
+            keypair,
+            x_public_key,
+            &bitcoin::ScriptBuf::new(),
+            5_000,
+            Network::Testnet,
+        )
+        .unwrap();
+        let inputs = [AccountUtxo {
+            input: TxInput {
+                id: Txid::from_str(
+                    "14a7109b642b4fca7f10cd9bee89db73770c5a2d107f6a51c6bd7625dcdc2aed",
+                )
+                .unwrap(),
+                index: 0,
+                amount: Amount::from_sat(8_000),
+            },
+            address_index: 0,
+        }];
+        let mut psbt = sender
+            .watch_only(&secp)
+            .unwrap()
+            .commit_psbt(&secp, &inputs, &taproot_payload, Amount::from_sat(1_000))
+            .unwrap();
+
+        let report = verify_signatures(&secp, &psbt).unwrap();
+        assert_eq!(report.inputs[0].state, InputState::MissingSignatures);
+        assert!(report.ensure_complete().is_err());
+
+        psbt.sign(&sender.private_key, &secp).unwrap();
+        let report = verify_signatures(&secp, &psbt).unwrap();
+        assert_eq!(report.inputs[0].state, InputState::Complete);
+        assert!(report.is_complete());
+
+        // a signature combined in under the wrong key
+        let signature = *psbt.inputs[0].partial_sigs.values().next().unwrap();
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(recipient.public_key, signature);
+        let report = verify_signatures(&secp, &psbt).unwrap();
+        assert_eq!(report.inputs[0].state, InputState::InvalidSignatures);
+        assert_eq!(report.inputs[0].valid, 1);
+        assert_eq!(
+            report.inputs[0].invalid[0].public_key,
+            recipient.public_key.to_string()
+        );
+
+        // a valid signature over the right sighash, from a key the input does not pay to
+        let prevout = psbt.inputs[0].witness_utxo.clone().unwrap();
+        let sighash = SighashCache::new(&psbt.unsigned_tx)
+            .p2wpkh_signature_hash(
+                0,
+                &prevout.script_pubkey,
+                prevout.value,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        let msg = Message::from_digest(sighash.to_byte_array());
+        let foreign_key = PrivateKey::new(keypair.secret_key(), Network::Testnet);
+        psbt.inputs[0].partial_sigs = BTreeMap::from([(
+            foreign_key.public_key(&secp),
+            ecdsa::Signature::sighash_all(secp.sign_ecdsa(&msg, &foreign_key.inner)),
+        )]);
+        let report = verify_signatures(&secp, &psbt).unwrap();
+        assert_eq!(report.inputs[0].state, InputState::InvalidSignatures);
+        assert_eq!(report.inputs[0].valid, 0);
+
+        // a key path signature on an input whose prevout is not taproot
+        psbt.inputs[0].partial_sigs.clear();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: prevout.value,
+            script_pubkey: bitcoin::ScriptBuf::new(),
+        });
+        psbt.inputs[0].tap_key_sig = Some(taproot::Signature {
+            sig: secp.sign_schnorr_no_aux_rand(&msg, &keypair),
+            hash_ty: TapSighashType::Default,
+        });
+        let report = verify_signatures(&secp, &psbt).unwrap();
+        assert_eq!(report.inputs[0].state, InputState::InvalidSignatures);
+    }
+
+    #[test]
+    fn test_should_count_tap_script_signatures_per_leaf() {
+        use bitcoin::absolute::LockTime;
+        use bitcoin::opcodes::all::{OP_CHECKSIG, OP_CHECKSIGADD, OP_NUMEQUAL};
+        use bitcoin::psbt::PsbtSighashType;
+        use bitcoin::taproot::{LeafVersion, TaprootBuilder};
+        use bitcoin::transaction::Version;
+        use bitcoin::{OutPoint, Sequence, Transaction, TxIn, Witness};
+
+        let secp = Secp256k1::new();
+        let keys = [
+            generate_keypair(&secp),
+            generate_keypair(&secp),
+            generate_keypair(&secp),
+        ];
+        let two_of_two = |first: XOnlyPublicKey, second: XOnlyPublicKey| {
+            bitcoin::script::Builder::new()
+                .push_x_only_key(&first)
+                .push_opcode(OP_CHECKSIG)
+                .push_x_only_key(&second)
+                .push_opcode(OP_CHECKSIGADD)
+                .push_int(2)
+                .push_opcode(OP_NUMEQUAL)
+                .into_script()
+        };
+        let leaf_a = two_of_two(keys[0].1, keys[1].1);
+        let leaf_b = two_of_two(keys[0].1, keys[2].1);
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(1, leaf_a.clone())
+            .unwrap()
+            .add_leaf(1, leaf_b.clone())
+            .unwrap()
+            .finalize(&secp, keys[0].1)
+            .unwrap();
+        let prevout = TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(spend_info.output_key()),
+        };
+
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(9_000),
+                script_pubkey: prevout.script_pubkey.clone(),
+            }],
+        })
+        .unwrap();
+        psbt.inputs[0].witness_utxo = Some(prevout.clone());
+        for leaf in [&leaf_a, &leaf_b] {
+            let version = (leaf.clone(), LeafVersion::TapScript);
+            let control_block = spend_info.control_block(&version).unwrap();
+            psbt.inputs[0].tap_scripts.insert(control_block, version);
+        }
+
+        let sign = |signer: usize, leaf_hash: TapLeafHash, hash_ty: TapSighashType| {
+            let sighash = SighashCache::new(&psbt.unsigned_tx)
+                .taproot_script_spend_signature_hash(
+                    0,
+                    &Prevouts::All(std::slice::from_ref(&prevout)),
+                    leaf_hash,
+                    hash_ty,
+                )
+                .unwrap();
+            let msg = Message::from_digest(sighash.to_byte_array());
+            taproot::Signature {
+                sig: secp.sign_schnorr_no_aux_rand(&msg, &keys[signer].0),
+                hash_ty,
+            }
+        };
+        let hash_a = TapLeafHash::from_script(&leaf_a, LeafVersion::TapScript);
+        let hash_b = TapLeafHash::from_script(&leaf_b, LeafVersion::TapScript);
+        let first_on_a = sign(0, hash_a, TapSighashType::Default);
+        let third_on_b = sign(2, hash_b, TapSighashType::Default);
+        let second_on_a = sign(1, hash_a, TapSighashType::Default);
+
+        // one signature on each leaf satisfies neither of them
+        psbt.inputs[0]
+            .tap_script_sigs
+            .insert((keys[0].1, hash_a), first_on_a);
+        psbt.inputs[0]
+            .tap_script_sigs
+            .insert((keys[2].1, hash_b), third_on_b);
+        let report = verify_signatures(&secp, &psbt).unwrap();
+        assert_eq!(report.inputs[0].state, InputState::MissingSignatures);
+        assert_eq!((report.inputs[0].valid, report.inputs[0].required), (1, 2));
+
+        psbt.inputs[0]
+            .tap_script_sigs
+            .insert((keys[1].1, hash_a), second_on_a);
+        let report = verify_signatures(&secp, &psbt).unwrap();
+        assert_eq!(report.inputs[0].state, InputState::Complete);
+        assert_eq!((report.inputs[0].valid, report.inputs[0].required), (2, 2));
+
+        // a hash type other than the one the input asks for
+        psbt.inputs[0].sighash_type = Some(PsbtSighashType::from(TapSighashType::All));
+        let report = verify_signatures(&secp, &psbt).unwrap();
+        assert_eq!(report.inputs[0].state, InputState::InvalidSignatures);
+        psbt.inputs[0].sighash_type = None;
+
+        // a valid signature from a key the leaf does not push
+        let third_on_a = sign(2, hash_a, TapSighashType::Default);
+        psbt.inputs[0]
+            .tap_script_sigs
+            .insert((keys[2].1, hash_a), third_on_a);
+        let report = verify_signatures(&secp, &psbt).unwrap();
+        assert_eq!(report.inputs[0].state, InputState::InvalidSignatures);
+        psbt.inputs[0].tap_script_sigs.remove(&(keys[2].1, hash_a));
+
+        // a signature for a leaf the input does not reveal
+        let unknown_leaf = TapLeafHash::from_script(&ScriptBuf::new(), LeafVersion::TapScript);
+        let third_on_unknown = sign(2, unknown_leaf, TapSighashType::Default);
+        psbt.inputs[0]
+            .tap_script_sigs
+            .insert((keys[2].1, unknown_leaf), third_on_unknown);
+        let report = verify_signatures(&secp, &psbt).unwrap();
+        assert_eq!(report.inputs[0].state, InputState::InvalidSignatures);
+        assert_eq!(report.inputs[0].valid, 2);
+    }
+}